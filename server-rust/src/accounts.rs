@@ -0,0 +1,101 @@
+// Player accounts, kept in the logins table. Registering a banner reserves it: nobody else can connect under it without logging in first. Unregistered banners are free for guests.
+use sha2::Sha256;
+
+const HASH_ROUNDS : u32 = 100000; // pbkdf2 rounds. slow on purpose, so run it with spawn_blocking
+pub const ATTEMPTS : u32 = 5; // logins and registrations allowed per connection
+pub const SCHEMA   : &str = "CREATE TABLE IF NOT EXISTS logins (banner TEXT, password TEXT, highscore INTEGER, wins INTEGER, losses INTEGER);DELETE FROM logins WHERE rowid NOT IN (SELECT MIN(rowid) FROM logins GROUP BY banner);CREATE UNIQUE INDEX IF NOT EXISTS logins_banner ON logins (banner);"; // the DELETE is for databases from before banners were unique: the first to register a banner keeps it
+
+
+#[derive(Debug, PartialEq)]
+pub enum LoginResult {
+    Ok,
+    BadPassword,
+    NoAccount
+}
+
+
+fn to_hex(bytes : &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+pub fn new_salt() -> String {
+    to_hex(&rand::random::<[u8; 16]>())
+}
+
+
+pub fn hash_password(password : &str, salt : &str) -> String {
+    let mut hash = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), HASH_ROUNDS, &mut hash);
+    to_hex(&hash)
+}
+
+
+fn password_of(connection : &sqlite::Connection, banner : &str) -> sqlite::Result<Option<String>> { // salt$hash, or None if nobody's registered this banner
+    let mut statement = connection.prepare("SELECT password FROM logins WHERE banner = ?")?;
+    statement.bind((1, banner))?;
+    match statement.next()? {
+        sqlite::State::Row => Ok(Some(statement.read::<String, _>(0)?)),
+        sqlite::State::Done => Ok(None)
+    }
+}
+
+
+fn stored_password(sql : &str, banner : &str) -> Result<Option<String>, String> {
+    let connection = sqlite::open(sql).map_err(|error| error.to_string())?;
+    password_of(&connection, banner).map_err(|error| error.to_string())
+}
+
+
+pub fn registered(connection : &sqlite::Connection, banner : &str) -> sqlite::Result<bool> { // is_registered, for code that already has a connection (like the database thread)
+    Ok(password_of(connection, banner)?.is_some())
+}
+
+
+pub fn is_registered(sql : &str, banner : &str) -> bool {
+    match stored_password(sql, banner) {
+        Ok(stored) => stored.is_some(),
+        Err(error) => {
+            println!("Couldn't check if {} is registered: {}", banner, error);
+            true // if the database is broken, we can't tell who owns what, so nobody gets in as a guest
+        }
+    }
+}
+
+
+pub fn register(sql : &str, banner : &str, password : &str) -> Result<(), String> {
+    if banner.len() == 0 || password.len() == 0 {
+        return Err("You need a banner and a password to register.".to_string());
+    }
+    let salt = new_salt();
+    let hash = hash_password(password, &salt);
+    let connection = sqlite::open(sql).map_err(|error| error.to_string())?;
+    let result = connection.prepare("INSERT INTO logins VALUES (?, ?, 0, 0, 0)").and_then(|mut statement| {
+        statement.bind((1, banner))?;
+        statement.bind((2, format!("{}${}", salt, hash).as_str()))?;
+        statement.next()?;
+        Ok(())
+    });
+    match result {
+        Ok(()) => Ok(()),
+        Err(error) if error.code == Some(19) => Err(format!("Somebody already registered '{}'.", banner)), // SQLITE_CONSTRAINT: the unique index on banner
+        Err(error) => Err(error.to_string())
+    }
+}
+
+
+pub fn login(sql : &str, banner : &str, password : &str) -> LoginResult {
+    let stored = match stored_password(sql, banner) { Ok(Some(stored)) => stored, _ => return LoginResult::NoAccount };
+    let (salt, hash) = match stored.split_once('$') { Some(parts) => parts, None => return LoginResult::BadPassword }; // not something we wrote
+    if hash_password(password, salt) == hash {
+        LoginResult::Ok
+    }
+    else {
+        LoginResult::BadPassword
+    }
+}
+
+
+pub fn may_use(sql : &str, account : Option<&str>, banner : &str) -> bool { // guests can have any banner nobody's registered; registered ones need you logged in as them
+    account == Some(banner) || !is_registered(sql, banner)
+}
\ No newline at end of file
@@ -0,0 +1,66 @@
+// Scorekeeping for capture-point games: sides earn a point for every second they hold a point, and the first to the threshold (or whoever's ahead when time runs out) wins
+use std::collections::HashMap;
+use crate::stats::Outcome;
+
+
+pub struct CaptureMode {
+    win_score  : u32, // 0 means there's no threshold, and only the time limit can end it
+    time_limit : u32, // in ticks; 0 means no time limit
+    per_point  : u32, // ticks of holding a point it takes to earn one score
+    elapsed    : u32,
+    held       : HashMap<usize, u32> // side -> ticks spent holding points, summed over every point they own
+}
+
+
+impl CaptureMode {
+    pub fn new(win_score : u32, time_limit : u32, per_point : u32) -> Self {
+        Self {
+            win_score,
+            time_limit,
+            per_point,
+            elapsed : 0,
+            held : HashMap::new()
+        }
+    }
+
+    pub fn hold(&mut self, side : usize) -> bool { // a point owned by this side was held for a tick. returns true if that earned them a new point of score.
+        let held = self.held.entry(side).or_insert(0);
+        *held += 1;
+        *held % self.per_point == 0
+    }
+
+    pub fn score(&self, side : usize) -> u32 {
+        self.held.get(&side).copied().unwrap_or(0) / self.per_point
+    }
+
+    pub fn remaining(&self) -> Option<u32> { // ticks left before time runs out, if there's a limit
+        if self.time_limit == 0 {
+            None
+        }
+        else {
+            Some(self.time_limit.saturating_sub(self.elapsed))
+        }
+    }
+
+    fn leaders(&self) -> Vec<usize> { // every side tied for the highest score
+        let best = self.held.keys().map(|side| self.score(*side)).max().unwrap_or(0);
+        if best == 0 {
+            return vec![];
+        }
+        let mut ret : Vec<usize> = self.held.keys().copied().filter(|side| self.score(*side) == best).collect();
+        ret.sort();
+        ret
+    }
+
+    pub fn tick(&mut self) -> Option<Outcome> { // call once per tick of play, after the points have updated
+        self.elapsed += 1;
+        let leaders = self.leaders();
+        if self.win_score > 0 && leaders.len() > 0 && self.score(leaders[0]) >= self.win_score {
+            return Some(if leaders.len() == 1 { Outcome::Winner (leaders[0]) } else { Outcome::Tie });
+        }
+        if self.time_limit > 0 && self.elapsed >= self.time_limit {
+            return Some(if leaders.len() == 1 { Outcome::Winner (leaders[0]) } else { Outcome::Tie }); // nobody scoring at all is a tie too
+        }
+        None
+    }
+}
@@ -1,6 +1,13 @@
 use serde::{Deserialize, Serialize};
 use crate::Server;
 use crate::input;
+use crate::gamepiece::nexus::{SpawnTable, Spawn};
+use crate::ext::ExtRegistry;
+use crate::capture::CaptureMode;
+use crate::ctf::CtfMode;
+use crate::matchclock::MatchClock;
+use crate::overtime::{OvertimeRules, SuddenDeath};
+use crate::matchmaking::MatchQueue;
 
 #[derive(Serialize, Deserialize)]
 struct ObjectDef {
@@ -35,11 +42,146 @@ pub struct Times {
 
 
 #[derive(Serialize, Deserialize)]
-pub struct ExtObjectDef {
-    t : String,
-    x : f32,
-    y : f32,
-    effect_radius : Option<f32> // for any ext type with an effect radius
+pub struct CaptureDef { // turns on the capture-point game; the points themselves go in ext
+    win_score  : Option<u32>, // score to win outright. missing or 0 means only the time limit ends it
+    time_limit : Option<f32>, // seconds of play
+    per_point  : Option<f32> // seconds a point has to be held for one score
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct CtfDef { // turns on capture the flag; each team's flag goes in ext
+    win_captures : Option<u32> // flags brought home to win. 0 means play forever
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct SuddenDeathDef { // what happens when a timed match is tied
+    kind        : String, // "shrink" or "npcs"
+    shrink_secs : Option<f32>, // how long the safe zone takes to close
+    damage      : Option<f32>, // per tick, outside the safe zone
+    npc_secs    : Option<f32> // until the first npc wave; the rest come quicker
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct LeaderboardDef {
+    top         : Option<usize>, // places sent to clients
+    season_days : Option<u32>,
+    live_secs   : Option<f32> // how often the live board goes out during play
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct MatchmakingDef { // only read from the default room's config. the lobby queue batches players into new rooms made from room_config.
+    room_config   : String, // should be passwordless, since matched players connect without one
+    min_players   : usize,
+    max_players   : usize,
+    wait_secs     : Option<u32>, // how long before the oldest player in line settles for a smaller or less even game
+    rating_spread : Option<f32>, // elo difference allowed within a room; missing means anyone plays anyone
+    start_secs    : Option<f32> // countdown once the room has everyone
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct SpawnDef {
+    unit        : char,
+    weight      : Option<f32>,
+    spam        : Option<f32>, // relative odds of each strategy; if none are given it always spams
+    pull_up     : Option<f32>,
+    pull_around : Option<f32>,
+    distance    : Option<(f32, f32)>
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct NexusDef { // everything here is optional; whatever's missing comes from SpawnTable::classic()
+    min_interval   : Option<u16>,
+    max_interval   : Option<u16>,
+    spawns         : Option<Vec<SpawnDef>>,
+    castle_chance  : Option<f32>,
+    wave_size      : Option<u16>,
+    max_wave       : Option<u16>,
+    escalate_every : Option<u32>,
+    wave_growth    : Option<u16>,
+    interval_scale : Option<f32>,
+    max_alive      : Option<usize>
+}
+
+
+impl NexusDef {
+    pub fn table(&self) -> Result<SpawnTable, String> {
+        let mut table = SpawnTable::classic();
+        if let Some(min_interval) = self.min_interval {
+            table.min_interval = min_interval;
+        }
+        if let Some(max_interval) = self.max_interval {
+            table.max_interval = max_interval;
+        }
+        if table.min_interval == 0 || table.max_interval < table.min_interval {
+            return Err("Bad nexus spawn interval in the config file!".to_string());
+        }
+        if let Some(spawns) = &self.spawns {
+            table.spawns.clear();
+            for spawn in spawns {
+                if crate::make_piece(spawn.unit).is_none() {
+                    return Err(format!("Nexuses can't spawn '{}'!", spawn.unit));
+                }
+                let no_strategy = spawn.spam.is_none() && spawn.pull_up.is_none() && spawn.pull_around.is_none();
+                table.spawns.push(Spawn {
+                    unit        : spawn.unit,
+                    weight      : spawn.weight.unwrap_or(1.0),
+                    spam        : spawn.spam.unwrap_or(if no_strategy { 1.0 } else { 0.0 }),
+                    pull_up     : spawn.pull_up.unwrap_or(0.0),
+                    pull_around : spawn.pull_around.unwrap_or(0.0),
+                    distance    : spawn.distance.unwrap_or((200.0, 600.0))
+                });
+            }
+        }
+        if let Some(castle_chance) = self.castle_chance {
+            table.castle_chance = castle_chance;
+        }
+        if let Some(wave_size) = self.wave_size {
+            table.wave_size = wave_size;
+        }
+        if let Some(max_wave) = self.max_wave {
+            table.max_wave = max_wave;
+        }
+        if let Some(escalate_every) = self.escalate_every {
+            table.escalate_every = escalate_every;
+            table.wave_growth = 1; // escalating without saying how much means one more unit per wave
+        }
+        if let Some(wave_growth) = self.wave_growth {
+            table.wave_growth = wave_growth;
+        }
+        if let Some(interval_scale) = self.interval_scale {
+            table.interval_scale = interval_scale;
+        }
+        if let Some(max_alive) = self.max_alive {
+            table.max_alive = max_alive;
+        }
+        Ok(table)
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct ExtObjectDef { // which of these matter depends on t; see ext.rs
+    pub t             : String,
+    pub x             : f32,
+    pub y             : f32,
+    pub effect_radius : Option<f32>, // for any ext type with an effect radius
+    pub nexus         : Option<NexusDef>, // spawn tuning for nexuses
+    pub unit          : Option<char>, // what spawners and loot spawners make
+    pub interval      : Option<u32>, // ticks between spawns, or between capture point payouts
+    pub count         : Option<u16>, // how many spawn at a time
+    pub max_alive     : Option<usize>, // spawners stop while this many of their units are still alive; 0 or missing means no cap
+    pub rate          : Option<f32>, // health per tick for heal zones, damage per tick for hazards
+    pub to_x          : Option<f32>, // where the other teleporter pad goes
+    pub to_y          : Option<f32>,
+    pub income        : Option<i32>, // paid to a capture point's owner every interval
+    pub capture_ticks : Option<u16>, // how long it takes to capture a capture point
+    pub team          : Option<String> // the team (by name) a flag belongs to
 }
 
 
@@ -61,7 +203,14 @@ struct ServerConfigFile {
     map_anchor      : Option<String>,
     zones           : Option<usize>,
     ext             : Option<Vec<ExtObjectDef>>,
-    times           : Option<Times>
+    times           : Option<Times>,
+    capture         : Option<CaptureDef>,
+    ctf             : Option<CtfDef>,
+    match_secs      : Option<f32>, // if set, whoever has the most points when this runs out wins
+    postgame_secs   : Option<f32>, // how long the summary stays up before the next game
+    sudden_death    : Option<SuddenDeathDef>,
+    matchmaking     : Option<MatchmakingDef>,
+    leaderboard     : Option<LeaderboardDef>
 }
 
 pub struct Config {
@@ -69,17 +218,22 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn new(file : &str) -> Self {
+    pub fn new(file : &str) -> Result<Self, String> {
         use std::fs;
         println!("Loading configuration from {}", file);
-        let json_reader = fs::File::open(file).expect("Error reading config file");
-        let json : ServerConfigFile = serde_json::from_reader(json_reader).expect("Error parsing JSON!");
-        Self {
+        let json_reader = fs::File::open(file).map_err(|error| format!("Error reading config file: {}", error))?;
+        let json : ServerConfigFile = serde_json::from_reader(json_reader).map_err(|error| format!("Error parsing JSON: {}", error))?;
+        Ok(Self {
             json
-        }
+        })
+    }
+
+    pub fn matchmaking(&self) -> Option<MatchQueue> {
+        let def = match &self.json.matchmaking { Some(def) => def, None => return None };
+        Some(MatchQueue::new(def.room_config.clone(), def.min_players, def.max_players, def.wait_secs.unwrap_or(60), def.rating_spread, (def.start_secs.unwrap_or(10.0) * crate::FPS) as u32))
     }
 
-    pub fn load_into(&self, server : &mut Server) {
+    pub fn load_into(&self, server : &mut Server) -> Result<(), String> { // anything wrong with the config comes back as an Err, and the server's half-loaded; throw it away
         server.gamesize = self.json.world_size;
         if self.json.world_size > 50000.0 {
             //server.vvlm = true;
@@ -107,8 +261,10 @@ impl Config {
         }
         if self.json.teams.is_some() {
             server.passwordless = false;
-            for team in self.json.teams.as_ref().unwrap() {
-                server.new_team(team.name.clone(), team.password.clone());
+            if server.teams.len() == 0 { // a restart keeps the teams (and their banners) it already has
+                for team in self.json.teams.as_ref().unwrap() {
+                    server.new_team(team.name.clone(), team.password.clone());
+                }
             }
         }
         match &self.json.times {
@@ -135,25 +291,53 @@ impl Config {
             }
             server.place_block(x, y, a, def.w, def.h);
         }
+        server.build_navgrid();
+        if let Some(capture) = &self.json.capture {
+            let win_score = capture.win_score.unwrap_or(0);
+            let time_limit = (capture.time_limit.unwrap_or(0.0) * crate::FPS) as u32;
+            if win_score == 0 && time_limit == 0 {
+                return Err("Capture games need a win_score or a time_limit!".to_string());
+            }
+            let per_point = (capture.per_point.unwrap_or(1.0) * crate::FPS) as u32;
+            if per_point == 0 {
+                return Err("Bad capture per_point in the config file!".to_string());
+            }
+            server.capture = Some(CaptureMode::new(win_score, time_limit, per_point));
+        }
+        if let Some(secs) = self.json.postgame_secs {
+            server.postgame = secs;
+        }
+        if let Some(leaderboard) = &self.json.leaderboard {
+            server.leaderboards.top = leaderboard.top.unwrap_or(10);
+            server.leaderboards.season_days = leaderboard.season_days.unwrap_or(30);
+            server.leaderboards.live_interval = (leaderboard.live_secs.unwrap_or(5.0) * crate::FPS) as u32;
+        }
+        if let Some(secs) = self.json.match_secs {
+            if secs <= 0.0 {
+                return Err("Bad match_secs in the config file!".to_string());
+            }
+            server.clock = Some(MatchClock::new((secs * crate::FPS) as u32));
+        }
+        if let Some(sudden_death) = &self.json.sudden_death {
+            if self.json.match_secs.is_none() {
+                return Err("Sudden death only happens in timed matches; set match_secs!".to_string());
+            }
+            let kind = match SuddenDeath::from_name(&sudden_death.kind) { Some(kind) => kind, None => return Err("Bad sudden death kind in the config file!".to_string()) };
+            server.sudden_death = Some(OvertimeRules {
+                kind,
+                shrink_ticks : (sudden_death.shrink_secs.unwrap_or(120.0) * crate::FPS) as u32,
+                damage       : sudden_death.damage.unwrap_or(0.01),
+                interval     : (sudden_death.npc_secs.unwrap_or(10.0) * crate::FPS) as u32
+            });
+        }
+        if let Some(ctf) = &self.json.ctf {
+            server.ctf = Some(CtfMode::new(ctf.win_captures.unwrap_or(3)));
+        }
         match &self.json.ext {
             Some(ext) => {
+                let registry = ExtRegistry::builtin();
                 for def in ext {
-                    match def.t.as_str() {
-                        "nexus" => {
-                            server.place_nexus(def.x, def.y, def.effect_radius.unwrap());
-                            server.place_wall(def.x - 60.0, def.y - 60.0, None);
-                            server.place_wall(def.x       , def.y - 60.0, None);
-                            server.place_wall(def.x + 60.0, def.y - 60.0, None);
-                            server.place_wall(def.x - 60.0, def.y, None);
-                            server.place_wall(def.x + 60.0, def.y, None);
-                            server.place_wall(def.x - 60.0, def.y + 60.0, None);
-                            server.place_wall(def.x       , def.y + 60.0, None);
-                            server.place_wall(def.x + 60.0, def.y + 60.0, None);
-                        },
-                        &_ => {
-                            panic!("Bad type in the config file!");
-                        }
-                    }
+                    registry.load(def, server)?;
                 }
             },
             _ => {}
@@ -198,5 +382,6 @@ impl Config {
             }
             _ => {}
         }
+        Ok(())
     }
 }
\ No newline at end of file
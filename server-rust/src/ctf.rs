@@ -0,0 +1,67 @@
+// Bookkeeping for capture-the-flag games. The flags are ordinary pieces; this keeps track of whose they are, where home is, and who's scored.
+use crate::vector::Vector2;
+use crate::stats::Outcome;
+use std::collections::HashMap;
+
+pub const CAPTURE_RADIUS : f32 = 150.0; // how close to your own flag's base you have to bring theirs
+pub const RETURN_TICKS : u32 = 900; // a dropped flag goes home by itself after this long
+
+
+pub struct FlagState {
+    pub id      : u32,
+    pub side    : usize, // the team banner it belongs to
+    pub base    : Vector2,
+    pub taken   : bool, // it's been picked up since it was last home
+    pub dropped : u32 // ticks it's been lying around away from home
+}
+
+
+pub struct CtfMode {
+    win_captures : u32,
+    pub flags    : Vec<FlagState>,
+    captures     : HashMap<usize, u32> // side -> flags they've brought home
+}
+
+
+impl CtfMode {
+    pub fn new(win_captures : u32) -> Self {
+        Self {
+            win_captures,
+            flags : vec![],
+            captures : HashMap::new()
+        }
+    }
+
+    pub fn add_flag(&mut self, id : u32, side : usize, base : Vector2) {
+        self.flags.push(FlagState {
+            id,
+            side,
+            base,
+            taken : false,
+            dropped : 0
+        });
+    }
+
+    pub fn flag_of(&self, side : usize) -> Option<&FlagState> {
+        self.flags.iter().find(|flag| flag.side == side)
+    }
+
+    pub fn side_of_flag(&self, id : u32) -> Option<usize> {
+        self.flags.iter().find(|flag| flag.id == id).map(|flag| flag.side)
+    }
+
+    pub fn captures(&self, side : usize) -> u32 {
+        self.captures.get(&side).copied().unwrap_or(0)
+    }
+
+    pub fn capture(&mut self, side : usize) -> Option<Outcome> { // side brought an enemy flag home. returns the outcome if that won it.
+        let captures = self.captures.entry(side).or_insert(0);
+        *captures += 1;
+        if self.win_captures > 0 && *captures >= self.win_captures {
+            Some(Outcome::Winner (side))
+        }
+        else {
+            None
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// The database thread. Every room shares one database, so everything that touches it goes through one connection on one thread: jobs run in the order they were sent, writes each get a transaction of their own, and the tick loops never wait on sqlite.
+use std::sync::mpsc::{Sender, Receiver, channel};
+
+pub type Write = Box<dyn FnOnce(&sqlite::Connection) -> sqlite::Result<()> + Send>;
+type Job = Box<dyn FnOnce(&sqlite::Connection) + Send>;
+
+const SCHEMA : &str = "CREATE TABLE IF NOT EXISTS teams_records (teamname TEXT, wins INTEGER, losses INTEGER);CREATE TABLE IF NOT EXISTS match_results (finished INTEGER, banner TEXT, won INTEGER, kills INTEGER, losses INTEGER, score INTEGER, built INTEGER, damage REAL);CREATE TABLE IF NOT EXISTS leaderboard (finished INTEGER, map TEXT, season INTEGER, banner TEXT, score INTEGER);CREATE INDEX IF NOT EXISTS leaderboard_map ON leaderboard (map, season, banner);CREATE INDEX IF NOT EXISTS leaderboard_season ON leaderboard (season, banner);CREATE TABLE IF NOT EXISTS ratings (name TEXT, team INTEGER, rating REAL, games INTEGER);CREATE TABLE IF NOT EXISTS matches (id INTEGER PRIMARY KEY, map TEXT, started INTEGER, ended INTEGER, winner TEXT);CREATE TABLE IF NOT EXISTS match_players (match_id INTEGER, banner TEXT, team TEXT, mode TEXT, score INTEGER);CREATE TABLE IF NOT EXISTS kill_events (match_id INTEGER, time REAL, killer TEXT, victim TEXT, killer_type TEXT, victim_type TEXT, x REAL, y REAL, castle INTEGER);";
+
+
+#[derive(Clone)]
+pub struct Database {
+    pub path : String, // for the lobby, which opens its own connections for slow account work so it doesn't hold everyone else up
+    jobs     : Sender<Job>
+}
+
+
+impl Database {
+    pub fn open(path : &str) -> Result<Self, String> { // makes sure the tables are there, then starts the thread
+        let connection = sqlite::open(path).map_err(|error| format!("couldn't open the database '{}': {}", path, error))?;
+        connection.execute(SCHEMA).map_err(|error| format!("couldn't set up the database '{}': {}", path, error))?;
+        connection.execute(crate::accounts::SCHEMA).map_err(|error| format!("couldn't set up the accounts in '{}': {}", path, error))?;
+        let (jobs, receiver) : (Sender<Job>, Receiver<Job>) = channel();
+        std::thread::spawn(move || {
+            for job in receiver { // ends once every handle's gone
+                job(&connection);
+            }
+        });
+        Ok(Self {
+            path : path.to_string(),
+            jobs
+        })
+    }
+
+    pub fn detached() -> Self { // goes nowhere: for servers that aren't in a room yet, like the tests'
+        let (jobs, _) = channel();
+        Self {
+            path : String::new(),
+            jobs
+        }
+    }
+
+    pub fn write(&self, what : &'static str, writes : Vec<Write>) { // all of them or none of them. what goes in the error message, as in "couldn't {what}"
+        let _ = self.jobs.send(Box::new(move |connection| {
+            if let Err(error) = transaction(connection, writes) {
+                println!("Couldn't {}: {}", what, error);
+            }
+        }));
+    }
+
+    pub fn read<T : Send + 'static>(&self, job : impl FnOnce(&sqlite::Connection) -> T + Send + 'static) -> Receiver<T> { // the answer shows up once everything sent before it is done. if the thread's gone, the receiver just disconnects.
+        let (tx, rx) = channel();
+        let _ = self.jobs.send(Box::new(move |connection| {
+            let _ = tx.send(job(connection));
+        }));
+        rx
+    }
+
+    pub async fn ask<T : Send + 'static>(&self, job : impl FnOnce(&sqlite::Connection) -> T + Send + 'static) -> Option<T> { // read, for async code. None if there's no database thread.
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let _ = self.jobs.send(Box::new(move |connection| {
+            let _ = tx.send(job(connection));
+        }));
+        rx.await.ok()
+    }
+}
+
+
+fn transaction(connection : &sqlite::Connection, writes : Vec<Write>) -> sqlite::Result<()> {
+    connection.execute("BEGIN")?;
+    for write in writes {
+        if let Err(error) = write(connection) {
+            let _ = connection.execute("ROLLBACK");
+            return Err(error);
+        }
+    }
+    connection.execute("COMMIT")
+}
\ No newline at end of file
@@ -0,0 +1,145 @@
+// Registry of the object types that can go in a config file's "ext" list. Each type gets a loader that reads its ExtObjectDef and puts whatever it needs into the world.
+use crate::Server;
+use crate::config::ExtObjectDef;
+use crate::gamepiece::nexus::SpawnTable;
+use crate::gamepiece::zones::{Spawner, HealZone, Teleporter, CapturePoint, HazardField, Flag};
+use crate::gamepiece::GamePieceBase;
+use crate::vector::Vector2;
+use std::collections::HashMap;
+
+pub type ExtLoader = fn(&ExtObjectDef, &mut Server) -> Result<(), String>;
+
+
+pub struct ExtRegistry {
+    kinds : HashMap<&'static str, ExtLoader>
+}
+
+
+impl ExtRegistry {
+    pub fn new() -> Self {
+        Self {
+            kinds : HashMap::new()
+        }
+    }
+
+    pub fn builtin() -> Self { // every type the server ships with
+        let mut ret = Self::new();
+        ret.register("nexus", load_nexus);
+        ret.register("spawner", load_spawner);
+        ret.register("loot", load_loot);
+        ret.register("heal_zone", load_heal_zone);
+        ret.register("teleporter", load_teleporter);
+        ret.register("capture_point", load_capture_point);
+        ret.register("hazard", load_hazard);
+        ret.register("flag", load_flag);
+        ret
+    }
+
+    pub fn register(&mut self, name : &'static str, loader : ExtLoader) {
+        self.kinds.insert(name, loader);
+    }
+
+    pub fn load(&self, def : &ExtObjectDef, server : &mut Server) -> Result<(), String> {
+        match self.kinds.get(def.t.as_str()) {
+            Some(loader) => loader(def, server),
+            None => Err(format!("Bad type in the config file: '{}'!", def.t))
+        }
+    }
+}
+
+
+fn radius(def : &ExtObjectDef) -> Result<f32, String> {
+    match def.effect_radius {
+        Some(radius) if radius > 0.0 => Ok(radius),
+        _ => Err(format!("{} at {}, {} needs a positive effect_radius!", def.t, def.x, def.y))
+    }
+}
+
+fn load_nexus(def : &ExtObjectDef, server : &mut Server) -> Result<(), String> {
+    let table = match &def.nexus {
+        Some(nexus) => nexus.table()?,
+        None => SpawnTable::classic()
+    };
+    server.place_nexus(def.x, def.y, radius(def)?, table);
+    server.place_wall(def.x - 60.0, def.y - 60.0, None);
+    server.place_wall(def.x       , def.y - 60.0, None);
+    server.place_wall(def.x + 60.0, def.y - 60.0, None);
+    server.place_wall(def.x - 60.0, def.y, None);
+    server.place_wall(def.x + 60.0, def.y, None);
+    server.place_wall(def.x - 60.0, def.y + 60.0, None);
+    server.place_wall(def.x       , def.y + 60.0, None);
+    server.place_wall(def.x + 60.0, def.y + 60.0, None);
+    Ok(())
+}
+
+fn spawner(kind : char, unit : char, def : &ExtObjectDef, server : &mut Server) -> Result<(), String> {
+    if crate::make_piece(unit).is_none() {
+        return Err(format!("Spawners can't spawn '{}'!", unit));
+    }
+    let interval = def.interval.unwrap_or(600);
+    if interval == 0 {
+        return Err("Spawner intervals have to be at least one tick!".to_string());
+    }
+    server.place(Box::new(Spawner::new(kind, unit, interval, def.count.unwrap_or(1), def.max_alive.unwrap_or(0), def.effect_radius.unwrap_or(200.0))), def.x, def.y, 0.0, None);
+    Ok(())
+}
+
+fn load_spawner(def : &ExtObjectDef, server : &mut Server) -> Result<(), String> {
+    let unit = match def.unit { Some(unit) => unit, None => return Err("Spawners need a unit!".to_string()) };
+    spawner('P', unit, def, server)
+}
+
+fn load_loot(def : &ExtObjectDef, server : &mut Server) -> Result<(), String> {
+    spawner('L', def.unit.unwrap_or('C'), def, server) // chests unless told otherwise
+}
+
+fn load_heal_zone(def : &ExtObjectDef, server : &mut Server) -> Result<(), String> {
+    server.place(Box::new(HealZone::new(radius(def)?, def.rate.unwrap_or(0.05))), def.x, def.y, 0.0, None);
+    Ok(())
+}
+
+fn load_teleporter(def : &ExtObjectDef, server : &mut Server) -> Result<(), String> { // one entry places both pads
+    let radius = radius(def)?;
+    let partner = match (def.to_x, def.to_y) {
+        (Some(x), Some(y)) => Vector2::new(x, y),
+        _ => return Err("Teleporters need a to_x and a to_y!".to_string())
+    };
+    if (partner - Vector2::new(def.x, def.y)).magnitude() < radius * 2.0 + 100.0 {
+        return Err("Teleporter pads are too close together!".to_string()); // you'd pop out on top of the other pad and bounce back and forth forever
+    }
+    server.place(Box::new(Teleporter::new(radius, partner)), def.x, def.y, 0.0, None);
+    server.place(Box::new(Teleporter::new(radius, Vector2::new(def.x, def.y))), partner.x, partner.y, 0.0, None);
+    Ok(())
+}
+
+fn load_capture_point(def : &ExtObjectDef, server : &mut Server) -> Result<(), String> {
+    let capture_ticks = def.capture_ticks.unwrap_or(300);
+    if capture_ticks == 0 {
+        return Err("Capture points need capture_ticks of at least one!".to_string());
+    }
+    server.place(Box::new(CapturePoint::new(radius(def)?, capture_ticks, def.income.unwrap_or(0), def.interval.unwrap_or(300))), def.x, def.y, 0.0, None);
+    Ok(())
+}
+
+fn load_hazard(def : &ExtObjectDef, server : &mut Server) -> Result<(), String> {
+    server.place(Box::new(HazardField::new(radius(def)? * 2.0, def.rate.unwrap_or(0.1))), def.x, def.y, 0.0, None);
+    Ok(())
+}
+
+
+fn load_flag(def : &ExtObjectDef, server : &mut Server) -> Result<(), String> {
+    if server.ctf.is_none() {
+        return Err("Flags only work in ctf games; add a ctf section to the config file!".to_string());
+    }
+    let name = match &def.team { Some(name) => name, None => return Err("Flags need a team!".to_string()) };
+    let side = match server.teams.iter().rev().find(|team| server.banners.get(team.banner_id) == Some(name)) {
+        Some(team) => team.banner_id,
+        None => return Err(format!("There's no team called {} for this flag!", name))
+    };
+    if server.ctf.as_ref().unwrap().flag_of(side).is_some() {
+        return Err(format!("{} already has a flag!", name));
+    }
+    let id = server.add(GamePieceBase::new(Box::new(Flag::new()), def.x, def.y, 0.0), Some(side));
+    server.ctf.as_mut().unwrap().add_flag(id, side, Vector2::new(def.x, def.y));
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,84 @@
+// Formation slot math for group moves
+use crate::vector::Vector2;
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Formation {
+    Line,   // shoulder to shoulder, perpendicular to the facing direction
+    Wedge,  // a V with the point forwards
+    Box,    // a tight square grid
+    Spread  // the same grid, but loose, so one nuke doesn't get everybody
+}
+
+
+impl Formation {
+    pub fn from_byte(byte : u8) -> Option<Self> {
+        match byte {
+            b'l' => Some(Formation::Line),
+            b'w' => Some(Formation::Wedge),
+            b'b' => Some(Formation::Box),
+            b's' => Some(Formation::Spread),
+            _ => None
+        }
+    }
+
+    pub fn offsets(&self, count : usize, spacing : f32) -> Vec<Vector2> { // slot offsets relative to the destination, with +x pointing the way the group faces
+        let mut ret = Vec::with_capacity(count);
+        match self {
+            Formation::Line => {
+                for i in 0..count {
+                    ret.push(Vector2::new(0.0, (i as f32 - (count as f32 - 1.0) / 2.0) * spacing));
+                }
+            },
+            Formation::Wedge => {
+                for i in 0..count {
+                    let row = ((i + 1) / 2) as f32;
+                    let side = if i % 2 == 1 { -1.0 } else { 1.0 };
+                    ret.push(Vector2::new(-row * spacing, side * row * spacing));
+                }
+            },
+            Formation::Box | Formation::Spread => {
+                let spacing = if *self == Formation::Spread { spacing * 2.5 } else { spacing };
+                let cols = (count as f32).sqrt().ceil() as usize;
+                let rows = (count + cols - 1) / std::cmp::max(cols, 1);
+                for i in 0..count {
+                    let row = (i / cols) as f32;
+                    let col = (i % cols) as f32;
+                    ret.push(Vector2::new(-(row - (rows as f32 - 1.0) / 2.0) * spacing, (col - (cols as f32 - 1.0) / 2.0) * spacing));
+                }
+            }
+        }
+        ret
+    }
+
+    pub fn slots(&self, destination : Vector2, facing : f32, count : usize, spacing : f32) -> Vec<Vector2> { // world positions of every slot
+        let mut ret = vec![];
+        for offset in self.offsets(count, spacing) {
+            ret.push(destination + offset.rot(facing));
+        }
+        ret
+    }
+}
+
+
+pub fn assign_slots(positions : &Vec<Vector2>, slots : &Vec<Vector2>) -> Vec<usize> { // greedy: each slot, in order, takes the closest unit that doesn't have one yet. returns the slot index for each unit.
+    let mut ret = vec![usize::MAX; positions.len()];
+    for (slot_index, slot) in slots.iter().enumerate() {
+        let mut best : Option<usize> = None;
+        let mut best_distance = 0.0;
+        for (unit, position) in positions.iter().enumerate() {
+            if ret[unit] != usize::MAX {
+                continue;
+            }
+            let distance = (*position - *slot).magnitude();
+            if best.is_none() || distance < best_distance {
+                best = Some(unit);
+                best_distance = distance;
+            }
+        }
+        if let Some(unit) = best {
+            ret[unit] = slot_index;
+        }
+    }
+    ret
+}
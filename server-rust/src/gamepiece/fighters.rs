@@ -1,6 +1,7 @@
 // Grand unified file for fighter types
 
 use super::GamePiece;
+use super::Stance;
 use crate::Server;
 use crate::physics::PhysicsObject;
 use crate::vector::Vector2;
@@ -62,7 +63,7 @@ impl GamePiece for BasicFighter {
     }
 
     fn update(&mut self, properties : &mut ExposedProperties, _servah : &mut Server) {
-        let mut thrust = Vector2::new(properties.goal_x - properties.physics.cx(), properties.goal_y - properties.physics.cy());
+        let mut thrust = properties.nav_target() - properties.physics.vector_position();
         if thrust.magnitude() < 10.0 {
             properties.physics.set_angle(properties.goal_a);
         }
@@ -78,6 +79,14 @@ impl GamePiece for BasicFighter {
         true
     }
 
+    fn does_navigate(&self) -> bool {
+        true
+    }
+
+    fn default_stance(&self) -> Option<Stance> {
+        Some(Stance::FireAtWill)
+    }
+
     fn on_upgrade(&mut self, properties : &mut ExposedProperties, upgrade : &String) {
         match upgrade.as_str() {
             "s" => {
@@ -113,7 +122,7 @@ impl GamePiece for TieFighter {
     }
 
     fn update(&mut self, properties : &mut ExposedProperties, _servah : &mut Server) {
-        let mut thrust = Vector2::new(properties.goal_x - properties.physics.cx(), properties.goal_y - properties.physics.cy());
+        let mut thrust = properties.nav_target() - properties.physics.vector_position();
         if thrust.magnitude() < 10.0 {
             properties.physics.set_angle(properties.goal_a);
         }
@@ -128,6 +137,14 @@ impl GamePiece for TieFighter {
     fn is_editable(&self) -> bool {
         true
     }
+
+    fn does_navigate(&self) -> bool {
+        true
+    }
+
+    fn default_stance(&self) -> Option<Stance> {
+        Some(Stance::FireAtWill)
+    }
 }
 
 impl GamePiece for Sniper {
@@ -154,7 +171,7 @@ impl GamePiece for Sniper {
     }
 
     fn update(&mut self, properties : &mut ExposedProperties, _servah : &mut Server) {
-        let mut thrust = Vector2::new(properties.goal_x - properties.physics.cx(), properties.goal_y - properties.physics.cy());
+        let mut thrust = properties.nav_target() - properties.physics.vector_position();
         if thrust.magnitude() < 10.0 {
             properties.physics.set_angle(properties.goal_a);
         }
@@ -169,6 +186,14 @@ impl GamePiece for Sniper {
     fn is_editable(&self) -> bool {
         true
     }
+
+    fn does_navigate(&self) -> bool {
+        true
+    }
+
+    fn default_stance(&self) -> Option<Stance> {
+        Some(Stance::FireAtWill)
+    }
 }
 
 impl GamePiece for Missile {
@@ -189,7 +214,7 @@ impl GamePiece for Missile {
     }
 
     fn update(&mut self, properties : &mut ExposedProperties, _servah : &mut Server) {
-        let goal = Vector2::new(properties.goal_x - properties.physics.cx(), properties.goal_y - properties.physics.cy());
+        let goal = properties.nav_target() - properties.physics.vector_position();
         properties.physics.set_angle(properties.physics.angle() * 0.9 + goal.angle() * 0.1);
         let thrust = Vector2::new_from_manda(0.3, properties.physics.angle());
         properties.physics.velocity = properties.physics.velocity + thrust;
@@ -199,4 +224,8 @@ impl GamePiece for Missile {
     fn is_editable(&self) -> bool {
         true
     }
+
+    fn does_navigate(&self) -> bool {
+        true
+    }
 }
\ No newline at end of file
@@ -5,6 +5,9 @@ use crate::physics::PhysicsObject;
 use super::TargetingFilter;
 use super::TargetingMode;
 use super::ExplosionMode;
+use super::Stance;
+use super::LaunchState;
+use super::BULLET_SPEED;
 use crate::ServerToClient;
 use crate::vector::Vector2;
 use super::BulletType;
@@ -13,6 +16,11 @@ use crate::ExposedProperties;
 use crate::ReqZone;
 use std::f32::consts::PI;
 
+const MISSILE_BURN_DISTANCE : f32 = 500.0; // anti-RTF missiles thrust hard until they're this close, then ease off so they can turn
+const MISSILE_FAR_THRUST : f32 = 2.0;
+const MISSILE_NEAR_THRUST : f32 = 1.0;
+const MISSILE_FRICTION : f32 = 0.99; // only up close
+
 pub struct Bullet {}
 pub struct AntiRTFBullet {}
 pub struct Wall {}
@@ -73,6 +81,9 @@ impl Seed {
 }
 
 impl Carrier {
+    pub const ACCEPTS : [char; 11] = ['f', 'h', 's', 't', 'T', 'n', 'm', 'g', 'G', 'H', 'a'];
+    pub const BERTHS : usize = 10;
+
     pub fn new() -> Self {
         Self {
             angle_v : 0.0,
@@ -80,6 +91,48 @@ impl Carrier {
             gt_count : 10
         }
     }
+
+    pub fn parse_loadout(loadout : &str) -> Result<Vec<(usize, char)>, String> { // loadouts are pairs of berth digit and type code, like "0T1T4h5h"
+        let chars : Vec<char> = loadout.chars().collect();
+        if chars.len() % 2 != 0 {
+            return Err("Carrier loadouts are pairs of berth and type".to_string());
+        }
+        let mut ret : Vec<(usize, char)> = vec![];
+        for pair in chars.chunks(2) {
+            let berth = match pair[0].to_digit(10) {
+                Some(berth) if (berth as usize) < Self::BERTHS => berth as usize,
+                _ => return Err(format!("'{}' isn't a berth", pair[0]))
+            };
+            if ret.iter().any(|(taken, _)| *taken == berth) {
+                return Err(format!("Berth {} is loaded twice", berth));
+            }
+            if !Self::ACCEPTS.contains(&pair[1]) {
+                return Err(format!("Carriers can't carry '{}'", pair[1]));
+            }
+            ret.push((berth, pair[1]));
+        }
+        Ok(ret)
+    }
+
+    pub fn loadout_from_variant(variant : u32) -> String { // the old base-9 encoding: one digit per berth, 0 for empty
+        let mut ret = String::new();
+        for berth in 0..Self::BERTHS {
+            let tp = match (variant / 9_u32.pow(berth as u32)) % 9 {
+                1 => 'h',
+                2 => 'f',
+                3 => 't',
+                4 => 's',
+                5 => 'n',
+                6 => 'T',
+                7 => 'm',
+                8 => 'g',
+                _ => continue
+            };
+            ret.push(std::char::from_digit(berth as u32, 10).unwrap());
+            ret.push(tp);
+        }
+        ret
+    }
 }
 
 impl AntiRTFBullet {
@@ -88,6 +141,26 @@ impl AntiRTFBullet {
 
         }
     }
+
+    pub fn average_speed(distance : f32) -> f32 { // how fast one fired from a standing launcher covers this distance on average, flying the way update does: out of the gun at BULLET_SPEED, burning hard, then easing off up close
+        let mut speed = BULLET_SPEED;
+        let mut travelled = 0.0;
+        let mut ticks = 0;
+        while travelled < distance && ticks < 1000 {
+            if distance - travelled > MISSILE_BURN_DISTANCE {
+                speed += MISSILE_FAR_THRUST;
+            }
+            else {
+                speed = speed * MISSILE_FRICTION + MISSILE_NEAR_THRUST;
+            }
+            travelled += speed;
+            ticks += 1;
+        }
+        if ticks == 0 {
+            return BULLET_SPEED; // right on top of us
+        }
+        distance / ticks as f32
+    }
 }
 
 impl Wall {
@@ -251,8 +324,9 @@ impl GamePiece for Carrier {
         thing.health_properties.passive_heal = 0.02;
         thing.collision_info.damage = 1.0;
         thing.physics.speed_cap = 12.0;
-        thing.carrier_properties.space_remaining = 10;
-        thing.carrier_properties.does_accept = vec!['f', 'h', 's', 't', 'T', 'n', 'm', 'g', 'G', 'H'];
+        thing.carrier_properties.space_remaining = Carrier::BERTHS as u32;
+        thing.carrier_properties.does_accept = Carrier::ACCEPTS.to_vec();
+        thing.carrier_properties.does_accept.push('Y'); // enemy flags, in ctf games. not in ACCEPTS because you can't buy them.
         thing.health_properties.prevent_friendly_fire = true;
     }
 
@@ -265,7 +339,7 @@ impl GamePiece for Carrier {
     }
     
     fn update(&mut self, properties : &mut ExposedProperties, server : &mut Server) {
-        let vec_to = Vector2::new(properties.goal_x - properties.physics.cx(), properties.goal_y - properties.physics.cy());
+        let vec_to = properties.nav_target() - properties.physics.vector_position();
         let mut thrust = Vector2::new_from_manda(1.0, properties.physics.angle());
         let mut l = loopize(properties.physics.angle(), vec_to.angle());
         let mut reversed = false;
@@ -307,6 +381,14 @@ impl GamePiece for Carrier {
         true
     }
 
+    fn does_navigate(&self) -> bool {
+        true
+    }
+
+    fn default_stance(&self) -> Option<Stance> {
+        Some(Stance::FireAtWill) // no guns; pick evasive and it runs home when it's hurt
+    }
+
     fn on_carry(&mut self, me : &mut ExposedProperties, thing : &mut ExposedProperties, server : &mut Server) { // when a new object becomes carried by this
         if thing.value == 'G' {
             self.green_thumbs += 1;
@@ -314,7 +396,7 @@ impl GamePiece for Carrier {
                 server.send_to(ServerToClient::Leprechaun, me.banner);
             }
         }
-        thing.goal_x = -1.0;
+        thing.carrier_properties.launch = LaunchState::Docked;
         if thing.value == 'h' {
             me.physics.speed_cap += 3.0;
         }
@@ -398,10 +480,7 @@ x|----|----|----|----|----|
         new_pos = new_pos.rotate_about(Vector2::new(me.physics.cx(), me.physics.cy()), me.physics.angle());
         thing.physics.set_cx(new_pos.x);
         thing.physics.set_cy(new_pos.y);
-        if thing.goal_x != -1.0 {
-            return true;
-        }
-        false
+        thing.carrier_properties.launch != LaunchState::Docked
     }
 
     fn drop_carry(&mut self, me : &mut ExposedProperties, thing : &mut ExposedProperties) {
@@ -444,12 +523,12 @@ impl GamePiece for AntiRTFBullet {
             Some(vector_to) => {
                 let goalangle = vector_to.angle();
                 properties.physics.change_angle(loopize(goalangle, properties.physics.angle()) * 0.4);
-                if vector_to.magnitude() > 500.0 {
-                    properties.physics.thrust(2.0); // go way faster if it's far away
+                if vector_to.magnitude() > MISSILE_BURN_DISTANCE {
+                    properties.physics.thrust(MISSILE_FAR_THRUST); // go way faster if it's far away
                 }
                 else {
-                    properties.physics.velocity = properties.physics.velocity * 0.99; // add a lil' friction so it can decelerate after going super fast cross-board
-                    properties.physics.thrust(1.0); // but also keep some thrust so the angle correction isn't moot
+                    properties.physics.velocity = properties.physics.velocity * MISSILE_FRICTION; // add a lil' friction so it can decelerate after going super fast cross-board
+                    properties.physics.thrust(MISSILE_NEAR_THRUST); // but also keep some thrust so the angle correction isn't moot
                 }
                 if (properties.physics.velocity.angle() - goalangle).abs() > PI/3.0 {
                     properties.physics.velocity = properties.physics.velocity * 0.9;
@@ -609,6 +688,7 @@ impl GamePiece for Turret {
         thing.targeting.mode = TargetingMode::Nearest;
         thing.targeting.filter = TargetingFilter::Fighters;
         thing.targeting.range = (0.0, 500.0);
+        thing.targeting.require_sight = true;
         thing.shooter_properties.shoot = true;
         thing.shooter_properties.counter = 30;
         thing.carrier_properties.can_update = true;
@@ -627,9 +707,9 @@ impl GamePiece for Turret {
     }
 
     fn update(&mut self, properties : &mut ExposedProperties, _server : &mut Server) {
-        match properties.targeting.vector_to {
+        match properties.targeting.lead(BULLET_SPEED) {
             Some(vector) => {
-                properties.physics.set_angle(vector.angle());
+                properties.physics.set_angle(vector.angle()); // lead the target, so we hit where it's going to be
                 properties.shooter_properties.suppress = false;
                 if properties.carrier_properties.is_carried {
                     properties.shooter_properties.suppress = false;
@@ -650,6 +730,10 @@ impl GamePiece for Turret {
     fn cost(&self) -> i32 {
         100
     }
+
+    fn default_stance(&self) -> Option<Stance> {
+        Some(Stance::FireAtWill)
+    }
 }
 
 impl GamePiece for MissileLaunchingSystem {
@@ -657,6 +741,7 @@ impl GamePiece for MissileLaunchingSystem {
         thing.targeting.mode = TargetingMode::Nearest;
         thing.targeting.filter = TargetingFilter::RealTimeFighter;
         thing.targeting.range = (0.0, 1000.0);
+        thing.targeting.require_sight = true;
         thing.shooter_properties.shoot = true;
         thing.shooter_properties.bullet_type = BulletType::AntiRTF;
         thing.shooter_properties.counter = 150;
@@ -678,9 +763,10 @@ impl GamePiece for MissileLaunchingSystem {
     }
 
     fn update(&mut self, properties : &mut ExposedProperties, _server : &mut Server) {
-        match properties.targeting.vector_to {
+        let distance = match properties.targeting.vector_to { Some(vector_to) => vector_to.magnitude(), None => 0.0 };
+        match properties.targeting.lead(AntiRTFBullet::average_speed(distance)) { // missiles speed up on the way, so lead by how fast they'll average getting there
             Some(vector) => {
-                properties.physics.set_angle(vector.angle());
+                properties.physics.set_angle(vector.angle()); // lead the target, so we hit where it's going to be
                 properties.shooter_properties.suppress = false;
             },
             None => {
@@ -692,6 +778,10 @@ impl GamePiece for MissileLaunchingSystem {
     fn cost(&self) -> i32 {
         100
     }
+
+    fn default_stance(&self) -> Option<Stance> {
+        Some(Stance::FireAtWill)
+    }
 }
 
 impl GamePiece for Radiation {
@@ -742,7 +832,7 @@ impl GamePiece for Nuke {
     }
 
     fn update(&mut self, properties : &mut ExposedProperties, _server : &mut Server) {
-        let thrust = Vector2::new(properties.goal_x - properties.physics.cx(), properties.goal_y - properties.physics.cy()).unit() * 0.1;
+        let thrust = (properties.nav_target() - properties.physics.vector_position()).unit() * 0.1;
         properties.physics.velocity = properties.physics.velocity + thrust;
         properties.physics.velocity = properties.physics.velocity * 0.999;
     }
@@ -750,6 +840,10 @@ impl GamePiece for Nuke {
     fn is_editable(&self) -> bool {
         true
     }
+
+    fn does_navigate(&self) -> bool {
+        true
+    }
 }
 
 impl GamePiece for Block {
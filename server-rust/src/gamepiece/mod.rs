@@ -8,7 +8,21 @@ pub mod fighters;
 pub mod misc;
 pub mod npc;
 pub mod nexus;
-use crate::functions::coterminal;
+pub mod zones;
+use crate::functions::{coterminal, loopize};
+use std::collections::HashMap;
+
+const STUCK_TICKS : u16 = 60; // if a navigating piece hasn't gotten any closer to its next waypoint in this long, it replans
+const ARRIVAL_RADIUS : f32 = 30.0; // how close a piece has to get to a queued waypoint before it moves on to the next one
+const ATTACK_MOVE_RANGE : f32 = 800.0; // how far off the route an attack-moving piece will go after things
+const PROVOKED_TICKS : u16 = 90; // how long a return-fire piece keeps shooting after it last took damage
+const FIRING_ARC : f32 = 0.3; // how far off-center (in radians) a target can be before a stance-controlled piece holds its fire
+const RETREAT_HEALTH : f32 = 0.4; // evasive pieces run home below this fraction of their health...
+const RECOVERED_HEALTH : f32 = 0.8; // ...and stop running once they're back above this
+pub const BULLET_SPEED : f32 = 20.0; // how fast bullets leave the gun, on top of the shooter's own velocity
+const SIGHT_RECHECK_TICKS : u16 = 15; // line of sight results are reused for this long before we raycast again
+const RECALL_REPLAN_DISTANCE : f32 = 100.0; // a recalled piece only re-aims at its carrier when the carrier has moved this far, so it isn't replanning every tick
+pub const MAX_ORDERS : usize = 32; // how many move orders can wait behind the current one. any more are dropped, so clients can't grow the queue forever
 
 
 #[derive(Clone)]
@@ -31,20 +45,68 @@ pub struct HealthProperties {
 }
 
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum TargetingFilter {
     Any,
     Fighters,
     Castles,
-    RealTimeFighter
+    RealTimeFighter,
+    Types (Vec<char>), // anything with one of these type codes
+    Banner (usize), // only things flying this banner
+    Threat (f32), // only things at least this dangerous (see GamePieceBase::threat)
+    All (Vec<TargetingFilter>) // has to pass every one of these
+}
+
+
+impl TargetingFilter {
+    pub fn matches(&self, object : &GamePieceBase) -> bool {
+        match self {
+            TargetingFilter::Any => true,
+            TargetingFilter::Fighters => {
+                match object.identify() {
+                    'f' | 'h' | 'R' | 't' | 's' | '&' | 'C' => true,
+                    _ => false
+                }
+            },
+            TargetingFilter::Castles => {
+                match object.identify() {
+                    'R' | 'c' => true,
+                    _ => false
+                }
+            },
+            TargetingFilter::RealTimeFighter => object.identify() == 'R',
+            TargetingFilter::Types (types) => types.contains(&object.identify()),
+            TargetingFilter::Banner (banner) => object.get_banner() == *banner,
+            TargetingFilter::Threat (threat) => object.threat() >= *threat,
+            TargetingFilter::All (filters) => filters.iter().all(|filter| filter.matches(object))
+        }
+    }
 }
 
 
-#[derive(PartialEq, Clone)]
+#[derive(PartialEq, Clone, Debug)]
 pub enum TargetingMode {
     None,
     Nearest,
-    Id (u32)
+    Id (u32),
+    LowestHealth, // finish off whatever's closest to dying
+    HighestCapture, // go for whatever's worth the most money
+    LastAttacker, // shoot back at whoever hit us last
+    FocusFire // whatever our team has designated, and the nearest thing if there isn't one in range
+}
+
+
+impl TargetingMode {
+    pub fn from_byte(byte : u8) -> Option<Self> { // Id isn't in here; it's only set by pieces themselves
+        match byte {
+            b'n' => Some(TargetingMode::Nearest),
+            b'l' => Some(TargetingMode::LowestHealth),
+            b'c' => Some(TargetingMode::HighestCapture),
+            b'a' => Some(TargetingMode::LastAttacker),
+            b'f' => Some(TargetingMode::FocusFire),
+            _ => None
+        }
+    }
 }
 
 
@@ -78,7 +140,30 @@ pub struct Targeting {
     mode      : TargetingMode,
     filter    : TargetingFilter,
     range     : (f32, f32),
-    vector_to : Option<Vector2>
+    vector_to : Option<Vector2>,
+    velocity  : Vector2, // the target's velocity relative to us, for leading shots
+    last_attacker : Option<usize>, // banner of whoever damaged us most recently
+    pub require_sight : bool // if set, we won't pick targets on the other side of map blocks
+}
+
+
+impl Targeting {
+    pub fn lock_on(&mut self, vector_to : Vector2, velocity : Vector2) {
+        self.vector_to = Some(vector_to);
+        self.velocity = velocity;
+    }
+
+    pub fn lead(&self, projectile_speed : f32) -> Option<Vector2> { // where to point to hit the target with a projectile. falls back to pointing right at it if it can't be caught.
+        match self.vector_to {
+            Some(vector_to) => {
+                match Vector2::intercept(vector_to, self.velocity, projectile_speed) {
+                    Some(aim) => Some(aim),
+                    None => Some(vector_to)
+                }
+            },
+            None => None
+        }
+    }
 }
 
 
@@ -90,7 +175,19 @@ pub struct CarrierProperties {
     pub can_update : bool, // if it can update safely while being carried
     pub is_carried : bool, // if it's being carried at the moment. objects being carried cannot shoot and don't do any collision damage.
     pub berth      : usize, // if it's being carried, this is the berth it's in.
-    pub carrier    : u32 // who is carrying us
+    pub carrier    : u32, // who is carrying us
+    pub launch     : LaunchState, // if it's being carried, whether it wants out
+    pub recall     : Option<u32>, // if it's flying back to dock on a carrier, which one
+    pub docked     : bool, // carriers: parked at a friendly castle or fort
+    pub purchase_queue : Vec<char> // carriers: cargo to buy and load, in order, next time it's docked
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LaunchState {
+    Docked,      // sitting in its berth
+    Launch,      // leave on the carrier's next update and park just outside it
+    LaunchToGoal // leave on the carrier's next update and head for goal_x/goal_y; this is what moving a carried piece does
 }
 
 
@@ -101,6 +198,77 @@ impl CarrierProperties {
 }
 
 
+#[derive(Clone)]
+pub struct Navigation {
+    pub waypoints : Vec<Vector2>, // what's left of the current path. the last one is the goal.
+    goal          : Vector2, // the goal this path was planned for; when goal_x/goal_y change, we replan
+    best_distance : f32, // closest we've gotten to the next waypoint, for stuck detection
+    stuck_timer   : u16
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stance {
+    FireAtWill, // the default: guns fire forward however they always have, and it goes where it's told without turning or chasing on its own
+    HoldFire,   // never shoots, never chases, just holds position
+    ReturnFire, // holds position, but turns on and shoots at threats for a while after taking damage
+    Aggressive, // goes after anything in range, and fires freely
+    Evasive     // only shoots when lined up on something in range, never chases, and runs home when it's hurt
+}
+
+
+impl Stance {
+    pub fn from_byte(byte : u8) -> Option<Self> {
+        match byte {
+            b'f' => Some(Stance::FireAtWill),
+            b'h' => Some(Stance::HoldFire),
+            b'r' => Some(Stance::ReturnFire),
+            b'a' => Some(Stance::Aggressive),
+            b'e' => Some(Stance::Evasive),
+            _ => None
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            Stance::FireAtWill => b'f',
+            Stance::HoldFire => b'h',
+            Stance::ReturnFire => b'r',
+            Stance::Aggressive => b'a',
+            Stance::Evasive => b'e'
+        }
+    }
+}
+
+
+#[derive(Copy, Clone, Debug)]
+pub struct MoveOrder {
+    pub x      : f32,
+    pub y      : f32,
+    pub a      : f32,
+    pub attack : bool // attack-move: engage anything that comes in range along the way
+}
+
+
+#[derive(Clone)]
+pub struct Orders {
+    pub queue       : Vec<MoveOrder>, // legs still to come, not counting the one we're on
+    pub current     : Option<MoveOrder>, // the leg we're on now. None means we're just sitting on a plain Move.
+    pub patrol      : bool, // finished legs go back on the end of the queue, so the route loops forever
+    saved_targeting : Option<Targeting> // what targeting looked like before an attack-move took it over
+}
+
+
+impl Orders {
+    pub fn is_attacking(&self) -> bool {
+        match self.current {
+            Some(order) => order.attack,
+            None => false
+        }
+    }
+}
+
+
 #[derive(Clone)]
 pub struct ExposedProperties { // everything a GamePieceBase wants to expose to GamePieces
     pub collision_info     : CollisionInfo,
@@ -117,10 +285,28 @@ pub struct ExposedProperties { // everything a GamePieceBase wants to expose to
     pub goal_a             : f32,
     pub ttl                : i32, // ttl of < 0 means ttl does nothing. ttl of 0 means die. ttl of anything higher means subtract one every update.
     pub repeater           : RepeaterProperties,
+    pub navigation         : Navigation,
+    pub orders             : Orders,
+    pub stance             : Option<Stance>, // None means the piece doesn't take stances and does its own thing
     pub banner             : usize
 }
 
 
+impl ExposedProperties {
+    pub fn nav_target(&self) -> Vector2 { // where a navigating piece should be steering right now: the next waypoint, or the goal if there's no path
+        if self.orders.is_attacking() || self.stance == Some(Stance::Aggressive) {
+            if let Some(vector_to) = self.targeting.vector_to {
+                return self.physics.vector_position() + vector_to; // break off and go after it
+            }
+        }
+        match self.navigation.waypoints.first() {
+            Some(waypoint) => *waypoint,
+            None => Vector2::new(self.goal_x, self.goal_y)
+        }
+    }
+}
+
+
 #[derive(Clone)]
 pub enum ExplosionMode {
     None,
@@ -189,6 +375,14 @@ pub trait GamePiece {
         false
     }
 
+    fn does_navigate(&self) -> bool { // pieces that drive to goal_x/goal_y and should be routed around blocks
+        false
+    }
+
+    fn default_stance(&self) -> Option<Stance> { // pieces that return None here ignore stances entirely
+        None
+    }
+
     fn on_subscribed_death(&mut self, _me : &mut ExposedProperties, _them : &mut GamePieceBase, _servah : &mut Server) {
 
     }
@@ -210,7 +404,14 @@ pub struct GamePieceBase {
     forts                  : Vec<u32>,
     pub upgrades           : Vec<String>,
     pub zones              : Vec<usize>,
-    pub death_subscriptions: Vec<u32>
+    pub death_subscriptions: Vec<u32>,
+    provoked               : u16, // ticks left of returning fire
+    last_health            : f32,
+    retreating             : bool,
+    sight_cache            : HashMap<u32, (bool, u16)>, // id -> (could we see it, ticks until we check again)
+    stance_eyes            : bool, // targeting is only on because the stance needs it
+    builtin_filter         : TargetingFilter, // what the piece shoots at out of the box; whatever the player picks only ever narrows it
+    pub fired_by           : Option<char> // what shot it, if it's a bullet
 }
 
 impl GamePieceBase {
@@ -248,7 +449,10 @@ impl GamePieceBase {
                     mode : TargetingMode::None,
                     filter : TargetingFilter::Any,
                     range : (0.0, 0.0),
-                    vector_to : None
+                    vector_to : None,
+                    velocity : Vector2::empty(),
+                    last_attacker : None,
+                    require_sight : false
                 },
                 goal_x : x,
                 goal_y : y,
@@ -264,22 +468,49 @@ impl GamePieceBase {
                     is_carried : false,
                     can_update : false,
                     berth : 0,
-                    carrier : 0
+                    carrier : 0,
+                    launch : LaunchState::Docked,
+                    recall : None,
+                    docked : false,
+                    purchase_queue : vec![]
                 },
                 repeater : RepeaterProperties {
                     repeats     : 0,
                     max_repeats : 0,
                     repeat_cd   : 5 // default, you don't usually have to touch this
-                }
+                },
+                navigation : Navigation {
+                    waypoints     : vec![],
+                    goal          : Vector2::new(x, y),
+                    best_distance : f32::MAX,
+                    stuck_timer   : 0
+                },
+                orders : Orders {
+                    queue           : vec![],
+                    current         : None,
+                    patrol          : false,
+                    saved_targeting : None
+                },
+                stance : None
             },
             broadcasts : vec![],
             forts : vec![],
             piece,
             upgrades : vec![],
-            death_subscriptions : vec![]
+            death_subscriptions : vec![],
+            provoked : 0,
+            last_health : 0.0,
+            retreating : false,
+            sight_cache : HashMap::new(),
+            stance_eyes : false,
+            builtin_filter : TargetingFilter::Any,
+            fired_by : None
         };
         thing.piece.construct(&mut thing.exposed_properties);
+        thing.exposed_properties.stance = thing.piece.default_stance();
+        thing.builtin_filter = thing.exposed_properties.targeting.filter.clone();
         thing.exposed_properties.health_properties.health = thing.exposed_properties.health_properties.max_health;
+        thing.last_health = thing.exposed_properties.health_properties.health;
         thing.exposed_properties.repeater.repeats = thing.exposed_properties.repeater.max_repeats;
         thing
     }
@@ -300,6 +531,10 @@ impl GamePieceBase {
         self.exposed_properties.value
     }
 
+    pub fn killer_type(&self) -> char { // what gets the credit when this kills something: bullets count as whatever fired them
+        self.fired_by.unwrap_or(self.identify())
+    }
+
     pub fn is_editable(&self) -> bool {
         self.piece.is_editable()
     }
@@ -319,6 +554,13 @@ impl GamePieceBase {
                 Some(obj) => {
                     if self.piece.carry_iter(&mut self.exposed_properties, &mut server.objects[obj].exposed_properties) { // drop the carried object
                         self.piece.drop_carry(&mut self.exposed_properties, &mut server.objects[obj].exposed_properties);
+                        let thing = &mut server.objects[obj].exposed_properties;
+                        if thing.carrier_properties.launch == LaunchState::Launch { // plain launches park right where they got dropped
+                            thing.goal_x = thing.physics.cx();
+                            thing.goal_y = thing.physics.cy();
+                            thing.goal_a = thing.physics.angle();
+                        }
+                        thing.carrier_properties.launch = LaunchState::Docked;
                         server.send_to(ServerToClient::UnCarry (server.objects[obj].get_id()), server.objects[obj].get_banner());
                         self.exposed_properties.carrier_properties.space_remaining += 1;
                         server.objects[obj].exposed_properties.carrier_properties.is_carried = false;
@@ -343,12 +585,32 @@ impl GamePieceBase {
         }
     }
 
+    fn can_see(&mut self, object : &GamePieceBase, server : &Server) -> bool {
+        let id = object.get_id();
+        if let Some((visible, _)) = self.sight_cache.get(&id) {
+            return *visible;
+        }
+        let visible = server.line_of_sight(self.exposed_properties.physics.vector_position(), object.exposed_properties.physics.vector_position(), id);
+        self.sight_cache.insert(id, (visible, SIGHT_RECHECK_TICKS));
+        visible
+    }
+
     pub fn target(&mut self, server : &mut Server) {
+        if self.exposed_properties.targeting.require_sight {
+            self.sight_cache.retain(|_, (_, ttl)| { // age out old results
+                if *ttl == 0 {
+                    return false;
+                }
+                *ttl -= 1;
+                true
+            });
+        }
         let mut best : Option<usize> = None;
         let mut best_value : f32 = 0.0; // If best is None, this value is ignored, so it can be anything.
         // The goal here is to compare the entire list of objects by some easily derived numerical component,
         // based on a set of options stored in targeting, and set the values in targeting based on that.
         // NOTE: the comparison is *always* <; if you want to compare > values multiply by negative 1.
+        let focus = if self.exposed_properties.targeting.mode == TargetingMode::FocusFire { server.get_focus_target(self.banner) } else { None };
         let mut carrier = None;
         if self.exposed_properties.carrier_properties.is_carried {
             carrier = server.obj_lookup(self.exposed_properties.carrier_properties.carrier);
@@ -361,26 +623,7 @@ impl GamePieceBase {
             if object.get_banner() != 0 && (object.get_banner() == self.get_banner() || (server.get_team_of_banner(object.get_banner()) == server.get_team_of_banner(self.get_banner())) && server.get_team_of_banner(self.get_banner()).is_some()) { // If you're under the same flag, skip.
                 continue;
             }
-            let mut viable = match self.exposed_properties.targeting.filter {
-                TargetingFilter::Any => {
-                    true
-                },
-                TargetingFilter::Fighters => {
-                    match object.identify() {
-                        'f' | 'h' | 'R' | 't' | 's' | '&' | 'C' => true,
-                        _ => false
-                    }
-                },
-                TargetingFilter::Castles => {
-                    match object.identify() {
-                        'R' | 'c' => true,
-                        _ => false
-                    }
-                },
-                TargetingFilter::RealTimeFighter => {
-                    object.identify() == 'R'
-                }
-            };
+            let mut viable = self.exposed_properties.targeting.filter.matches(object);
             match carrier {
                 Some(carrier) => {
                     let mut bullet = object.exposed_properties.physics.vector_position() - self.exposed_properties.physics.vector_position(); // anticipate a bullet position. we won't target this if shooting at it would damage the carrier.
@@ -394,16 +637,52 @@ impl GamePieceBase {
                 None => {}
             }
             if viable {
+                let dist = (object.exposed_properties.physics.vector_position() - self.exposed_properties.physics.vector_position()).magnitude();
+                let in_range = (dist >= self.exposed_properties.targeting.range.0 && dist <= self.exposed_properties.targeting.range.1) || self.exposed_properties.targeting.range.1 == 0.0;
                 let val = match self.exposed_properties.targeting.mode {
                     TargetingMode::Nearest => {
-                        let dist = (object.exposed_properties.physics.vector_position() - self.exposed_properties.physics.vector_position()).magnitude();
-                        if (dist >= self.exposed_properties.targeting.range.0 && dist <= self.exposed_properties.targeting.range.1) || self.exposed_properties.targeting.range.1 == 0.0 {
+                        if in_range {
                             Some(dist)
                         }
                         else {
                             None
                         }
                     },
+                    TargetingMode::LowestHealth => {
+                        if in_range {
+                            Some(object.get_health_perc())
+                        }
+                        else {
+                            None
+                        }
+                    },
+                    TargetingMode::HighestCapture => {
+                        if in_range {
+                            Some(-(object.capture() as f32))
+                        }
+                        else {
+                            None
+                        }
+                    },
+                    TargetingMode::LastAttacker => {
+                        if in_range && self.exposed_properties.targeting.last_attacker == Some(object.get_banner()) {
+                            Some(dist) // we only know the banner, so go for the closest thing flying it
+                        }
+                        else {
+                            None
+                        }
+                    },
+                    TargetingMode::FocusFire => {
+                        if !in_range {
+                            None
+                        }
+                        else if focus == Some(object.get_id()) {
+                            Some(-1.0) // beats any distance
+                        }
+                        else {
+                            Some(dist)
+                        }
+                    },
                     TargetingMode::Id (id) => {
                         if object.exposed_properties.id == id {
                             Some(0.0) // the id is always the best possibility
@@ -416,15 +695,19 @@ impl GamePieceBase {
                 };
                 if val.is_some() {
                     if val.unwrap() < best_value || !best.is_some() {
-                        best_value = val.unwrap();
-                        best = Some(i);
+                        if !self.exposed_properties.targeting.require_sight || self.can_see(object, server) { // only raycast for things that would actually win
+                            best_value = val.unwrap();
+                            best = Some(i);
+                        }
                     }
                 }
             }
         }
         match best {
             Some(best) => {
-                self.exposed_properties.targeting.vector_to = Some(server.objects[best].exposed_properties.physics.vector_position() - self.exposed_properties.physics.vector_position());
+                let vector_to = server.objects[best].exposed_properties.physics.vector_position() - self.exposed_properties.physics.vector_position();
+                let velocity = server.objects[best].exposed_properties.physics.velocity - self.exposed_properties.physics.velocity;
+                self.exposed_properties.targeting.lock_on(vector_to, velocity);
             }
             None => {
                 self.exposed_properties.targeting.vector_to = None;
@@ -432,6 +715,219 @@ impl GamePieceBase {
         }
     }
 
+    pub fn order_move(&mut self, x : f32, y : f32, a : f32) { // a plain Move throws away whatever was queued
+        self.clear_orders();
+        self.exposed_properties.goal_x = x;
+        self.exposed_properties.goal_y = y;
+        self.exposed_properties.goal_a = a;
+        self.launch_if_carried();
+    }
+
+    fn launch_if_carried(&mut self) { // moving a carried piece sends it out of the carrier towards the new goal
+        self.exposed_properties.carrier_properties.recall = None;
+        if self.exposed_properties.carrier_properties.is_carried {
+            self.exposed_properties.carrier_properties.launch = LaunchState::LaunchToGoal;
+        }
+    }
+
+    pub fn launch(&mut self) -> bool { // returns false if it isn't being carried
+        if !self.exposed_properties.carrier_properties.is_carried {
+            return false;
+        }
+        self.exposed_properties.carrier_properties.launch = LaunchState::Launch;
+        true
+    }
+
+    pub fn recall(&mut self, carrier : u32) {
+        self.clear_orders();
+        self.exposed_properties.carrier_properties.recall = Some(carrier);
+    }
+
+    fn follow_recall(&mut self, server : &Server) { // fly back to the carrier; the collision code docks us when we touch it
+        let carrier = match self.exposed_properties.carrier_properties.recall { Some(carrier) => carrier, None => return };
+        match server.obj_lookup(carrier) {
+            Some(index) if server.objects[index].exposed_properties.carrier_properties.will_carry(self.identify()) => {
+                let position = server.objects[index].exposed_properties.physics.vector_position();
+                if (position - Vector2::new(self.exposed_properties.goal_x, self.exposed_properties.goal_y)).magnitude() > RECALL_REPLAN_DISTANCE {
+                    self.exposed_properties.goal_x = position.x;
+                    self.exposed_properties.goal_y = position.y;
+                }
+            },
+            _ => { // the carrier died or filled up in the meantime, so just stop where we are
+                self.exposed_properties.carrier_properties.recall = None;
+                self.exposed_properties.goal_x = self.exposed_properties.physics.cx();
+                self.exposed_properties.goal_y = self.exposed_properties.physics.cy();
+            }
+        }
+    }
+
+    pub fn queue_order(&mut self, order : MoveOrder) {
+        if self.exposed_properties.orders.current.is_none() {
+            self.start_order(order);
+        }
+        else if self.exposed_properties.orders.queue.len() < MAX_ORDERS {
+            self.exposed_properties.orders.queue.push(order);
+        }
+    }
+
+    pub fn clear_orders(&mut self) {
+        self.end_attack();
+        self.exposed_properties.orders.current = None;
+        self.exposed_properties.orders.queue.clear();
+        self.exposed_properties.orders.patrol = false;
+    }
+
+    fn start_order(&mut self, order : MoveOrder) {
+        self.exposed_properties.goal_x = order.x;
+        self.exposed_properties.goal_y = order.y;
+        self.exposed_properties.goal_a = order.a;
+        self.launch_if_carried();
+        self.exposed_properties.orders.current = Some(order);
+        if order.attack && self.exposed_properties.orders.saved_targeting.is_none() {
+            self.exposed_properties.orders.saved_targeting = Some(self.exposed_properties.targeting.clone());
+            self.exposed_properties.targeting.mode = TargetingMode::Nearest;
+            self.exposed_properties.targeting.filter = TargetingFilter::Fighters;
+            self.exposed_properties.targeting.range = (0.0, ATTACK_MOVE_RANGE);
+        }
+        else if !order.attack {
+            self.end_attack();
+        }
+    }
+
+    fn end_attack(&mut self) {
+        if let Some(targeting) = self.exposed_properties.orders.saved_targeting.take() {
+            self.exposed_properties.targeting = targeting;
+        }
+    }
+
+    fn follow_orders(&mut self) {
+        let order = match self.exposed_properties.orders.current { Some(order) => order, None => return };
+        if (Vector2::new(order.x, order.y) - self.exposed_properties.physics.vector_position()).magnitude() > ARRIVAL_RADIUS {
+            return; // not there yet
+        }
+        self.exposed_properties.orders.current = None;
+        if self.exposed_properties.orders.patrol {
+            self.exposed_properties.orders.queue.push(order);
+        }
+        if self.exposed_properties.orders.queue.len() > 0 {
+            let next = self.exposed_properties.orders.queue.remove(0);
+            self.start_order(next);
+        }
+        else {
+            self.end_attack();
+        }
+    }
+
+    pub fn set_stance(&mut self, stance : Stance) -> bool { // returns false if this piece doesn't take stances
+        if self.exposed_properties.stance.is_none() {
+            return false;
+        }
+        self.exposed_properties.stance = Some(stance);
+        self.provoked = 0;
+        self.retreating = false;
+        let needs_eyes = stance != Stance::FireAtWill && stance != Stance::HoldFire;
+        let shoot = self.exposed_properties.shooter_properties.shoot;
+        let range = self.exposed_properties.shooter_properties.range as f32 * 20.0;
+        let targeting = match &mut self.exposed_properties.orders.saved_targeting { Some(targeting) => targeting, None => &mut self.exposed_properties.targeting }; // an attack-move gives it back when it's done
+        if needs_eyes && !self.stance_eyes && shoot && targeting.mode == TargetingMode::None {
+            // forward-firing guns don't target on their own, so give them eyes out to about as far as their bullets go
+            targeting.mode = TargetingMode::Nearest;
+            targeting.filter = TargetingFilter::Fighters;
+            targeting.range = (0.0, range);
+            self.stance_eyes = true;
+        }
+        else if !needs_eyes && self.stance_eyes {
+            targeting.mode = TargetingMode::None;
+            targeting.vector_to = None;
+            self.stance_eyes = false;
+        }
+        true
+    }
+
+    fn apply_stance(&mut self, server : &Server) {
+        let stance = match self.exposed_properties.stance { Some(stance) => stance, None => return };
+        let health = self.exposed_properties.health_properties.health;
+        if health < self.last_health {
+            self.provoked = PROVOKED_TICKS;
+        }
+        else if self.provoked > 0 {
+            self.provoked -= 1;
+        }
+        self.last_health = health;
+        let target = self.exposed_properties.targeting.lead(BULLET_SPEED);
+        let engaged = target.is_some() && (self.exposed_properties.orders.is_attacking() || match stance {
+            Stance::FireAtWill | Stance::HoldFire => false, // fire-at-will guns don't need a target to shoot
+            Stance::ReturnFire => self.provoked > 0,
+            Stance::Aggressive | Stance::Evasive => true
+        });
+        let at_rest = (Vector2::new(self.exposed_properties.goal_x, self.exposed_properties.goal_y) - self.exposed_properties.physics.vector_position()).magnitude() < 10.0;
+        if engaged && at_rest && stance != Stance::Evasive {
+            self.exposed_properties.physics.set_angle(target.unwrap().angle()); // swing around to face it
+        }
+        if self.exposed_properties.shooter_properties.shoot && stance != Stance::Aggressive && stance != Stance::FireAtWill { // these keep firing however they always have
+            let lined_up = match target {
+                Some(vector) => loopize(vector.angle(), self.exposed_properties.physics.angle()).abs() < FIRING_ARC,
+                None => false
+            };
+            self.exposed_properties.shooter_properties.suppress = !(engaged && lined_up);
+        }
+        if stance == Stance::Evasive && self.piece.does_navigate() {
+            let fraction = health / self.exposed_properties.health_properties.max_health;
+            if fraction < RETREAT_HEALTH && !self.retreating {
+                let position = self.exposed_properties.physics.vector_position();
+                let mut home : Option<Vector2> = None;
+                for obj in &server.objects {
+                    if obj.get_banner() == self.banner && (obj.identify() == 'c' || obj.identify() == 'F') {
+                        let candidate = obj.exposed_properties.physics.vector_position();
+                        if home.is_none() || (candidate - position).magnitude() < (home.unwrap() - position).magnitude() {
+                            home = Some(candidate);
+                        }
+                    }
+                }
+                if let Some(home) = home {
+                    let offset = (position - home).unit() * 150.0; // park next to it, not on top of it
+                    self.order_move(home.x + offset.x, home.y + offset.y, self.exposed_properties.physics.angle());
+                    self.retreating = true;
+                }
+            }
+            else if fraction > RECOVERED_HEALTH {
+                self.retreating = false;
+            }
+        }
+    }
+
+    pub fn navigate(&mut self, server : &Server) {
+        let position = self.exposed_properties.physics.vector_position();
+        let goal = Vector2::new(self.exposed_properties.goal_x, self.exposed_properties.goal_y);
+        let nav = &mut self.exposed_properties.navigation;
+        if nav.goal != goal { // new orders, new path
+            nav.goal = goal;
+            nav.waypoints = server.navgrid.find_path(position, goal);
+            nav.best_distance = f32::MAX;
+            nav.stuck_timer = 0;
+        }
+        while nav.waypoints.len() > 1 && (nav.waypoints[0] - position).magnitude() < server.navgrid.cell_size() {
+            nav.waypoints.remove(0); // close enough, move on to the next one
+            nav.best_distance = f32::MAX;
+            nav.stuck_timer = 0;
+        }
+        if nav.waypoints.len() > 0 {
+            let distance = (nav.waypoints[0] - position).magnitude();
+            if distance < nav.best_distance - 1.0 {
+                nav.best_distance = distance;
+                nav.stuck_timer = 0;
+            }
+            else if distance > 10.0 { // not getting any closer, and not already there
+                nav.stuck_timer += 1;
+                if nav.stuck_timer > STUCK_TICKS { // something's in the way. replan from here.
+                    nav.waypoints = server.navgrid.find_path(position, goal);
+                    nav.best_distance = f32::MAX;
+                    nav.stuck_timer = 0;
+                }
+            }
+        }
+    }
+
     pub fn broadcast(&mut self, message : ServerToClient) {
         self.broadcasts.push(message);
     }
@@ -474,11 +970,17 @@ impl GamePieceBase {
             self.exposed_properties.physics.set_cy(server.objects[fort].exposed_properties.physics.cy());
             return; // Don't die yet! You have a fort!
         }
+        self.follow_orders();
+        self.follow_recall(server);
         if self.exposed_properties.targeting.mode != TargetingMode::None {
             self.target(server);
         }
+        if self.piece.does_navigate() {
+            self.navigate(server);
+        }
         self.exposed_properties.physics.update();
         self.piece.update(&mut self.exposed_properties, server);
+        self.apply_stance(server);
         self.update_carried(server);
         if self.exposed_properties.physics.portals {
             self.exposed_properties.physics.set_cx(coterminal(self.exposed_properties.physics.cx(), server.gamesize as f32));
@@ -535,9 +1037,10 @@ impl GamePieceBase {
                 server.fire_laser(self.exposed_properties.physics.extend_point(50.0, *angle), *angle + self.exposed_properties.physics.angle(), intensity);
             }
             else {
-                let bullet_id = server.shoot(self.exposed_properties.shooter_properties.bullet_type, self.exposed_properties.physics.extend_point(50.0, *angle), Vector2::new_from_manda(20.0, self.exposed_properties.physics.angle() + *angle) + self.exposed_properties.physics.velocity, range, None);
+                let bullet_id = server.shoot(self.exposed_properties.shooter_properties.bullet_type, self.exposed_properties.physics.extend_point(50.0, *angle), Vector2::new_from_manda(BULLET_SPEED, self.exposed_properties.physics.angle() + *angle) + self.exposed_properties.physics.velocity, range, None);
                 let bullet = server.obj_lookup(bullet_id).unwrap(); // Unwrap is safe here because the object is guaranteed to exist at this point.
                 server.objects[bullet].set_banner(self.banner); // Set the banner.
+                server.objects[bullet].fired_by = Some(self.identify());
             }
         }
     }
@@ -549,6 +1052,39 @@ impl GamePieceBase {
     pub fn damage(&mut self, harm : f32) {
         self.exposed_properties.health_properties.health -= harm;
     }
+
+    pub fn hit_by(&mut self, banner : usize) { // remember who's been shooting at us, for LastAttacker targeting
+        if banner != 0 && banner != self.banner {
+            self.exposed_properties.targeting.last_attacker = Some(banner);
+        }
+    }
+
+    pub fn last_attacker(&self) -> Option<usize> {
+        self.exposed_properties.targeting.last_attacker
+    }
+
+    pub fn threat(&self) -> f32 { // rough danger level: contact damage plus how many bullets it puts out per 100 ticks
+        let mut threat = self.exposed_properties.collision_info.damage;
+        if self.exposed_properties.shooter_properties.shoot {
+            threat += self.exposed_properties.shooter_properties.angles.len() as f32 * 100.0 / std::cmp::max(self.exposed_properties.shooter_properties.counter, 1) as f32;
+        }
+        threat
+    }
+
+    pub fn set_targeting(&mut self, mode : TargetingMode, filter : Option<TargetingFilter>) -> bool { // returns false if this piece doesn't pick its own targets. no filter goes back to the built-in one.
+        if self.exposed_properties.targeting.mode == TargetingMode::None || self.stance_eyes {
+            return false; // stance-given eyes are the stance's business
+        }
+        if let TargetingMode::Id (_) = self.exposed_properties.targeting.mode {
+            return false; // locked onto something specific, like a missile
+        }
+        self.exposed_properties.targeting.mode = mode;
+        self.exposed_properties.targeting.filter = match filter {
+            Some(filter) => TargetingFilter::All (vec![self.builtin_filter.clone(), filter]), // an MLS can't be talked into shooting at things its missiles can't hit
+            None => self.builtin_filter.clone()
+        };
+        true
+    }
     
     pub fn dead(&self) -> bool {
         self.exposed_properties.health_properties.health <= 0.0 && self.forts.len() == 0
@@ -701,6 +1237,8 @@ impl GamePiece for Castle {
             thing.physics.speed_cap = 20.0;
             thing.physics.portals = true;
             thing.health_properties.passive_heal = 0.002;
+            thing.carrier_properties.does_accept = vec!['Y']; // RTFs can run off with one flag at a time
+            thing.carrier_properties.space_remaining = 1;
         }
     }
 
@@ -710,6 +1248,13 @@ impl GamePiece for Castle {
         }
     }
 
+    fn carry_iter(&mut self, me : &mut ExposedProperties, thing : &mut ExposedProperties) -> bool { // drag the flag along behind us
+        let trail = me.physics.extend_point(-60.0, 0.0);
+        thing.physics.set_cx(trail.x);
+        thing.physics.set_cy(trail.y);
+        false
+    }
+
     fn identify(&self) -> char {
         if self.is_rtf { 'R' } else { 'c' }
     }
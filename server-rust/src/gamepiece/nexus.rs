@@ -7,12 +7,116 @@ use crate::TargetingMode;
 use crate::TargetingFilter;
 use crate::functions::coterminal;
 use crate::gamepiece::GamePieceBase;
+use crate::ledger::TransactionReason;
 
 
 pub struct Nexus {
     place_counter : u16,
     effect_radius : f32,
-    players       : Vec<usize> // list of player banners currently in this nexus; refreshed every tick at the moment. OPTIMIZATION PENDING.
+    players       : Vec<usize>, // list of player banners currently in this nexus; refreshed every tick at the moment. OPTIMIZATION PENDING.
+    table         : SpawnTable,
+    alive         : Vec<u32>, // ids of everything we've spawned that's still around
+    elapsed       : u32 // ticks spent with players inside, for escalation
+}
+
+
+#[derive(Clone)]
+pub struct Spawn {
+    pub unit        : char,
+    pub weight      : f32, // relative chance of picking this one
+    pub spam        : f32, // relative chances of each NexusStrategy
+    pub pull_up     : f32,
+    pub pull_around : f32,
+    pub distance    : (f32, f32) // min and max distance for PullUp and PullAround
+}
+
+
+#[derive(Clone)]
+pub struct SpawnTable {
+    pub min_interval   : u16, // ticks between waves
+    pub max_interval   : u16,
+    pub spawns         : Vec<Spawn>,
+    pub castle_chance  : f32, // chance a wave goes for a player's castle instead of the nexus itself
+    pub wave_size      : u16, // units per wave to start with
+    pub max_wave       : u16,
+    pub escalate_every : u32, // ticks between escalations; 0 never escalates
+    pub wave_growth    : u16, // extra units per wave each escalation
+    pub interval_scale : f32, // waves come this much faster each escalation
+    pub max_alive      : usize // never more than this many spawns around at once; 0 is no cap
+}
+
+
+impl Spawn {
+    fn pick_strategy(&self) -> NexusStrategy {
+        let distance = self.distance.0 + rand::random::<f32>() * (self.distance.1 - self.distance.0);
+        let roll = rand::random::<f32>() * (self.spam + self.pull_up + self.pull_around);
+        if roll < self.pull_up {
+            NexusStrategy::PullUp (distance)
+        }
+        else if roll < self.pull_up + self.pull_around {
+            NexusStrategy::PullAround (distance)
+        }
+        else {
+            NexusStrategy::Spam
+        }
+    }
+}
+
+
+impl SpawnTable {
+    pub fn classic() -> Self { // the spawns nexuses have always had
+        let fighter = |unit| Spawn { unit, weight : 1.0, spam : 1.0, pull_up : 1.0, pull_around : 1.0, distance : (200.0, 600.0) };
+        Self {
+            min_interval   : 200,
+            max_interval   : 500,
+            spawns         : vec![
+                Spawn { unit : 'h', weight : 1.0, spam : 1.0, pull_up : 0.0, pull_around : 0.0, distance : (0.0, 0.0) },
+                fighter('f'),
+                fighter('t'),
+                Spawn { unit : 's', weight : 1.0, spam : 0.0, pull_up : 0.0, pull_around : 1.0, distance : (400.0, 1000.0) }
+            ],
+            castle_chance  : 0.5,
+            wave_size      : 1,
+            max_wave       : u16::MAX,
+            escalate_every : 0,
+            wave_growth    : 0,
+            interval_scale : 1.0,
+            max_alive      : 0
+        }
+    }
+
+    fn pick(&self) -> Option<&Spawn> {
+        let total : f32 = self.spawns.iter().map(|spawn| spawn.weight).sum();
+        let mut roll = rand::random::<f32>() * total;
+        for spawn in &self.spawns {
+            if roll < spawn.weight {
+                return Some(spawn);
+            }
+            roll -= spawn.weight;
+        }
+        self.spawns.last()
+    }
+
+    pub fn level(&self, elapsed : u32) -> u32 { // how many times it's escalated
+        if self.escalate_every == 0 {
+            0
+        }
+        else {
+            elapsed / self.escalate_every
+        }
+    }
+
+    pub fn wave(&self, elapsed : u32) -> u16 {
+        let wave = self.wave_size as u32 + self.level(elapsed) * self.wave_growth as u32;
+        std::cmp::min(wave, self.max_wave as u32) as u16
+    }
+
+    pub fn interval(&self, elapsed : u32) -> u16 {
+        let scale = self.interval_scale.powi(self.level(elapsed) as i32);
+        let base = self.min_interval as f32 + rand::random::<f32>() * (self.max_interval - self.min_interval) as f32;
+        let interval = (base * scale) as u16;
+        if interval < 1 { 1 } else { interval }
+    }
 }
 
 pub struct NexusEnemy {
@@ -21,11 +125,14 @@ pub struct NexusEnemy {
 }
 
 impl Nexus {
-    pub fn new(effect_radius : f32) -> Nexus {
+    pub fn new(effect_radius : f32, table : SpawnTable) -> Nexus {
         Nexus {
             effect_radius,
             place_counter : 100,
-            players: vec![]
+            players: vec![],
+            table,
+            alive : vec![],
+            elapsed : 0
         }
     }
 }
@@ -86,8 +193,9 @@ impl GamePiece for Nexus {
     }
 
     fn on_subscribed_death(&mut self, _me : &mut ExposedProperties, them : &mut GamePieceBase, server : &mut Server) {
+        self.alive.retain(|id| *id != them.get_id());
         for player in &self.players {
-            server.score_to(*player, them.capture() as i32);
+            server.score_to(*player, them.capture() as i32, TransactionReason::Kill);
         }
     }
 
@@ -110,76 +218,79 @@ impl GamePiece for Nexus {
             }
         }
         if self.players.len() > 0 {
+            self.elapsed += 1;
             self.place_counter -= 1;
             if self.place_counter == 0 {
-                self.place_counter = 200 + rand::random::<u16>() % 300;
-                let pick_pos = coterminal(rand::random::<f32>() * self.effect_radius, self.effect_radius) - self.effect_radius/2.0;
-                println!("Pick pos: {}", pick_pos);
-                let mut x : f32 = 0.0;
-                let mut y : f32 = 0.0;
-                match rand::random::<u8>() % 4 {
-                    0 => {
-                        x = pick_pos;
-                        y = -self.effect_radius / 2.0;
-                    }
-                    1 => {
-                        x = pick_pos;
-                        y = self.effect_radius / 2.0;
-                    }
-                    2 => {
-                        y = pick_pos;
-                        x = -self.effect_radius / 2.0;
-
-                    }
-                    3 => {
-                        y = pick_pos;
-                        x = self.effect_radius / 2.0;
-                    }
-                    _ => {}
+                self.place_counter = self.table.interval(self.elapsed);
+                let target = if rand::random::<f32>() < self.table.castle_chance { // the whole wave goes for the same place
+                    use rand::seq::SliceRandom;
+                    Some(*self.players.choose(&mut rand::thread_rng()).unwrap())
                 }
-                x += properties.physics.cx();
-                y += properties.physics.cy();
-                let a = rand::random::<f32>() * std::f32::consts::PI * 2.0;
-                let mut strategy : NexusStrategy = NexusStrategy::Spam;
-                let enemy = match rand::random::<u16>() % 4 {
-                    0 => {
-                        server.place_missile(x, y, a, None)
-                    }
-                    1 => {
-                        strategy = match rand::random::<u8>() % 3 { 0 => NexusStrategy::PullUp (200.0 + rand::random::<f32>() * 400.0), 1 => NexusStrategy::PullAround (200.0 + rand::random::<f32>() * 400.0), _ => NexusStrategy::Spam };
-                        server.place_basic_fighter(x, y, a, None)
-                    }
-                    2 => {
-                        strategy = match rand::random::<u8>() % 3 { 0 => NexusStrategy::PullUp (200.0 + rand::random::<f32>() * 400.0), 1 => NexusStrategy::PullAround (200.0 + rand::random::<f32>() * 400.0), _ => NexusStrategy::Spam };
-                        server.place_tie_fighter(x, y, a, None)
-                    }
-                    3 => {
-                        strategy = NexusStrategy::PullAround (400.0 + rand::random::<f32>() * 600.0);
-                        server.place_sniper(x, y, a, None)
-                    }
-                    _ => {0}
+                else {
+                    None
                 };
-                let en_ind = server.obj_lookup(enemy).unwrap();
-                let mut t_v = properties.physics.vector_position();
-                if rand::random::<u8>() <= 128 && self.players.len() > 0 {
-                    use rand::seq::SliceRandom;
-                    let target = *self.players.choose(&mut rand::thread_rng()).unwrap();
-                    println!("Attacking {}", target);
-                    for obj in &server.objects {
-                        if obj.get_banner() == target && obj.identify() == 'c' { // it only attacks stationary castles; rtfs are too mobile
-                            t_v = obj.exposed_properties.physics.vector_position();
-                        }
+                for _ in 0..self.table.wave(self.elapsed) {
+                    if self.table.max_alive > 0 && self.alive.len() >= self.table.max_alive {
+                        break;
                     }
+                    self.spawn(properties, target, server);
+                }
+            }
+        }
+    }
+}
+
+
+impl Nexus {
+    fn spawn(&mut self, properties : &mut ExposedProperties, target : Option<usize>, server : &mut Server) {
+        let spawn = match self.table.pick() { Some(spawn) => spawn.clone(), None => return };
+        let pick_pos = coterminal(rand::random::<f32>() * self.effect_radius, self.effect_radius) - self.effect_radius/2.0;
+        let mut x : f32 = 0.0;
+        let mut y : f32 = 0.0;
+        match rand::random::<u8>() % 4 {
+            0 => {
+                x = pick_pos;
+                y = -self.effect_radius / 2.0;
+            }
+            1 => {
+                x = pick_pos;
+                y = self.effect_radius / 2.0;
+            }
+            2 => {
+                y = pick_pos;
+                x = -self.effect_radius / 2.0;
+
+            }
+            3 => {
+                y = pick_pos;
+                x = self.effect_radius / 2.0;
+            }
+            _ => {}
+        }
+        x += properties.physics.cx();
+        y += properties.physics.cy();
+        let a = rand::random::<f32>() * std::f32::consts::PI * 2.0;
+        let strategy = spawn.pick_strategy();
+        let piece = match crate::make_piece(spawn.unit) { Some(piece) => piece, None => return }; // the config loader checks these, so this shouldn't happen
+        let enemy = server.place(piece, x, y, a, None);
+        let en_ind = match server.obj_lookup(enemy) { Some(index) => index, None => return };
+        let mut t_v = properties.physics.vector_position();
+        if let Some(target) = target {
+            println!("Attacking {}", target);
+            for obj in &server.objects {
+                if obj.get_banner() == target && obj.identify() == 'c' { // it only attacks stationary castles; rtfs are too mobile
+                    t_v = obj.exposed_properties.physics.vector_position();
                 }
-                let (t_v, angle) = strategy.calculate(t_v, Vector2::new(x, y));
-                server.objects[en_ind].exposed_properties.goal_x = t_v.x;
-                server.objects[en_ind].exposed_properties.goal_y = t_v.y;
-                server.objects[en_ind].exposed_properties.goal_a = angle;
-                server.objects[en_ind].exposed_properties.collision_info.worthit = false;
-                server.objects[en_ind].exposed_properties.physics.velocity = Vector2::new_from_manda(20.0, rand::random::<f32>() * std::f32::consts::PI * 2.0);
-                server.objects[en_ind].death_subscribe(properties.id);
             }
         }
+        let (t_v, angle) = strategy.calculate(t_v, Vector2::new(x, y));
+        server.objects[en_ind].exposed_properties.goal_x = t_v.x;
+        server.objects[en_ind].exposed_properties.goal_y = t_v.y;
+        server.objects[en_ind].exposed_properties.goal_a = angle;
+        server.objects[en_ind].exposed_properties.collision_info.worthit = false;
+        server.objects[en_ind].exposed_properties.physics.velocity = Vector2::new_from_manda(20.0, rand::random::<f32>() * std::f32::consts::PI * 2.0);
+        server.objects[en_ind].death_subscribe(properties.id);
+        self.alive.push(enemy);
     }
 }
 
@@ -0,0 +1,348 @@
+// Map objects that come from the "ext" list in config files: spawners, healing zones, teleporters, capture points, hazards and flags
+use super::GamePiece;
+use super::GamePieceBase;
+use crate::Server;
+use crate::physics::PhysicsObject;
+use crate::vector::Vector2;
+use crate::ExposedProperties;
+use crate::ledger::TransactionReason;
+use crate::ServerToClient;
+
+
+pub struct Spawner {
+    kind      : char, // 'P' for npc spawners, 'L' for loot spawners; they work the same, they just look different
+    unit      : char,
+    interval  : u32,
+    count     : u16,
+    max_alive : usize,
+    radius    : f32,
+    timer     : u32,
+    alive     : Vec<u32>
+}
+
+pub struct HealZone {
+    radius : f32,
+    rate   : f32
+}
+
+pub struct Teleporter {
+    radius  : f32,
+    partner : Vector2 // where the other pad is
+}
+
+pub struct CapturePoint {
+    radius        : f32,
+    capture_ticks : u16, // how long one banner has to hold it uncontested to take it
+    income        : i32,
+    interval      : u32,
+    timer         : u32,
+    pub owner     : Option<usize>, // a side: a team banner, or a player banner for loners
+    pub progress  : (usize, u16) // which side is capturing it, and how far along they are
+}
+
+pub struct HazardField {
+    size   : f32,
+    damage : f32
+}
+
+pub struct Flag {} // the server's ctf bookkeeping does all the work; this just has to exist and be carried around
+
+
+impl Spawner {
+    pub fn new(kind : char, unit : char, interval : u32, count : u16, max_alive : usize, radius : f32) -> Self {
+        Self {
+            kind,
+            unit,
+            interval,
+            count,
+            max_alive,
+            radius,
+            timer : interval,
+            alive : vec![]
+        }
+    }
+}
+
+impl HealZone {
+    pub fn new(radius : f32, rate : f32) -> Self {
+        Self {
+            radius,
+            rate
+        }
+    }
+}
+
+impl Teleporter {
+    pub fn new(radius : f32, partner : Vector2) -> Self {
+        Self {
+            radius,
+            partner
+        }
+    }
+}
+
+impl CapturePoint {
+    pub fn new(radius : f32, capture_ticks : u16, income : i32, interval : u32) -> Self {
+        Self {
+            radius,
+            capture_ticks,
+            income,
+            interval,
+            timer : interval,
+            owner : None,
+            progress : (0, 0)
+        }
+    }
+
+    fn occupants(&self, properties : &ExposedProperties, server : &Server) -> Vec<usize> { // every side with a piece inside
+        let mut ret = vec![];
+        for obj in &server.objects {
+            if obj.get_banner() == 0 || obj.identify() == 'b' || obj.exposed_properties.carrier_properties.is_carried {
+                continue;
+            }
+            let side = server.side_of(obj.get_banner());
+            if !ret.contains(&side) && within(properties, obj, self.radius) {
+                ret.push(side);
+            }
+        }
+        ret
+    }
+
+    fn send_progress(&self, properties : &ExposedProperties, server : &Server) {
+        server.broadcast(ServerToClient::CaptureProgress (properties.id, self.progress.0 as u32, self.progress.1 as f32 / self.capture_ticks as f32));
+    }
+}
+
+impl HazardField {
+    pub fn new(size : f32, damage : f32) -> Self {
+        Self {
+            size,
+            damage
+        }
+    }
+}
+
+impl Flag {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+
+fn within(properties : &ExposedProperties, obj : &GamePieceBase, radius : f32) -> bool {
+    (obj.exposed_properties.physics.vector_position() - properties.physics.vector_position()).magnitude() < radius
+}
+
+
+impl GamePiece for Spawner {
+    fn construct<'a>(&'a self, thing : &mut ExposedProperties) {
+        thing.physics.fixed = true;
+        thing.collision_info.damage = 0.0;
+        thing.collision_info.worthit = false;
+    }
+
+    fn identify(&self) -> char {
+        self.kind
+    }
+
+    fn obtain_physics(&self) -> PhysicsObject {
+        PhysicsObject::new(0.0, 0.0, 40.0, 40.0, 0.0)
+    }
+
+    fn get_does_collide(&self, _id : char) -> bool {
+        false
+    }
+
+    fn update(&mut self, properties : &mut ExposedProperties, server : &mut Server) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.interval;
+        for _ in 0..self.count {
+            if self.max_alive > 0 && self.alive.len() >= self.max_alive {
+                break;
+            }
+            let piece = match crate::make_piece(self.unit) { Some(piece) => piece, None => return }; // the config loader checks these, so this shouldn't happen
+            let offset = Vector2::new_from_manda(rand::random::<f32>() * self.radius, rand::random::<f32>() * std::f32::consts::PI * 2.0);
+            let position = properties.physics.vector_position() + offset;
+            let id = server.place(piece, position.x, position.y, offset.angle(), None);
+            if let Some(index) = server.obj_lookup(id) {
+                server.objects[index].exposed_properties.goal_x = position.x; // stay put until something else gives orders
+                server.objects[index].exposed_properties.goal_y = position.y;
+                server.objects[index].exposed_properties.goal_a = offset.angle();
+                server.objects[index].death_subscribe(properties.id);
+                self.alive.push(id);
+            }
+        }
+    }
+
+    fn on_subscribed_death(&mut self, _me : &mut ExposedProperties, them : &mut GamePieceBase, _server : &mut Server) {
+        self.alive.retain(|id| *id != them.get_id());
+    }
+}
+
+impl GamePiece for HealZone {
+    fn construct<'a>(&'a self, thing : &mut ExposedProperties) {
+        thing.physics.fixed = true;
+        thing.collision_info.damage = 0.0;
+        thing.collision_info.worthit = false;
+    }
+
+    fn identify(&self) -> char {
+        'Z'
+    }
+
+    fn obtain_physics(&self) -> PhysicsObject {
+        PhysicsObject::new(0.0, 0.0, self.radius * 2.0, self.radius * 2.0, 0.0)
+    }
+
+    fn get_does_collide(&self, _id : char) -> bool {
+        false
+    }
+
+    fn update(&mut self, properties : &mut ExposedProperties, server : &mut Server) {
+        for obj in &mut server.objects {
+            if obj.get_banner() != 0 && within(properties, obj, self.radius) { // only player stuff gets fixed up
+                let health = &mut obj.exposed_properties.health_properties;
+                health.health = if health.health + self.rate > health.max_health { health.max_health } else { health.health + self.rate };
+            }
+        }
+    }
+}
+
+impl GamePiece for Teleporter {
+    fn construct<'a>(&'a self, thing : &mut ExposedProperties) {
+        thing.physics.fixed = true;
+        thing.collision_info.damage = 0.0;
+        thing.collision_info.worthit = false;
+    }
+
+    fn identify(&self) -> char {
+        'X'
+    }
+
+    fn obtain_physics(&self) -> PhysicsObject {
+        PhysicsObject::new(0.0, 0.0, self.radius * 2.0, self.radius * 2.0, 0.0)
+    }
+
+    fn get_does_collide(&self, _id : char) -> bool {
+        false
+    }
+
+    fn update(&mut self, properties : &mut ExposedProperties, server : &mut Server) {
+        for obj in &mut server.objects {
+            if obj.exposed_properties.physics.fixed || obj.exposed_properties.carrier_properties.is_carried || !within(properties, obj, self.radius) {
+                continue;
+            }
+            // come out the far side of the other pad, going the same way, so we don't land right back on a pad
+            let heading = if obj.exposed_properties.physics.velocity.is_zero() { obj.exposed_properties.physics.angle() } else { obj.exposed_properties.physics.velocity.angle() };
+            let exit = self.partner + Vector2::new_from_manda(self.radius + 50.0, heading);
+            obj.exposed_properties.physics.set_cx(exit.x);
+            obj.exposed_properties.physics.set_cy(exit.y);
+        }
+    }
+}
+
+impl GamePiece for CapturePoint {
+    fn construct<'a>(&'a self, thing : &mut ExposedProperties) {
+        thing.physics.fixed = true;
+        thing.collision_info.damage = 0.0;
+        thing.collision_info.worthit = false;
+    }
+
+    fn identify(&self) -> char {
+        'Q'
+    }
+
+    fn obtain_physics(&self) -> PhysicsObject {
+        PhysicsObject::new(0.0, 0.0, self.radius * 2.0, self.radius * 2.0, 0.0)
+    }
+
+    fn get_does_collide(&self, _id : char) -> bool {
+        false
+    }
+
+    fn update(&mut self, properties : &mut ExposedProperties, server : &mut Server) {
+        let occupants = self.occupants(properties, server);
+        let old_progress = self.progress;
+        if occupants.len() == 1 && Some(occupants[0]) != self.owner { // one side alone on the point, and it isn't theirs yet
+            if self.progress.0 == occupants[0] {
+                self.progress.1 += 1;
+                if self.progress.1 >= self.capture_ticks {
+                    self.owner = Some(occupants[0]);
+                    self.progress = (0, 0);
+                    self.timer = self.interval;
+                    server.broadcast(ServerToClient::PointOwner (properties.id, occupants[0] as u32));
+                }
+            }
+            else if self.progress.1 > 0 {
+                self.progress.1 -= 1; // they have to wind down the last guy's progress first
+            }
+            else {
+                self.progress = (occupants[0], 1);
+            }
+        }
+        else if occupants.len() == 0 && self.progress.1 > 0 {
+            self.progress.1 -= 1;
+        }
+        if self.progress != old_progress && (self.progress.1 % 10 == 0 || self.progress.0 != old_progress.0) { // every third of a second is plenty for a progress bar
+            self.send_progress(properties, server);
+        }
+        if let Some(owner) = self.owner {
+            server.hold_point(owner);
+            if self.timer > 0 {
+                self.timer -= 1;
+            }
+            else {
+                self.timer = self.interval;
+                if self.income != 0 {
+                    for banner in server.members_of(owner) {
+                        server.score_to(banner, self.income, TransactionReason::Objective);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl GamePiece for HazardField {
+    fn construct<'a>(&'a self, thing : &mut ExposedProperties) {
+        thing.physics.fixed = true;
+        thing.collision_info.damage = self.damage; // the collision code does the rest, just like radiation
+        thing.collision_info.worthit = false;
+    }
+
+    fn identify(&self) -> char {
+        'z'
+    }
+
+    fn obtain_physics(&self) -> PhysicsObject {
+        PhysicsObject::new(0.0, 0.0, self.size, self.size, 0.0)
+    }
+
+    fn get_does_collide(&self, _id : char) -> bool {
+        false
+    }
+}
+
+
+impl GamePiece for Flag {
+    fn construct<'a>(&'a self, thing : &mut ExposedProperties) {
+        thing.physics.fixed = true;
+        thing.collision_info.damage = 0.0;
+        thing.collision_info.worthit = false;
+    }
+
+    fn identify(&self) -> char {
+        'Y'
+    }
+
+    fn obtain_physics(&self) -> PhysicsObject {
+        PhysicsObject::new(0.0, 0.0, 40.0, 40.0, 0.0)
+    }
+
+    fn get_does_collide(&self, _id : char) -> bool {
+        false // can't be shot down, only picked up
+    }
+}
\ No newline at end of file
@@ -0,0 +1,147 @@
+// The archive: one record per match (map, when, who played as what, final scores) and one per kill, so balance work has more to go on than the console
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::ClientMode;
+use crate::matchmaking::mode_name;
+
+
+#[derive(Clone, Debug)]
+pub struct KillEvent {
+    pub time        : f32, // seconds into the match
+    pub killer      : usize, // banner; 0 if nobody in particular
+    pub victim      : usize,
+    pub killer_type : char, // '?' when it didn't die to anything in particular
+    pub victim_type : char,
+    pub x           : f32,
+    pub y           : f32,
+    pub castle      : bool,
+    pub victim_id   : u32 // not saved; just so castles don't get logged twice
+}
+
+
+pub struct Participant {
+    pub banner : String,
+    pub team   : String, // empty if they weren't on one
+    pub mode   : ClientMode,
+    pub score  : i32
+}
+
+
+#[derive(Clone)]
+pub struct MatchHistory {
+    started : Option<SystemTime>, // None until the game starts
+    modes   : HashMap<usize, (ClientMode, SystemTime)>, // everyone who placed a castle, how they're playing, and when they joined
+    events  : Vec<KillEvent>
+}
+
+
+fn unix(time : SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0) as i64
+}
+
+
+impl MatchHistory {
+    pub fn new() -> Self {
+        Self {
+            started : None,
+            modes   : HashMap::new(),
+            events  : vec![]
+        }
+    }
+
+    pub fn begin(&mut self) {
+        self.started = Some(SystemTime::now());
+    }
+
+    pub fn elapsed(&self) -> f32 {
+        match self.started {
+            Some(started) => started.elapsed().map(|time| time.as_secs_f32()).unwrap_or(0.0),
+            None => 0.0
+        }
+    }
+
+    pub fn join(&mut self, banner : usize, mode : ClientMode) {
+        self.modes.insert(banner, (mode, SystemTime::now()));
+    }
+
+    pub fn mode_of(&self, banner : usize) -> Option<ClientMode> {
+        self.modes.get(&banner).map(|joined| joined.0)
+    }
+
+    pub fn participants(&self) -> Vec<usize> {
+        let mut ret : Vec<usize> = self.modes.keys().copied().collect();
+        ret.sort();
+        ret
+    }
+
+    pub fn kill(&mut self, event : KillEvent) {
+        self.events.push(event);
+    }
+
+    pub fn has_death(&self, id : u32) -> bool {
+        self.events.iter().any(|event| event.victim_id == id)
+    }
+
+    pub fn events(&self) -> &Vec<KillEvent> {
+        &self.events
+    }
+
+    pub fn take_session(&mut self, banner : usize) -> Self { // an io-mode life: moves this player, and every kill they were in, out into a history of their own, so io games don't pile up events forever
+        let (events, rest) : (Vec<KillEvent>, Vec<KillEvent>) = self.events.drain(..).partition(|event| event.killer == banner || event.victim == banner);
+        self.events = rest;
+        let mut modes = HashMap::new();
+        let mut started = self.started;
+        if let Some(joined) = self.modes.remove(&banner) {
+            started = Some(joined.1);
+            modes.insert(banner, joined);
+        }
+        Self {
+            started,
+            modes,
+            events
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.started = None;
+        self.modes.clear();
+        self.events.clear();
+    }
+
+    pub fn save(&self, connection : &sqlite::Connection, map : &str, winner : &str, participants : &Vec<Participant>, banners : &Vec<String>) -> sqlite::Result<i64> { // returns the new match's id. runs on the database thread, inside its transaction.
+        let ended = SystemTime::now();
+        let mut statement = connection.prepare("INSERT INTO matches (map, started, ended, winner) VALUES (?, ?, ?, ?)")?;
+        statement.bind((1, map))?;
+        statement.bind((2, unix(self.started.unwrap_or(ended))))?;
+        statement.bind((3, unix(ended)))?;
+        statement.bind((4, winner))?;
+        statement.next()?;
+        let mut statement = connection.prepare("SELECT last_insert_rowid()")?;
+        statement.next()?;
+        let id = statement.read::<i64, _>(0)?;
+        for participant in participants {
+            let mut statement = connection.prepare("INSERT INTO match_players VALUES (?, ?, ?, ?, ?)")?;
+            statement.bind((1, id))?;
+            statement.bind((2, participant.banner.as_str()))?;
+            statement.bind((3, participant.team.as_str()))?;
+            statement.bind((4, mode_name(participant.mode)))?;
+            statement.bind((5, participant.score as i64))?;
+            statement.next()?;
+        }
+        let name = |banner : usize| match banners.get(banner) { Some(name) => name.as_str(), None => "" };
+        for event in &self.events {
+            let mut statement = connection.prepare("INSERT INTO kill_events VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+            statement.bind((1, id))?;
+            statement.bind((2, event.time as f64))?;
+            statement.bind((3, name(event.killer)))?;
+            statement.bind((4, name(event.victim)))?;
+            statement.bind((5, event.killer_type.to_string().as_str()))?;
+            statement.bind((6, event.victim_type.to_string().as_str()))?;
+            statement.bind((7, event.x as f64))?;
+            statement.bind((8, event.y as f64))?;
+            statement.bind((9, event.castle as i64))?;
+            statement.next()?;
+        }
+        Ok(id)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,199 @@
+// Leaderboards. The persistent ones (all-time, this map, this season) come out of the leaderboard table, one row per player per game; the live one is just everybody's score right now.
+use crate::ServerToClient;
+use crate::database::{Database, Write};
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+pub const ALL_TIME : u8 = b'a';
+pub const MAP      : u8 = b'm';
+pub const SEASON   : u8 = b's';
+const REFRESH_TICKS : u32 = (crate::FPS * 10.0) as u32; // the persistent boards reload at most this often, however many games end
+
+type Boards = Vec<(u8, Vec<Entry>)>;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub banner : String,
+    pub score  : i64 // best single game
+}
+
+
+pub struct Leaderboards {
+    pub top           : usize, // how many places get sent
+    pub season_days   : u32,
+    pub live_interval : u32, // ticks between live board pushes
+    pub map           : String, // the config file's name, so every room on the same map shares a board
+    timer             : u32,
+    boards            : Boards, // the persistent boards as of the last refresh; they only change when somebody's game ends
+    stale             : bool, // a game's ended since the boards were loaded
+    refresh_timer     : u32,
+    loading           : Option<Receiver<Boards>> // a reload waiting on the database thread
+}
+
+
+impl Leaderboards {
+    pub fn new(map : String) -> Self {
+        Self {
+            top           : 10,
+            season_days   : 30,
+            live_interval : (crate::FPS * 5.0) as u32,
+            map,
+            timer         : 0,
+            boards        : vec![],
+            stale         : false,
+            refresh_timer : 0,
+            loading       : None
+        }
+    }
+
+    fn now() -> i64 {
+        std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0) as i64
+    }
+
+    pub fn season_of(&self, time : i64) -> i64 { // seasons are numbered from the epoch
+        time / (std::cmp::max(self.season_days, 1) as i64 * 86400)
+    }
+
+    pub fn record(&self, finished : i64, banner : String, score : i32) -> Write {
+        let (map, season) = (self.map.clone(), self.season_of(finished));
+        Box::new(move |connection : &sqlite::Connection| -> sqlite::Result<()> {
+            let mut statement = connection.prepare("INSERT INTO leaderboard VALUES (?, ?, ?, ?, ?)")?;
+            statement.bind((1, finished))?;
+            statement.bind((2, map.as_str()))?;
+            statement.bind((3, season))?;
+            statement.bind((4, banner.as_str()))?;
+            statement.bind((5, score as i64))?;
+            statement.next()?;
+            Ok(())
+        })
+    }
+
+    pub fn invalidate(&mut self) { // somebody's score went in; reload the boards when it's next allowed
+        self.stale = true;
+    }
+
+    pub fn poll(&mut self, database : &Database) -> bool { // every tick. starts a background reload if the boards are stale, and returns true when one has finished and the boards should be sent out
+        if let Some(loading) = &self.loading {
+            match loading.try_recv() {
+                Ok(boards) => {
+                    self.boards = boards;
+                    self.loading = None;
+                    return true;
+                },
+                Err(TryRecvError::Empty) => {
+                    return false;
+                },
+                Err(TryRecvError::Disconnected) => { // no database; the next invalidate tries again
+                    self.loading = None;
+                }
+            }
+        }
+        if self.refresh_timer > 0 {
+            self.refresh_timer -= 1;
+        }
+        if self.stale && self.refresh_timer == 0 {
+            self.stale = false;
+            self.refresh_timer = REFRESH_TICKS;
+            let (map, season, count) = (self.map.clone(), self.season_of(Self::now()), self.top);
+            self.loading = Some(database.read(move |connection| load(connection, &map, season, count))); // queued behind whatever scores made it stale, so they're in it
+        }
+        false
+    }
+
+    pub fn messages(&self) -> Vec<ServerToClient> { // every persistent board, ready to send
+        let mut ret = vec![];
+        for (kind, entries) in &self.boards {
+            for (rank, entry) in entries.iter().enumerate() {
+                ret.push(ServerToClient::Leaderboard (*kind, rank as u32 + 1, entry.banner.clone(), entry.score as i32));
+            }
+        }
+        ret
+    }
+
+    pub fn board(&self, kind : u8) -> Vec<Entry> {
+        match self.boards.iter().find(|board| board.0 == kind) {
+            Some(board) => board.1.clone(),
+            None => vec![]
+        }
+    }
+
+    pub fn live_tick(&mut self) -> bool { // true when it's time to send the live board again
+        self.timer += 1;
+        if self.timer >= self.live_interval {
+            self.timer = 0;
+            return true;
+        }
+        false
+    }
+}
+
+
+fn load(connection : &sqlite::Connection, map : &str, season : i64, count : usize) -> Boards { // whatever boards could be read; errors just get printed
+    let filters = [
+        (ALL_TIME, None),
+        (MAP, Some(("map", sqlite::Value::String(map.to_string())))),
+        (SEASON, Some(("season", sqlite::Value::Integer(season))))
+    ];
+    let mut boards = vec![];
+    for (kind, filter) in filters {
+        match top(connection, filter, count) {
+            Ok(entries) => {
+                boards.push((kind, entries));
+            },
+            Err(error) => {
+                println!("Couldn't load a leaderboard: {}", error);
+            }
+        }
+    }
+    boards
+}
+
+
+fn top(connection : &sqlite::Connection, filter : Option<(&str, sqlite::Value)>, count : usize) -> sqlite::Result<Vec<Entry>> {
+    let filter_clause = match &filter { Some((column, _)) => format!("WHERE {} = ?", column), None => "".to_string() };
+    let mut statement = connection.prepare(format!("SELECT banner, MAX(score) FROM leaderboard {} GROUP BY banner ORDER BY 2 DESC LIMIT {}", filter_clause, count))?;
+    if let Some((_, value)) = filter {
+        statement.bind((1, value))?;
+    }
+    let mut ret = vec![];
+    while let sqlite::State::Row = statement.next()? {
+        ret.push(Entry {
+            banner : statement.read::<String, _>(0)?,
+            score  : statement.read::<i64, _>(1)?
+        });
+    }
+    Ok(ret)
+}
+
+
+pub fn live(scores : Vec<(usize, i32)>, count : usize) -> Vec<(usize, i32)> { // banner, score; best first
+    let mut scores = scores;
+    scores.sort_by(|a, b| b.1.cmp(&a.1));
+    scores.truncate(count);
+    scores
+}
+
+
+pub fn read_leaderboard(path : &str) -> Vec<Entry> { // an exported board: one "banner score" per line. a missing file is an empty board.
+    let contents = match std::fs::read_to_string(path) { Ok(contents) => contents, Err(_) => return vec![] };
+    let mut ret = vec![];
+    for line in contents.lines() {
+        let (banner, score) = match line.rsplit_once(' ') { Some(parts) => parts, None => continue }; // banners can have spaces, scores can't
+        if let Ok(score) = score.parse::<i64>() {
+            ret.push(Entry {
+                banner : banner.to_string(),
+                score
+            });
+        }
+    }
+    ret
+}
+
+
+pub fn write_leaderboard(path : &str, entries : &Vec<Entry>) -> std::io::Result<()> {
+    let mut contents = String::new();
+    for entry in entries {
+        contents += &format!("{} {}\n", entry.banner, entry.score);
+    }
+    std::fs::write(path, contents)
+}
\ No newline at end of file
@@ -0,0 +1,120 @@
+// Server-authoritative economy. Every banner's balance lives here, and every credit or debit is logged with a reason.
+use std::collections::{HashMap, VecDeque};
+
+const LOG_LENGTH : usize = 1000; // how many transactions we keep around; running totals cover the rest
+
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TransactionReason {
+    Kill,      // shot something down
+    Chest,     // broke open a chest
+    Shop,      // bought an upgrade or an item from the shop
+    Placement, // paid for a newly placed piece
+    Refund,    // got money back for something that was taken away
+    Grant,     // starting money and gifts from God
+    Objective  // income from holding a capture point
+}
+
+
+#[derive(Clone, Debug)]
+pub struct Transaction {
+    pub banner  : usize,
+    pub amount  : i32, // positive is a credit, negative is a debit
+    pub reason  : TransactionReason,
+    pub balance : i32  // the balance right after this transaction went through
+}
+
+
+pub struct Ledger {
+    balances : HashMap<usize, i32>,
+    earnings : HashMap<usize, i32>, // running totals for earned()
+    paid     : HashMap<u32, (usize, i32)>, // object id -> who paid for it and how much, so refunds give back exactly that
+    log      : VecDeque<Transaction> // just the most recent ones
+}
+
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self {
+            balances : HashMap::new(),
+            earnings : HashMap::new(),
+            paid     : HashMap::new(),
+            log      : VecDeque::new()
+        }
+    }
+
+    pub fn balance(&self, banner : usize) -> i32 {
+        match self.balances.get(&banner) {
+            Some(balance) => *balance,
+            None => 0
+        }
+    }
+
+    pub fn credit(&mut self, banner : usize, amount : i32, reason : TransactionReason) -> i32 { // returns the new balance
+        let balance = self.balances.entry(banner).or_insert(0);
+        *balance += amount;
+        let balance = *balance;
+        if amount > 0 && match reason {
+            TransactionReason::Kill | TransactionReason::Chest | TransactionReason::Objective => true,
+            _ => false
+        } {
+            *self.earnings.entry(banner).or_insert(0) += amount;
+        }
+        if self.log.len() >= LOG_LENGTH {
+            self.log.pop_front();
+        }
+        self.log.push_back(Transaction {
+            banner,
+            amount,
+            reason,
+            balance
+        });
+        balance
+    }
+
+    pub fn charge(&mut self, banner : usize, amount : i32, reason : TransactionReason) -> Option<i32> { // check and charge in one step: returns the new balance, or None (and changes nothing) if the banner can't afford it
+        if self.balance(banner) < amount {
+            return None;
+        }
+        Some(self.credit(banner, -amount, reason))
+    }
+
+    pub fn log(&self) -> &VecDeque<Transaction> {
+        &self.log
+    }
+
+    pub fn history(&self, banner : usize) -> Vec<&Transaction> {
+        self.log.iter().filter(|transaction| transaction.banner == banner).collect()
+    }
+
+    pub fn earned(&self, banner : usize) -> i32 { // money made from fighting and objectives, not counting grants, refunds or spending
+        match self.earnings.get(&banner) {
+            Some(earned) => *earned,
+            None => 0
+        }
+    }
+
+    pub fn paid_for(&mut self, id : u32, banner : usize, amount : i32) { // remember that this object was bought, for refunds
+        if amount > 0 {
+            self.paid.insert(id, (banner, amount));
+        }
+    }
+
+    pub fn paid(&self, id : u32) -> i32 {
+        match self.paid.get(&id) {
+            Some((_, amount)) => *amount,
+            None => 0
+        }
+    }
+
+    pub fn forget(&mut self, id : u32) -> Option<(usize, i32)> { // the object's gone; returns who paid for it and how much, if anybody did
+        self.paid.remove(&id)
+    }
+
+    pub fn clear(&mut self) {
+        self.balances.clear();
+        self.earnings.clear();
+        self.paid.clear();
+        self.log.clear();
+    }
+}
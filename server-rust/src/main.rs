@@ -8,11 +8,28 @@ pub mod physics;
 pub mod gamepiece;
 pub mod config;
 pub mod functions;
+pub mod ledger;
+pub mod pathfinding;
+pub mod formation;
+pub mod ext;
+pub mod capture;
+pub mod ctf;
+pub mod matchclock;
+pub mod stats;
+pub mod overtime;
+pub mod rooms;
+pub mod matchmaking;
+pub mod accounts;
+pub mod leaderboard;
+pub mod rating;
+pub mod history;
+pub mod database;
 use crate::vector::Vector2;
 use std::vec::Vec;
 use std::sync::Arc;
+use std::collections::HashMap;
 use crate::gamepiece::*;
-use crate::nexus::Nexus;
+use crate::nexus::{Nexus, SpawnTable};
 //use crate::nexus::NexusEnemy;
 use std::f32::consts::PI;
 use rand::Rng;
@@ -21,6 +38,19 @@ use crate::gamepiece::misc::*;
 use crate::gamepiece::npc;
 use crate::physics::BoxShape;
 use crate::config::Config;
+use crate::ledger::{Ledger, TransactionReason};
+use crate::pathfinding::NavGrid;
+use crate::formation::Formation;
+use crate::capture::CaptureMode;
+use crate::stats::{Outcome, MatchStats};
+use crate::overtime::{Overtime, OvertimeRules, SuddenDeath, MAX_NPCS};
+use crate::ctf::{CtfMode, FlagState, CAPTURE_RADIUS, RETURN_TICKS};
+use crate::matchclock::{MatchClock, Standing, most_points};
+use crate::rooms::{RoomManager, Matched};
+use crate::accounts::LoginResult;
+use crate::leaderboard::Leaderboards;
+use crate::history::{MatchHistory, KillEvent, Participant};
+use crate::database::{Database, Write};
 use futures::future::FutureExt; // for `.fuse()`
 use tokio::select;
 use crate::gamepiece::BulletType;
@@ -29,6 +59,10 @@ use protocol_v3::protocol::ProtocolFrame;
 use protocol_v3::protocol_v3_macro::ProtocolFrame;
 
 const FPS : f32 = 30.0;
+const DOCK_SPEED : f32 = 1.0; // carriers have to be going slower than this to count as parked
+const DOCK_REPAIR : f32 = 2.0; // health per tick a docked carrier gets back
+const RESUPPLY_TICKS : u32 = 30; // a docked carrier loads one item off its purchase queue this often
+const MAX_SELECTION : usize = 256; // a client can't select more pieces than this at once
 
 
 #[derive(PartialEq, Copy, Clone, Debug)]
@@ -44,7 +78,6 @@ pub struct Client {
     is_superuser      : bool,
     socket            : WebSocketClientStream,
     is_authorized     : bool,
-    score             : i32,
     has_placed        : bool,
     banner            : usize,
     m_castle          : Option<u32>,
@@ -57,7 +90,8 @@ pub struct Client {
     walls_remaining   : u16,
     walls_cap         : u16,
     game_cmode        : GameMode,
-    is_ready          : bool
+    is_ready          : bool,
+    selection         : Vec<u32> // ids the player has selected for group moves
 }
 
 
@@ -91,7 +125,38 @@ pub enum ServerToClient {
     UnCarry (u32), // no longer carrying this guy
     YouAreGod, // you are God
     Leprechaun, // we enable the leppy kaun
-    CastLaser (f32, f32, f32, f32, f32) // x, y, x2, y2, intensity
+    CastLaser (f32, f32, f32, f32, f32), // x, y, x2, y2, intensity
+    OrderQueued (u32, f32, f32, f32, bool), // id, x, y, a, attack-move. lets the owner draw the route.
+    OrdersCleared (u32),
+    StanceChanged (u32, u8), // id, stance ('f'ire at will, 'h'old fire, 'r'eturn fire, 'a'ggressive, 'e'vasive)
+    FocusTarget (u32), // the id your team is focusing fire on; 0 means none
+    BerthAssigned (u32, u8), // id, berth
+    Recalling (u32, u32), // id, carrier it's flying back to
+    PlacementRejected (String), // why something you tried to place didn't get placed
+    Docked (u32, bool), // carrier id, whether it's docked at a friendly castle or fort
+    CargoQueue (u32, String), // carrier id, type codes still waiting to be bought and loaded
+    CaptureProgress (u32, u32, f32), // capture point id, side capturing it (0 if nobody), how far along they are from 0 to 1
+    PointOwner (u32, u32), // capture point id, side that owns it now (0 if nobody)
+    CaptureScore (u32, u32), // side, their score in a capture-point game
+    FlagTaken (u32, u32), // flag id, side that picked it up
+    FlagReturned (u32), // flag id; it's back at its base
+    FlagCaptured (u32, u32), // side that brought a flag home, how many they've brought home now
+    MatchClock (u32), // seconds left in a timed match
+    Summary (u32, u32, u32, i32, u32, f32), // banner, kills, losses, score, units built, damage dealt. sent for every player when the game ends.
+    NewGame, // the post-game is over and everything's been cleared; you're back in the lobby
+    SuddenDeath (u8), // the timed match was tied, so now it's overtime: 's'hrinking zone or 'n'pc waves
+    SafeZone (f32, f32, f32), // x, y, radius of the circle that's safe during shrinking sudden death
+    RoomInfo (String, u32), // room name, how many clients are in it. one per room, in answer to ListRooms.
+    NoSuchRoom (String), // the room you asked for in ConnectRoom doesn't exist (any more)
+    Queued (u32), // you're in the matchmaking queue, at this place in line
+    Unqueued, // you're out of the queue: you left, or matchmaking is off, or your match couldn't get a room
+    Matched (String), // matchmaking found you a game in this room; you're being connected to it now
+    LoggedIn (String), // the banner you're logged in (or just registered) as
+    AccountError (String), // why a Register or Login didn't work
+    BannerReserved (String), // that banner belongs to a registered player; log in as them or pick another
+    Leaderboard (u8, u32, String, i32), // board ('a'll-time, 'm'ap, 's'eason), place (1 starts the list over), banner, best score. sent on joining and whenever a game ends.
+    LiveStanding (u32, u32, i32), // place (1 starts the list over), banner id, score right now. sent every few seconds during play.
+    Rating (String, bool, f32, u32) // player or team name, whether it's a team, elo rating, games played. answers GetRating, and everyone gets their own after a game.
 }
 
 #[derive(ProtocolFrame, Debug, Clone)]
@@ -113,7 +178,32 @@ pub enum ClientToServer {
     GodDisconnect (u32),
     GodNuke (u32),
     GodFlip,
-    GodBless (u32)
+    GodBless (u32),
+    QueueMove (u32, f32, f32, f32, bool), // id, x, y, a, attack-move
+    SetPatrol (u32, bool), // id, whether the queued route loops
+    ClearOrders (u32),
+    Select (u32), // add an id to the group selection
+    Deselect (u32),
+    ClearSelection,
+    GroupMove (f32, f32, f32, u8), // x, y, a, formation ('l'ine, 'w'edge, 'b'ox, 's'pread). moves everything selected.
+    SetStance (u32, u8), // id, stance ('f'ire at will, 'h'old fire, 'r'eturn fire, 'a'ggressive, 'e'vasive)
+    SetTargeting (u32, u8, String, u32, f32), // id, mode ('n'earest, 'l'owest health, highest 'c'apture, last 'a'ttacker, 'f'ocus fire), type codes to shoot at (empty for anything), banner to shoot at (0 for anyone), minimum threat. these only narrow down what the piece shoots at anyways; with none of them, it goes back to that.
+    FocusTarget (u32), // designate an id for your whole team to focus fire on; 0 clears it
+    RequireSight (u32, bool), // id, whether it should ignore targets hidden behind map blocks
+    Launch (u32), // id of a carried piece to send out
+    LaunchAll (u32), // carrier id; empties it
+    Recall (u32, u32), // id, carrier to dock on
+    AssignBerth (u32, u8), // id of a carried piece, berth to move it to
+    PlaceCarrier (f32, f32, String), // x, y, loadout: pairs of berth digit and type code, like "0T1T4h5h"
+    QueueCargo (u32, u8), // carrier id, type code to buy and load next time it docks
+    SwapLoadout (u32, String), // carrier id, new loadout in the same format as PlaceCarrier. strategy phase only, and the carrier has to be docked.
+    ListRooms, // only before connecting
+    ConnectRoom (String, String, String, String), // password, banner, mode, room name. like Connect, but into a specific room.
+    Queue (String, String), // banner, comma-separated modes you'd play ("normal,rtf"). only before connecting; you'll get Matched and be connected when there's a game.
+    LeaveQueue,
+    Register (String, String), // banner, password. only before connecting; it logs you in, too.
+    Login (String, String), // banner, password. only before connecting. guests don't have to bother.
+    GetRating (String, bool) // player or team name, whether it's a team. works before and after connecting.
 }
 
 
@@ -122,6 +212,7 @@ enum GameMode {
     Waiting,  // The game hasn't started yet: you can join at this point. Countdown may exist.
     Strategy, // Strategy change
     Play,     // Ships are moving
+    Over      // Somebody won: the world is frozen while everyone looks at the summary, then it's back to Waiting
 }
 
 struct TeamData {
@@ -137,17 +228,17 @@ enum ClientCommand { // Commands sent to clients
     Send (ServerToClient),
     SendTo (ServerToClient, usize),
     Tick (u32, GameMode),
-    ScoreTo (usize, i32),
     CloseAll,
     ChatRoom (String, usize, u8, Option<usize>), // message, sender, priority
     GrantA2A (usize),
-    AttachToBanner (u32, usize, i32),
+    ShopGrant (usize, u8), // banner, shop item that was paid for. the server has already charged for it.
+    AttachToBanner (u32, usize),
     SetCastle (usize, u32), // banner to set, id of the castle
     HealthStream (u32, f32), // id to stream, health value
     RoleCall, // the client will immediately report its banner in the WinningBanner message.
     SomeoneDied (usize), // banner
-    Christmas,
-    Close (usize)
+    Close (usize),
+    NewGame // the world's been cleared for another round; forget everything about the last one
 }
 
 
@@ -174,15 +265,31 @@ pub struct Server {
     living_players    : u32,
     isnt_rtf          : u32,
     times             : (f32, f32),
+    postgame          : f32, // seconds between the end of a game and going back to Waiting
     clients_connected : u32,
     is_headless       : bool,
     permit_npcs       : bool,
     port              : u16,
-    sql               : String,
+    sql               : String, // what the config asked for; the room might've been given a shared one instead
+    database          : Database,
     worldzone_count   : usize,
     zones             : Vec<Vec<usize>>,
     vvlm              : bool,
-    readies           : u32
+    readies           : u32,
+    ledger            : Ledger,
+    navgrid           : NavGrid,
+    focus_targets     : HashMap<usize, u32>, // team banner (or player banner, for loners) -> the id everybody on it should be shooting at
+    capture           : Option<CaptureMode>, // set if this is a capture-point game
+    ctf               : Option<CtfMode>, // set if this is a capture-the-flag game
+    clock             : Option<MatchClock>, // set if matches have a time limit
+    stats             : MatchStats,
+    allegiance        : HashMap<usize, usize>, // player banner -> team banner, for everyone who's played on a team this game, dead or alive
+    sudden_death      : Option<OvertimeRules>, // what to do if a timed match ends in a tie; None means it just ends in a tie
+    overtime          : Option<Overtime>,
+    eliminated        : bool, // somebody's castle went down this tick, so check for a winner (or a tie) once the tick's done
+    leaderboards      : Leaderboards,
+    eliminations      : Vec<usize>, // player banners in the order they went down this game, for placing everyone in the ratings
+    history           : MatchHistory
 }
 
 #[derive(Debug)]
@@ -262,42 +369,261 @@ impl Server {
         return true;
     }
 
+    fn zone_allows(&self, zone : ReqZone, x : f32, y : f32, banner : usize) -> bool {
+        match zone {
+            ReqZone::NoZone => true,
+            ReqZone::WithinCastle => {
+                self.is_inside_friendly(x, y, banner, 'c', 1600.0) || self.is_inside_friendly(x, y, banner, 'R', 800.0)
+            },
+            ReqZone::WithinCastleOrFort => {
+                self.is_inside_friendly(x, y, banner, 'c', 1600.0) || self.is_inside_friendly(x, y, banner, 'F', 800.0) || self.is_inside_friendly(x, y, banner, 'R', 800.0)
+            },
+            ReqZone::AwayFromThings => {
+                self.is_clear(x, y)
+            },
+            ReqZone::Both => {
+                self.is_clear(x, y) || self.is_inside_friendly(x, y, banner, 'c', 1600.0) || self.is_inside_friendly(x, y, banner, 'F', 800.0)
+            }
+        }
+    }
+
     fn place(&mut self, piece : Box<dyn GamePiece + Send + Sync>, x : f32, y : f32, a : f32, banner : Option<usize>) -> u32 { // return the id of the object released
         let zone = piece.req_zone();
         let la_thang = GamePieceBase::new(piece, x, y, a);
         if banner.is_some() {
             let banner = banner.unwrap();
-            if !match zone {
-                ReqZone::NoZone => true,
-                ReqZone::WithinCastle => {
-                    self.is_inside_friendly(x, y, banner, 'c', 1600.0) || self.is_inside_friendly(x, y, banner, 'R', 800.0)
-                },
-                ReqZone::WithinCastleOrFort => {
-                    self.is_inside_friendly(x, y, banner, 'c', 1600.0) || self.is_inside_friendly(x, y, banner, 'F', 800.0) || self.is_inside_friendly(x, y, banner, 'R', 800.0)
-                },
-                ReqZone::AwayFromThings => {
-                    self.is_clear(x, y)
-                },
-                ReqZone::Both => {
-                    self.is_clear(x, y) || self.is_inside_friendly(x, y, banner, 'c', 1600.0) || self.is_inside_friendly(x, y, banner, 'F', 800.0)
-                }
-            } {
+            if !self.zone_allows(zone, x, y, banner) {
                 //sender.as_mut().unwrap().kys = true; // drop the client, something nefarious is going on
                 return 0; // refuse to place, returning nothing.
             }
-            /*if self.costs {
-                let cost = la_thang.cost() as i32;
-                if cost > sender.as_ref().unwrap().score {
-                    return 0;
+            let cost = la_thang.cost();
+            if self.costs {
+                if cost > 0 && !self.charge(banner, cost, TransactionReason::Placement) {
+                    return 0; // can't afford it, so it never gets placed
                 }
-                self.broadcast_tx.send(ClientCommand::ScoreTo (banner, -cost)).expect("BROADCAST FAILED");
-            }*/
+            }
+            if cost > 0 {
+                self.stats.built(banner); // only things you'd pay for; free stuff like bullets doesn't count
+            }
+            let id = self.add(la_thang, Some(banner));
+            if self.costs {
+                self.ledger.paid_for(id, banner, cost);
+            }
+            return id;
         }
         self.add(la_thang, banner)
     }
 
-    fn score_to(&self, banner : usize, amount : i32) {
-        self.broadcast_tx.send(ClientCommand::ScoreTo(banner, amount)).expect("BROADCAST FAILLEDDDDDD");
+    fn place_carrier_loadout(&mut self, x : f32, y : f32, banner : Option<usize>, loadout : &str) -> Result<u32, String> { // places a carrier and everything in it, paying for the whole lot at once
+        let loadout = Carrier::parse_loadout(loadout)?;
+        let carrier = Carrier::new();
+        let mut total = carrier.cost();
+        let mut cargo = vec![];
+        for (berth, tp) in loadout {
+            let piece = match make_piece(tp) { Some(piece) => piece, None => return Err(format!("Can't build '{}'", tp)) };
+            total += piece.cost();
+            cargo.push((berth, piece));
+        }
+        if let Some(banner) = banner {
+            if !self.zone_allows(carrier.req_zone(), x, y, banner) {
+                return Err("You can't place a carrier there".to_string());
+            }
+            if self.costs && total > 0 && !self.charge(banner, total, TransactionReason::Placement) {
+                return Err(format!("That carrier costs {} coins and you have {}", total, self.ledger.balance(banner)));
+            }
+        }
+        if let Some(banner) = banner {
+            for _ in 0..(cargo.len() + 1) {
+                self.stats.built(banner);
+            }
+        }
+        let paying = if self.costs { banner } else { None };
+        let carrier_cost = carrier.cost();
+        let carrier_id = self.add(GamePieceBase::new(Box::new(carrier), x, y, 0.0), banner);
+        if let Some(banner) = paying {
+            self.ledger.paid_for(carrier_id, banner, carrier_cost);
+        }
+        let carrier = self.obj_lookup(carrier_id).unwrap(); // we just added it, so it has to be there
+        for (berth, piece) in cargo {
+            let cost = piece.cost();
+            let id = self.add(GamePieceBase::new(piece, x, y, 0.0), banner); // cargo goes straight into the carrier, so it doesn't care where it's "placed"
+            if let Some(banner) = paying {
+                self.ledger.paid_for(id, banner, cost);
+            }
+            self.into_berth(carrier, id, berth);
+        }
+        Ok(carrier_id)
+    }
+
+    fn is_docked(&self, carrier : usize) -> bool { // same radii as placement
+        let props = &self.objects[carrier].exposed_properties;
+        let (x, y) = (props.physics.cx(), props.physics.cy());
+        let banner = self.objects[carrier].get_banner();
+        props.physics.velocity.magnitude() < DOCK_SPEED && (self.is_inside_friendly(x, y, banner, 'c', 1600.0) || self.is_inside_friendly(x, y, banner, 'F', 800.0))
+    }
+
+    fn free_berth(&self, carrier : usize) -> Option<usize> {
+        let props = &self.objects[carrier].exposed_properties.carrier_properties;
+        let mut taken = vec![];
+        for id in &props.carrying {
+            if let Some(index) = self.obj_lookup(*id) {
+                taken.push(self.objects[index].exposed_properties.carrier_properties.berth);
+            }
+        }
+        (0..(props.carrying.len() + props.space_remaining as usize)).find(|berth| !taken.contains(berth))
+    }
+
+    fn dock_carriers(&mut self) {
+        for i in 0..self.objects.len() {
+            if self.objects[i].identify() != 'K' || self.objects[i].exposed_properties.carrier_properties.is_carried {
+                continue;
+            }
+            let docked = self.is_docked(i);
+            if docked != self.objects[i].exposed_properties.carrier_properties.docked {
+                self.objects[i].exposed_properties.carrier_properties.docked = docked;
+                self.send_to(ServerToClient::Docked (self.objects[i].get_id(), docked), self.objects[i].get_banner());
+            }
+            if !docked {
+                continue;
+            }
+            let health = &mut self.objects[i].exposed_properties.health_properties; // the carrier's health covers everything in it, so this repairs the cargo too
+            health.health = if health.health + DOCK_REPAIR > health.max_health { health.max_health } else { health.health + DOCK_REPAIR };
+            if self.counter % RESUPPLY_TICKS == 0 && self.objects[i].exposed_properties.carrier_properties.purchase_queue.len() > 0 {
+                let berth = match self.free_berth(i) { Some(berth) => berth, None => continue };
+                let tp = self.objects[i].exposed_properties.carrier_properties.purchase_queue[0];
+                let banner = self.objects[i].get_banner();
+                let piece = match make_piece(tp) { Some(piece) => piece, None => continue };
+                let cost = piece.cost();
+                if self.costs && cost > 0 && !self.charge(banner, cost, TransactionReason::Placement) {
+                    continue; // wait until they can afford it
+                }
+                self.objects[i].exposed_properties.carrier_properties.purchase_queue.remove(0);
+                let position = self.objects[i].exposed_properties.physics.vector_position();
+                let id = self.add(GamePieceBase::new(piece, position.x, position.y, 0.0), Some(banner));
+                if self.costs {
+                    self.ledger.paid_for(id, banner, cost);
+                }
+                self.into_berth(i, id, berth); // add() pushes on the end, so i still points at the carrier
+                self.send_cargo_queue(i);
+            }
+        }
+    }
+
+    fn send_cargo_queue(&self, carrier : usize) {
+        let queue : String = self.objects[carrier].exposed_properties.carrier_properties.purchase_queue.iter().collect();
+        self.send_to(ServerToClient::CargoQueue (self.objects[carrier].get_id(), queue), self.objects[carrier].get_banner());
+    }
+
+    fn queue_cargo(&mut self, carrier : u32, tp : char) -> bool {
+        let carrier = match self.obj_lookup(carrier) { Some(carrier) => carrier, None => return false };
+        if !self.objects[carrier].exposed_properties.carrier_properties.does_accept.contains(&tp) || make_piece(tp).is_none() {
+            return false;
+        }
+        let props = &self.objects[carrier].exposed_properties.carrier_properties;
+        if props.purchase_queue.len() >= props.space_remaining as usize {
+            return false; // no more orders than there are empty berths
+        }
+        self.objects[carrier].exposed_properties.carrier_properties.purchase_queue.push(tp);
+        self.send_cargo_queue(carrier);
+        true
+    }
+
+    fn uncarry(&mut self, carrier : usize, thing : usize) { // take a carried piece out of the carrier right now, leaving it wherever drop_carry puts it
+        let id = self.objects[thing].get_id();
+        let mut carrier_props = self.objects[carrier].exposed_properties.clone();
+        let mut thing_props = self.objects[thing].exposed_properties.clone();
+        self.objects[carrier].piece.drop_carry(&mut carrier_props, &mut thing_props); // undo what carrying it did to the carrier
+        carrier_props.carrier_properties.carrying.retain(|carried| *carried != id);
+        carrier_props.carrier_properties.space_remaining += 1;
+        thing_props.carrier_properties.is_carried = false;
+        self.objects[carrier].exposed_properties = carrier_props;
+        self.objects[thing].exposed_properties = thing_props;
+        self.send_to(ServerToClient::UnCarry (id), self.objects[thing].get_banner());
+    }
+
+    fn unload(&mut self, carrier : u32, id : u32) { // take a carried piece out of the carrier and get rid of it entirely, with a refund of whatever was paid for it
+        let carrier = match self.obj_lookup(carrier) { Some(carrier) => carrier, None => return };
+        let thing = match self.obj_lookup(id) { Some(thing) => thing, None => return };
+        self.uncarry(carrier, thing);
+        if self.objects[thing].identify() == 'Y' {
+            return; // flags aren't ours to sell, so they just get dropped
+        }
+        self.refund_obj(id);
+        self.delete_obj(id);
+    }
+
+    fn swap_loadout(&mut self, banner : usize, carrier_id : u32, loadout : &str) -> Result<(), String> { // strategy phase only: sell everything in a docked carrier and buy a new loadout, all or nothing
+        if self.mode != GameMode::Strategy {
+            return Err("Loadouts can only be swapped during strategy".to_string());
+        }
+        let carrier = match self.obj_lookup(carrier_id) { Some(carrier) => carrier, None => return Err("That carrier doesn't exist".to_string()) };
+        if self.objects[carrier].identify() != 'K' {
+            return Err("That isn't a carrier".to_string());
+        }
+        if !self.is_docked(carrier) {
+            return Err("Carriers have to be docked at a castle or fort to swap loadouts".to_string());
+        }
+        let loadout = Carrier::parse_loadout(loadout)?;
+        let mut cost = 0;
+        for (_, tp) in &loadout {
+            cost += match make_piece(*tp) { Some(piece) => piece.cost(), None => return Err(format!("Can't build '{}'", tp)) };
+        }
+        let cargo = self.objects[carrier].exposed_properties.carrier_properties.carrying.clone();
+        let mut refund = 0;
+        for id in &cargo {
+            refund += self.ledger.paid(*id); // free cargo sells for nothing
+        }
+        if self.costs && self.ledger.balance(banner) + refund < cost {
+            return Err(format!("That loadout costs {} coins and you only have {} after selling the old one", cost, self.ledger.balance(banner) + refund));
+        }
+        for id in cargo {
+            self.unload(carrier_id, id);
+        }
+        if self.costs && cost > 0 && !self.charge(banner, cost, TransactionReason::Placement) {
+            return Err("Couldn't pay for the new loadout".to_string()); // can't happen, we checked above
+        }
+        let position = self.objects[self.obj_lookup(carrier_id).unwrap()].exposed_properties.physics.vector_position();
+        for (berth, tp) in loadout {
+            let piece = make_piece(tp).unwrap();
+            let cost = piece.cost();
+            let id = self.add(GamePieceBase::new(piece, position.x, position.y, 0.0), Some(banner));
+            if self.costs {
+                self.ledger.paid_for(id, banner, cost);
+            }
+            let carrier = self.obj_lookup(carrier_id).unwrap(); // deleting the old cargo moved everything around
+            self.into_berth(carrier, id, berth);
+        }
+        Ok(())
+    }
+
+    fn score_to(&mut self, banner : usize, amount : i32, reason : TransactionReason) {
+        if banner == 0 {
+            return; // the system banner doesn't keep score
+        }
+        let balance = self.ledger.credit(banner, amount, reason);
+        self.send_to(ServerToClient::SetScore (balance), banner);
+    }
+
+    fn charge(&mut self, banner : usize, amount : i32, reason : TransactionReason) -> bool { // atomic check-and-charge. returns false, without charging anything, if the banner can't afford it.
+        match self.ledger.charge(banner, amount, reason) {
+            Some(balance) => {
+                self.send_to(ServerToClient::SetScore (balance), banner);
+                true
+            },
+            None => {
+                println!("Banner {} can't afford {} coins!", banner, amount);
+                false
+            }
+        }
+    }
+
+    fn refund_obj(&mut self, id : u32) { // give whoever paid for an object their money back. used when an object is taken away by force. free stuff refunds nothing.
+        match self.ledger.forget(id) {
+            Some((banner, amount)) => {
+                self.score_to(banner, amount, TransactionReason::Refund);
+            },
+            None => {}
+        }
     }
 
     fn obj_lookup(&self, id : u32) -> Option<usize> { // GIVEN the ID of an OBJECT, return the INDEX or NONE if it DOES NOT EXIST.
@@ -329,8 +655,8 @@ impl Server {
         self.place(Box::new(GoldBar::new()), x, y, 0.0, sender)
     }
 
-    fn place_nexus(&mut self, x : f32, y : f32, effect_radius : f32) -> u32 {
-        self.place(Box::new(Nexus::new(effect_radius)), x, y, 0.0, None)
+    fn place_nexus(&mut self, x : f32, y : f32, effect_radius : f32, table : SpawnTable) -> u32 {
+        self.place(Box::new(Nexus::new(effect_radius, table)), x, y, 0.0, None)
     }
 
     /*fn place_nexus_enemy(&mut self, x : f32, y : f32, parent : u32) -> u32 {
@@ -354,6 +680,16 @@ impl Server {
         self.objects[i].exposed_properties.physics.set_cy(y);
     }
 
+    fn build_navgrid(&mut self) { // call after the map's blocks are placed
+        let mut blocks = vec![];
+        for obj in &self.objects {
+            if obj.identify() == 'B' {
+                blocks.push(obj.exposed_properties.physics.shape);
+            }
+        }
+        self.navgrid = NavGrid::new(self.gamesize, &blocks);
+    }
+
     fn place_tie_fighter(&mut self, x : f32, y : f32, a : f32, sender : Option<usize>) -> u32 {
         self.place(Box::new(TieFighter::new()), x, y, a, sender)
     }
@@ -406,6 +742,10 @@ impl Server {
         if self.living_players == 0 || self.isnt_rtf > 0 { // All RTF games will spawn NPCs
             return;
         }
+        self.spawn_npc();
+    }
+
+    fn spawn_npc(&mut self) { // place_random_npc without the rules about when; sudden death uses this directly
         let mut rng = rand::thread_rng();
         let x = rng.gen_range(0.0..self.gamesize);
         let y = rng.gen_range(0.0..self.gamesize);
@@ -481,6 +821,53 @@ impl Server {
         self.broadcast(ServerToClient::MoveObjectFull (id, phys.x, phys.y, phys.a, phys.w, phys.h));
     }
 
+    pub fn assign_berth(&mut self, id : u32, berth : usize) -> bool { // move a carried piece to another berth, swapping with whatever's already there
+        let thing = match self.obj_lookup(id) { Some(thing) => thing, None => return false };
+        if !self.objects[thing].exposed_properties.carrier_properties.is_carried {
+            return false;
+        }
+        let carrier = match self.obj_lookup(self.objects[thing].exposed_properties.carrier_properties.carrier) { Some(carrier) => carrier, None => return false };
+        let capacity = self.objects[carrier].exposed_properties.carrier_properties.carrying.len() + self.objects[carrier].exposed_properties.carrier_properties.space_remaining as usize;
+        if berth >= capacity {
+            return false;
+        }
+        let old_berth = self.objects[thing].exposed_properties.carrier_properties.berth;
+        let mut moved = vec![thing];
+        for other in self.objects[carrier].exposed_properties.carrier_properties.carrying.clone() {
+            if let Some(other) = self.obj_lookup(other) {
+                if other != thing && self.objects[other].exposed_properties.carrier_properties.berth == berth {
+                    self.objects[other].exposed_properties.carrier_properties.berth = old_berth;
+                    let other_id = self.objects[other].get_id();
+                    self.send_to(ServerToClient::BerthAssigned (other_id, old_berth as u8), self.objects[other].get_banner());
+                    moved.push(other);
+                }
+            }
+        }
+        self.objects[thing].exposed_properties.carrier_properties.berth = berth;
+        self.send_to(ServerToClient::BerthAssigned (id, berth as u8), self.objects[thing].get_banner());
+        let mut carrier_props = self.objects[carrier].exposed_properties.clone();
+        for index in moved { // snap them into their new spots. copies, like uncarry; only the positions come back.
+            let mut props = self.objects[index].exposed_properties.clone();
+            self.objects[carrier].piece.carry_iter(&mut carrier_props, &mut props);
+            self.objects[index].exposed_properties.physics = props.physics;
+        }
+        true
+    }
+
+    pub fn recall(&mut self, id : u32, carrier : u32) -> bool { // send a piece back to dock on a carrier
+        let thing = match self.obj_lookup(id) { Some(thing) => thing, None => return false };
+        let carrier_index = match self.obj_lookup(carrier) { Some(carrier) => carrier, None => return false };
+        if self.objects[thing].exposed_properties.carrier_properties.is_carried || !self.objects[thing].piece.does_navigate() {
+            return false; // already docked, or can't fly back on its own
+        }
+        if self.objects[carrier_index].get_banner() != self.objects[thing].get_banner() || !self.objects[carrier_index].exposed_properties.carrier_properties.will_carry(self.objects[thing].identify()) {
+            return false;
+        }
+        self.objects[thing].recall(carrier);
+        self.send_to(ServerToClient::Recalling (id, carrier), self.objects[thing].get_banner());
+        true
+    }
+
     pub fn shoot(&mut self, bullet_type : BulletType, position : Vector2, velocity : Vector2, range : i32, sender : Option<usize>) -> u32 {
         let bullet = self.place(match bullet_type {
             BulletType::Bullet => Box::new(Bullet::new()),
@@ -512,12 +899,25 @@ impl Server {
         }
     }
 
+    pub fn line_of_sight(&self, from : Vector2, to : Vector2, target : u32) -> bool { // true if nothing solid and fixed (i.e. map blocks) sits between the two points
+        for object in &self.objects {
+            if object.get_id() == target || !object.exposed_properties.physics.solid || !object.exposed_properties.physics.fixed {
+                continue;
+            }
+            if object.exposed_properties.physics.shape.segment_intersects(from, to) {
+                return false;
+            }
+        }
+        true
+    }
+
     fn carry_tasks(&mut self, carrier : usize, carried : usize) { // expects that you've already done the lookups - this is the result of a very effective premature optimization in the physics engine
         let carry_id = self.objects[carried].get_id();
         self.objects[carrier].exposed_properties.carrier_properties.carrying.push(carry_id);
         self.objects[carrier].exposed_properties.carrier_properties.space_remaining -= 1;
         self.objects[carried].exposed_properties.carrier_properties.is_carried = true;
         self.objects[carried].exposed_properties.carrier_properties.carrier = self.objects[carrier].get_id();
+        self.objects[carried].exposed_properties.carrier_properties.recall = None; // made it home
         let mut carrier_props = self.objects[carrier].exposed_properties.clone();
         let mut carried_props = self.objects[carried].exposed_properties.clone();
         unsafe { // gotta hate borrow checking
@@ -538,12 +938,15 @@ impl Server {
             }
         }
         self.living_players -= 1;
-        for i in 0..self.teams.len() {
-            if self.teams[i].members.len() == self.living_players as usize {
-                self.broadcast(ServerToClient::End (self.teams[i].banner_id as u32));
-                return;
-            }
+        if !self.is_io { // io games don't end, so there's no finishing order to keep
+            self.eliminations.push(player);
+        }
+        if self.is_io { // io games never end, so every life is its own session
+            let session = self.history.take_session(player);
+            self.database.write("record the session", vec![self.record_highscore(player), self.record_session(player), self.archive(&session, "".to_string())]);
+            self.send_leaderboards();
         }
+        self.eliminated = true; // don't call it yet: whoever else died this tick (mutual nukes and the like) has to be counted first
         if !was_rtf {
             self.isnt_rtf -= 1;
         }
@@ -564,18 +967,24 @@ impl Server {
         }
         let intasectah = self.objects[x].exposed_properties.physics.shape().intersects(self.objects[y].exposed_properties.physics.shape());
         if intasectah.0 {
-            if self.objects[x].exposed_properties.carrier_properties.will_carry(self.objects[y].identify()) {
+            if self.objects[x].exposed_properties.carrier_properties.will_carry(self.objects[y].identify()) && self.may_carry(x, y) {
                 self.carry_tasks(x, y);
                 return;
             }
-            if self.objects[y].exposed_properties.carrier_properties.will_carry(self.objects[x].identify()) {
+            if self.objects[y].exposed_properties.carrier_properties.will_carry(self.objects[x].identify()) && self.may_carry(y, x) {
                 self.carry_tasks(y, x);
                 return;
             }
             let mut is_collide = false;
             if self.objects[x].get_does_collide(self.objects[y].identify()) {
                 let dmg = self.objects[y].get_collision_info().damage;
+                let dealt = dmg.min(self.objects[x].health().max(0.0));
                 self.objects[x].damage(dmg);
+                let attacker = self.objects[y].get_banner();
+                self.objects[x].hit_by(attacker);
+                if attacker != 0 && attacker != self.objects[x].get_banner() {
+                    self.stats.damage(attacker, dealt);
+                }
                 if self.objects[x].dead() && (self.objects[y].get_banner() != self.objects[x].get_banner() || self.objects[x].identify() == 'g') {
                     /*let killah = self.get_client_by_banner(self.objects[y].get_banner()).await;
                     if killah.is_some() {
@@ -583,7 +992,12 @@ impl Server {
                         killah.unwrap().lock().await.collect(amount).await;
                     }*/
                     if self.objects[x].does_give_score() {
-                        self.broadcast_tx.send(ClientCommand::ScoreTo (self.objects[y].get_banner(), self.objects[x].capture() as i32)).expect("Broadcast failed");
+                        let killer = self.objects[y].get_banner();
+                        let amount = self.objects[x].capture() as i32;
+                        self.stats.kill(killer);
+                        self.log_death(x, killer, self.objects[y].killer_type());
+                        let reason = if self.objects[x].identify() == 'C' { TransactionReason::Chest } else { TransactionReason::Kill };
+                        self.score_to(killer, amount, reason);
                     }
                     if self.objects[x].does_grant_a2a() {
                         self.broadcast_tx.send(ClientCommand::GrantA2A (self.objects[y].get_banner())).expect("Broadcast failed part 2");
@@ -593,7 +1007,13 @@ impl Server {
             }
             if self.objects[y].get_does_collide(self.objects[x].identify()) {
                 let dmg = self.objects[x].get_collision_info().damage;
+                let dealt = dmg.min(self.objects[y].health().max(0.0));
                 self.objects[y].damage(dmg);
+                let attacker = self.objects[x].get_banner();
+                self.objects[y].hit_by(attacker);
+                if attacker != 0 && attacker != self.objects[y].get_banner() {
+                    self.stats.damage(attacker, dealt);
+                }
                 if self.objects[y].dead() && (self.objects[y].get_banner() != self.objects[x].get_banner() || self.objects[y].identify() == 'g') {
                     /*let killah = self.get_client_by_banner(self.objects[x].get_banner()).await;
                     if killah.is_some() {
@@ -601,7 +1021,12 @@ impl Server {
                         killah.unwrap().lock().await.collect(amount).await;
                     }*/
                     if self.objects[y].does_give_score() {
-                        self.broadcast_tx.send(ClientCommand::ScoreTo (self.objects[x].get_banner(), self.objects[y].capture() as i32)).expect("Broadcast failed");
+                        let killer = self.objects[x].get_banner();
+                        let amount = self.objects[y].capture() as i32;
+                        self.stats.kill(killer);
+                        self.log_death(y, killer, self.objects[x].killer_type());
+                        let reason = if self.objects[y].identify() == 'C' { TransactionReason::Chest } else { TransactionReason::Kill };
+                        self.score_to(killer, amount, reason);
                     }
                     if self.objects[y].does_grant_a2a() {
                         self.broadcast_tx.send(ClientCommand::GrantA2A (self.objects[x].get_banner())).expect("Broadcast failed part 2");
@@ -753,6 +1178,15 @@ impl Server {
         i = 0;
         while i < self.objects.len() {
             if self.objects[i].dead() {
+                if self.objects[i].get_banner() != 0 && self.objects[i].does_give_score() {
+                    let banner = self.objects[i].get_banner();
+                    self.stats.loss(banner);
+                }
+                let tp = self.objects[i].identify();
+                if (tp == 'c' || tp == 'R') && !self.history.has_death(self.objects[i].get_id()) { // castles that went down to something other than a kill still go in the archive
+                    let killer = self.objects[i].last_attacker().unwrap_or(0);
+                    self.log_death(i, killer, '?');
+                }
                 unsafe {
                     let objects = &mut self.objects as *mut Vec<GamePieceBase>;
                     let obj = &mut (*objects)[i];
@@ -768,6 +1202,8 @@ impl Server {
                     }
                 }
                 self.broadcast(ServerToClient::Delete (self.objects[i].get_id()));
+                self.ledger.forget(self.objects[i].get_id());
+                self.forget_focus(self.objects[i].get_id());
                 self.objects.swap_remove(i);
                 continue; // don't allow it to reach the increment
             }
@@ -775,10 +1211,38 @@ impl Server {
         }
     }
 
+    fn group_move(&mut self, banner : usize, ids : Vec<u32>, x : f32, y : f32, a : f32, formation : Formation, superuser : bool) {
+        let mut members = vec![]; // indexes of the objects that are actually ours to move, and that can move at all
+        let mut positions = vec![];
+        let mut spacing : f32 = 0.0;
+        for id in ids {
+            if let Some(index) = self.obj_lookup(id) {
+                if (self.objects[index].get_banner() == banner || superuser) && self.objects[index].piece.does_navigate() {
+                    let phys = &self.objects[index].exposed_properties.physics;
+                    spacing = spacing.max(phys.width().max(phys.height()));
+                    positions.push(phys.vector_position());
+                    members.push(index);
+                }
+            }
+        }
+        if members.len() == 0 {
+            return;
+        }
+        spacing += 40.0; // elbow room
+        let slots = formation.slots(Vector2::new(x, y), a, members.len(), spacing);
+        let assignments = crate::formation::assign_slots(&positions, &slots);
+        for i in 0..members.len() {
+            let slot = slots[assignments[i]];
+            self.objects[members[i]].order_move(slot.x, slot.y, a);
+        }
+    }
+
     fn delete_obj(&mut self, id : u32) {
         match self.obj_lookup(id) {
             Some (index) => {
                 self.broadcast(ServerToClient::Delete (id));
+                self.ledger.forget(id);
+                self.forget_focus(id);
                 self.objects.remove(index);
             },
             None => {} // No need to do anything, the object already doesn't exist
@@ -795,6 +1259,8 @@ impl Server {
             if self.objects[i].get_banner() == banner {
                 //println!("Deleting a {} with id {}", self.objects[i].identify(), self.objects[i].get_id());
                 self.broadcast(ServerToClient::Delete (self.objects[i].get_id()));
+                self.ledger.forget(self.objects[i].get_id());
+                self.forget_focus(self.objects[i].get_id());
                 self.objects.remove(i);
                 delted = true;
             }
@@ -812,6 +1278,11 @@ impl Server {
         if self.authenticateds == 0 { // nothing happens if there isn't anyone for it to happen to
             return;
         }
+        if self.leaderboards.poll(&self.database) {
+            for message in self.leaderboards.messages() {
+                self.broadcast(message);
+            }
+        }
         if self.mode == GameMode::Waiting {
             if self.is_io {
                 self.start();
@@ -835,6 +1306,15 @@ impl Server {
                 }
             }
         }
+        else if self.mode == GameMode::Over { // nothing moves; just count down to the next game
+            if self.counter > 0 {
+                self.counter -= 1;
+                self.broadcast_tx.send(ClientCommand::Tick (self.counter, self.mode)).expect("Broadcast failed");
+            }
+            else {
+                self.restart();
+            }
+        }
         else {
             if self.counter > 0 {
                 self.counter -= 1;
@@ -868,12 +1348,44 @@ impl Server {
                     }
                 }
             }*/
+            if let Some(clock) = self.clock.as_mut() {
+                let up = clock.tick();
+                let remaining = clock.remaining();
+                if remaining % FPS as u32 == 0 {
+                    self.broadcast(ServerToClient::MatchClock (remaining / FPS as u32));
+                }
+                if up {
+                    let outcome = self.resolve_by_points();
+                    if outcome == Outcome::Tie && self.sudden_death.is_some() {
+                        self.start_overtime();
+                    }
+                    else {
+                        self.end_game(outcome);
+                        return;
+                    }
+                }
+            }
             if self.mode == GameMode::Play {
                 self.send_physics_updates();
+                if self.leaderboards.live_tick() {
+                    self.send_live_standings();
+                }
             }
             self.broadcast_tx.send(ClientCommand::Tick (self.counter, self.mode)).expect("Broadcast failed");
             if self.mode == GameMode::Play {
                 self.deal_with_objects();
+                self.dock_carriers();
+                if self.check_elimination() || self.overtime_tick() {
+                    return;
+                }
+                if let Some(outcome) = self.capture.as_mut().and_then(|capture| capture.tick()) {
+                    self.end_game(outcome);
+                    return;
+                }
+                if let Some(outcome) = self.ctf_tick() {
+                    self.end_game(outcome);
+                    return;
+                }
                 self.place_timer -= 1;
                 if self.place_timer <= 0 {
                     self.place_timer = rand::random::<u32>() % 200 + 50; // set to 2 for object count benchmarking
@@ -892,7 +1404,8 @@ impl Server {
                 1.0
             },
             GameMode::Strategy => FPS * self.times.0,
-            GameMode::Play => FPS * self.times.1
+            GameMode::Play => FPS * self.times.1,
+            GameMode::Over => FPS * self.postgame
         } as u32;
         self.mode = mode;
     }
@@ -901,7 +1414,8 @@ impl Server {
         self.set_mode(match self.mode {
             GameMode::Strategy => GameMode::Play,
             GameMode::Play => GameMode::Strategy,
-            GameMode::Waiting => GameMode::Waiting
+            GameMode::Waiting => GameMode::Waiting,
+            GameMode::Over => GameMode::Over // only restart() gets us out of here
         });
     }
 
@@ -911,6 +1425,7 @@ impl Server {
                 self.place_random_rubble();
             }
             self.set_mode(GameMode::Strategy);
+            self.history.begin();
             println!("Game start.");
         }
         else {
@@ -936,7 +1451,7 @@ impl Server {
         piece.zones = self.zone_check(piece.exposed_properties.physics.shape);
         if banner.is_some(){
             piece.set_banner(banner.unwrap());
-            self.broadcast_tx.send(ClientCommand::AttachToBanner (piece.get_id(), banner.unwrap(), if self.costs { piece.cost() } else { 0 })).expect("Broadcast FAILED!");
+            self.broadcast_tx.send(ClientCommand::AttachToBanner (piece.get_id(), banner.unwrap())).expect("Broadcast FAILED!");
         }
         self.broadcast(piece.get_new_message());
         let ret = piece.get_id();
@@ -1009,135 +1524,591 @@ impl Server {
         None
     }
 
-    /*async fn metadata(&mut self, user : &mut Client) {
-        println!("Sending metadata to {}", self.banners[user.banner]);
-        for index in 0..self.banners.len() {
-            let banner = &self.banners[index];
-            let team = self.get_team_of_banner(index);
-            println!("Team: {:?}", team);
-            user.send_protocol_message(ServerToClient::BannerAdd (index as u32, banner.to_string())).await;
-            if team.is_some(){
-                user.send_protocol_message(ServerToClient::BannerAddToTeam (index as u32, self.teams[team.unwrap()].banner_id as u32)).await;
-            }
-        }
-        for piece in &self.objects {
-            user.send_protocol_message(piece.get_new_message()).await;
-            for i in 0..piece.upgrades.len() {
-                let upg = piece.upgrades[i].to_string();
-                user.send_protocol_message(ServerToClient::UpgradeThing(piece.get_id(), upg)).await;
-            }
+    fn side_of(&self, banner : usize) -> usize { // teams act as one side (their team banner); everyone else is their own side
+        match self.allegiance.get(&banner) {
+            Some(side) => *side,
+            None => banner
         }
-        user.send_protocol_message(ServerToClient::Metadata (self.gamesize, self.terrain_seed)).await;
-    }
-
-    async fn user_logged_in(&mut self, user : &mut Client) {
-        self.authenticateds += 1;
-        self.metadata(user).await;
     }
 
-    async fn spectator_joined(&mut self, user : &mut Client) {
-        self.metadata(user).await;
-    }*/
-
-    fn reset(&mut self) {
-        println!("############## RESETTING ##############");
-        /*while self.clients.len() > 0 {
-            self.clients[0].lock().await.do_close = true;
-            self.clients.remove(0);
-        }*/
-        self.broadcast_tx.send(ClientCommand::CloseAll).expect("Broadcast failed");
-        while self.objects.len() > 0 {
-            self.objects.remove(0);
+    fn members_of(&self, side : usize) -> Vec<usize> { // the player banners on a side
+        for team in &self.teams {
+            if team.banner_id == side {
+                return team.members.clone();
+            }
         }
-        self.isnt_rtf = 0;
-        self.living_players = 0;
-        self.clients_connected = 0;
-        self.set_mode(GameMode::Waiting);
-        self.clear_banners();
-        self.load_config();
+        vec![side]
     }
 
-    fn clear_banners(&mut self) {
-        println!("Clearing banners...");
-        while self.banners.len() > 1 { 
-            self.banners.remove(1); // Leave the first one, which is the null banner
+    pub fn hold_point(&mut self, side : usize) { // a capture point owned by this side was held for another tick
+        if let Some(capture) = self.capture.as_mut() {
+            if capture.hold(side) {
+                let score = capture.score(side);
+                self.broadcast(ServerToClient::CaptureScore (side as u32, score));
+            }
         }
     }
 
-    fn load_config(&mut self) {
-        if self.config.is_some() {
-            let config = self.config.as_ref().unwrap().clone();
-            config.load_into(self);
+    fn may_carry(&self, carrier : usize, thing : usize) -> bool { // rules on top of will_carry: flags can only be carried off by the other side
+        if self.objects[thing].identify() != 'Y' {
+            return true;
+        }
+        match self.ctf.as_ref().and_then(|ctf| ctf.side_of_flag(self.objects[thing].get_id())) {
+            Some(side) => self.side_of(self.objects[carrier].get_banner()) != side,
+            None => false
         }
     }
 
-    fn new_team(&mut self, name : String, password : String) {
-        let banner = self.banner_add(name);
-        let id = self.teams.len();
-        self.teams.push(TeamData {
-            id,
-            banner_id: banner,
-            password: Arc::new(password),
-            members: vec![]
-        });
-    }
-}
-
-
-impl Client {
-    fn new(socket : WebSocketClientStream, commandah : tokio::sync::mpsc::Sender<ServerCommand>) -> Self {
-        Self {
-            socket,
-            is_superuser : false,
-            game_cmode: GameMode::Waiting,
-            is_authorized: false,
-            score: 0,
-            has_placed: false,
-            banner: 0,
-            m_castle: None,
-            mode: ClientMode::None,
-            team: None,
-            commandah,
-            is_team_leader: false,
-            kys: false,
-            a2a: 0,
-            walls_cap : 2,
-            walls_remaining : 4, // you get a bonus on turn 1
-            is_ready : false
+    fn flag_is_home(&self, flag : &FlagState) -> bool {
+        match self.obj_lookup(flag.id) {
+            Some(index) => !self.objects[index].exposed_properties.carrier_properties.is_carried && (self.objects[index].exposed_properties.physics.vector_position() - flag.base).magnitude() < 1.0,
+            None => false
         }
     }
 
-    async fn grant_a2a(&mut self) {
-        self.a2a += 1;
-        self.refresh_a2a().await;
+    fn send_flag_home(&mut self, flag : usize, base : Vector2) {
+        self.objects[flag].exposed_properties.physics.set_cx(base.x);
+        self.objects[flag].exposed_properties.physics.set_cy(base.y);
     }
 
-    async fn collect(&mut self, amount : i32) {
-        self.score += amount;
-        self.send_protocol_message(ServerToClient::SetScore(self.score)).await;
+    fn ctf_tick(&mut self) -> Option<Outcome> { // pickups, returns and captures for capture-the-flag games
+        let mut ctf = match self.ctf.take() { Some(ctf) => ctf, None => return None };
+        let mut outcome = None;
+        for i in 0..ctf.flags.len() {
+            let index = match self.obj_lookup(ctf.flags[i].id) { Some(index) => index, None => continue };
+            if self.objects[index].exposed_properties.carrier_properties.is_carried {
+                let carrier = match self.obj_lookup(self.objects[index].exposed_properties.carrier_properties.carrier) { Some(carrier) => carrier, None => continue };
+                let side = self.side_of(self.objects[carrier].get_banner());
+                ctf.flags[i].dropped = 0;
+                if !ctf.flags[i].taken {
+                    ctf.flags[i].taken = true;
+                    self.broadcast(ServerToClient::FlagTaken (ctf.flags[i].id, side as u32));
+                }
+                let own_base = match ctf.flag_of(side) {
+                    Some(own) if self.flag_is_home(own) => own.base,
+                    _ => continue // you can't score while they've got your flag
+                };
+                if (self.objects[carrier].exposed_properties.physics.vector_position() - own_base).magnitude() < CAPTURE_RADIUS {
+                    self.uncarry(carrier, index);
+                    let base = ctf.flags[i].base;
+                    self.send_flag_home(index, base);
+                    ctf.flags[i].taken = false;
+                    let won = ctf.capture(side);
+                    self.broadcast(ServerToClient::FlagCaptured (side as u32, ctf.captures(side)));
+                    if outcome.is_none() {
+                        outcome = won;
+                    }
+                }
+            }
+            else if ctf.flags[i].taken || !self.flag_is_home(&ctf.flags[i]) {
+                ctf.flags[i].dropped += 1;
+                let shape = self.objects[index].exposed_properties.physics.shape();
+                let rescued = self.objects.iter().any(|obj| obj.get_banner() != 0 && obj.identify() != 'Y' && self.side_of(obj.get_banner()) == ctf.flags[i].side && obj.exposed_properties.physics.shape().intersects(shape).0); // a friendly touching it brings it straight home
+                if rescued || ctf.flags[i].dropped >= RETURN_TICKS {
+                    let base = ctf.flags[i].base;
+                    self.send_flag_home(index, base);
+                    ctf.flags[i].taken = false;
+                    ctf.flags[i].dropped = 0;
+                    self.broadcast(ServerToClient::FlagReturned (ctf.flags[i].id));
+                }
+            }
+        }
+        self.ctf = Some(ctf);
+        outcome
     }
 
-    async fn send_protocol_message(&mut self, message : ServerToClient) {
-        match self.socket.send(message).await { // unwrap the error; it isn't important here
-            _ => {}
+    fn check_elimination(&mut self) -> bool { // returns true if the game's over
+        if !self.eliminated || self.is_io {
+            return false;
+        }
+        self.eliminated = false;
+        if self.living_players == 0 {
+            self.end_game(Outcome::Tie); // everybody went down together
+            return true;
+        }
+        for i in 0..self.teams.len() {
+            if self.teams[i].members.len() == self.living_players as usize {
+                self.end_game(Outcome::Winner (self.teams[i].banner_id));
+                return true;
+            }
         }
+        false // if there's one loner left, they answer the role call and WinningBanner ends it
     }
 
-    async fn refresh_a2a(&mut self) {
-        self.send_protocol_message(ServerToClient::A2A (self.a2a)).await;
+    fn start_overtime(&mut self) {
+        let rules = match self.sudden_death.clone() { Some(rules) => rules, None => return };
+        println!("MATCH TIED, GOING TO SUDDEN DEATH");
+        self.broadcast(ServerToClient::SuddenDeath (rules.kind.to_byte()));
+        self.overtime = Some(Overtime::new(rules, self.gamesize));
+        self.clock = None;
     }
 
-    async fn cost(&mut self, amount : i32) -> bool {
-        if self.score >= amount {
-            self.collect(-amount).await;
-            true
+    fn overtime_tick(&mut self) -> bool { // returns true if the game's over
+        let overtime = match self.overtime.as_mut() { Some(overtime) => overtime, None => return false };
+        let npcs = overtime.tick();
+        let kind = overtime.kind();
+        let radius = overtime.radius();
+        let damage = overtime.damage();
+        let alive = self.objects.iter().filter(|obj| obj.get_banner() == 0 && ['0', '1', '2', '3'].contains(&obj.identify())).count();
+        for _ in 0..std::cmp::min(npcs as usize, MAX_NPCS.saturating_sub(alive)) {
+            self.spawn_npc();
         }
-        else {
-            self.kys = true;
-            println!("Client can't afford to buy a thing!");
-            false
+        if kind == SuddenDeath::Shrink {
+            let center = Vector2::new(self.gamesize / 2.0, self.gamesize / 2.0);
+            for obj in &mut self.objects {
+                if obj.get_banner() != 0 && (obj.exposed_properties.physics.vector_position() - center).magnitude() > radius {
+                    obj.damage(damage);
+                }
+            }
+            if self.counter % FPS as u32 == 0 {
+                self.broadcast(ServerToClient::SafeZone (center.x, center.y, radius));
+            }
         }
-    }
+        if self.counter % FPS as u32 == 0 { // the first side to pull ahead takes it
+            if let Outcome::Winner (side) = self.resolve_by_points() {
+                self.end_game(Outcome::Winner (side));
+                return true;
+            }
+        }
+        false
+    }
+
+    fn resolve_by_points(&self) -> Outcome { // time's up: every side still alive, ranked by money, with teams pooling their members'
+        let mut standings : Vec<Standing> = vec![];
+        for obj in &self.objects {
+            if obj.identify() != 'c' && obj.identify() != 'R' {
+                continue;
+            }
+            let side = self.side_of(obj.get_banner());
+            if standings.iter().any(|standing| standing.side == side) {
+                continue;
+            }
+            let members = self.members_of(side);
+            standings.push(Standing {
+                side,
+                points : members.iter().map(|banner| self.ledger.balance(*banner)).sum(),
+                earned : members.iter().map(|banner| self.ledger.earned(*banner)).sum()
+            });
+        }
+        most_points(&standings)
+    }
+
+    fn end_game(&mut self, outcome : Outcome) { // freeze everything, tell everyone how it went, and write it down. restart() picks up from here once the post-game is over.
+        if self.mode == GameMode::Over {
+            return; // already ended; this happens when several things notice at once
+        }
+        match outcome {
+            Outcome::Winner (side) => {
+                println!("GAME ENDS WITH A WINNER");
+                self.broadcast(ServerToClient::End (side as u32));
+            },
+            Outcome::Tie => {
+                println!("GAME ENDS WITH A TIE");
+                self.broadcast(ServerToClient::Tie);
+            }
+        }
+        let players = self.players();
+        for banner in &players {
+            let stats = self.stats.get(*banner);
+            self.broadcast(ServerToClient::Summary (*banner as u32, stats.kills, stats.losses, self.ledger.balance(*banner), stats.built, stats.damage));
+        }
+        let mut writes = self.record_results(&outcome, &players);
+        writes.push(self.update_ratings(&outcome, &players));
+        writes.push(self.record_history(&outcome));
+        self.database.write("record the match", writes);
+        self.send_leaderboards();
+        self.set_mode(GameMode::Over);
+    }
+
+    fn players(&self) -> Vec<usize> { // everybody who placed a castle this game. spectators and people from past games don't count.
+        self.history.participants()
+    }
+
+    fn record_results(&self, outcome : &Outcome, players : &Vec<usize>) -> Vec<Write> { // per-player and per-team results, high scores and leaderboard entries, ready for the database thread
+        let finished = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0) as i64;
+        let mut writes = vec![];
+        let mut rows = vec![];
+        for banner in players {
+            let stats = self.stats.get(*banner);
+            let won = match outcome {
+                Outcome::Winner (side) => Some(self.side_of(*banner) == *side),
+                Outcome::Tie => None // nobody won, and nobody lost either
+            };
+            rows.push((self.banners[*banner].clone(), won, stats.kills, stats.losses, self.ledger.balance(*banner), stats.built, stats.damage));
+            writes.push(self.record_highscore(*banner));
+            writes.push(self.leaderboards.record(finished, self.banners[*banner].clone(), self.ledger.balance(*banner)));
+        }
+        let mut teams = vec![];
+        if let Outcome::Winner (side) = outcome {
+            for team in &self.teams {
+                if !self.allegiance.values().any(|member| *member == team.banner_id) {
+                    continue; // nobody played for them this time
+                }
+                teams.push((self.banners[team.banner_id].clone(), team.banner_id == *side));
+            }
+        }
+        writes.push(Box::new(move |connection : &sqlite::Connection| -> sqlite::Result<()> {
+            for (banner, won, kills, losses, score, built, damage) in rows {
+                let mut statement = connection.prepare("INSERT INTO match_results VALUES (?, ?, ?, ?, ?, ?, ?, ?)")?;
+                statement.bind((1, finished))?;
+                statement.bind((2, banner.as_str()))?;
+                statement.bind((3, won.unwrap_or(false) as i64))?;
+                statement.bind((4, kills as i64))?;
+                statement.bind((5, losses as i64))?;
+                statement.bind((6, score as i64))?;
+                statement.bind((7, built as i64))?;
+                statement.bind((8, damage as f64))?;
+                statement.next()?;
+                // registered players keep a running record; guests have no row in logins, so these don't touch them
+                let record = match won {
+                    Some(true) => "UPDATE logins SET wins = wins + 1 WHERE banner = ?",
+                    Some(false) => "UPDATE logins SET losses = losses + 1 WHERE banner = ?",
+                    None => continue
+                };
+                let mut statement = connection.prepare(record)?;
+                statement.bind((1, banner.as_str()))?;
+                statement.next()?;
+            }
+            for (name, won) in teams {
+                let mut statement = connection.prepare("INSERT INTO teams_records SELECT ?, 0, 0 WHERE NOT EXISTS (SELECT 1 FROM teams_records WHERE teamname = ?)")?;
+                statement.bind((1, name.as_str()))?;
+                statement.bind((2, name.as_str()))?;
+                statement.next()?;
+                let mut statement = connection.prepare(if won { "UPDATE teams_records SET wins = wins + 1 WHERE teamname = ?" } else { "UPDATE teams_records SET losses = losses + 1 WHERE teamname = ?" })?;
+                statement.bind((1, name.as_str()))?;
+                statement.next()?;
+            }
+            Ok(())
+        }));
+        writes
+    }
+
+    fn update_ratings(&self, outcome : &Outcome, players : &Vec<usize>) -> Write { // elo for every player, and for the teams if there were at least two of them. the ratings are read and written in the same transaction, so two rooms finishing at once can't lose each other's games.
+        let winners : Vec<usize> = match outcome {
+            Outcome::Winner (side) => players.iter().copied().filter(|player| self.side_of(*player) == *side).collect(),
+            Outcome::Tie => vec![]
+        };
+        let places = rating::placements(players, &winners, &self.eliminations);
+        // a side places as well as its best member, so teammates share the result
+        let mut sides : Vec<(usize, u32)> = vec![];
+        for (i, player) in players.iter().enumerate() {
+            let side = self.side_of(*player);
+            match sides.iter_mut().find(|entry| entry.0 == side) {
+                Some(entry) => {
+                    entry.1 = entry.1.min(places[i]);
+                },
+                None => {
+                    sides.push((side, places[i]));
+                }
+            }
+        }
+        let place_of = |side : usize| sides.iter().find(|entry| entry.0 == side).unwrap().1;
+        let players : Vec<(usize, String, u32)> = players.iter().map(|player| (*player, self.banners[*player].clone(), place_of(self.side_of(*player)))).collect();
+        let teams : Vec<(String, u32)> = sides.iter().filter(|entry| self.teams.iter().any(|team| team.banner_id == entry.0)).map(|entry| (self.banners[entry.0].clone(), entry.1)).collect();
+        let broadcast_tx = self.broadcast_tx.clone();
+        Box::new(move |connection : &sqlite::Connection| -> sqlite::Result<()> {
+            let mut registered = vec![]; // guests still place, but only accounts get a rating
+            for player in players {
+                if accounts::registered(connection, &player.1)? {
+                    registered.push(player);
+                }
+            }
+            if registered.len() >= 2 { // somebody to be rated against
+                let rated = rating::rate(connection, registered.iter().map(|player| player.1.clone()).collect(), registered.iter().map(|player| player.2).collect(), false)?;
+                for ((banner, name, _), (value, games)) in registered.into_iter().zip(rated) {
+                    let _ = broadcast_tx.send(ClientCommand::SendTo (ServerToClient::Rating (name, false, value, games), banner)); // nobody's listening if the room's emptied out
+                }
+            }
+            if teams.len() >= 2 {
+                rating::rate(connection, teams.iter().map(|team| team.0.clone()).collect(), teams.iter().map(|team| team.1).collect(), true)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn log_death(&mut self, victim : usize, killer : usize, killer_type : char) { // victim is an index into objects, killer is a banner
+        let position = self.objects[victim].exposed_properties.physics.vector_position();
+        let victim_type = self.objects[victim].identify();
+        let event = KillEvent {
+            time : self.history.elapsed(),
+            killer,
+            victim : self.objects[victim].get_banner(),
+            killer_type,
+            victim_type,
+            x : position.x,
+            y : position.y,
+            castle : victim_type == 'c' || victim_type == 'R',
+            victim_id : self.objects[victim].get_id()
+        };
+        self.history.kill(event);
+    }
+
+    fn record_history(&self, outcome : &Outcome) -> Write {
+        let winner = match outcome {
+            Outcome::Winner (side) => self.banners[*side].clone(),
+            Outcome::Tie => "".to_string()
+        };
+        self.archive(&self.history, winner)
+    }
+
+    fn archive(&self, history : &MatchHistory, winner : String) -> Write { // a whole match, or one io-mode life
+        let mut participants = vec![];
+        for banner in history.participants() {
+            let side = self.side_of(banner);
+            participants.push(Participant {
+                banner : self.banners[banner].clone(),
+                team : if side == banner { "".to_string() } else { self.banners[side].clone() },
+                mode : history.mode_of(banner).unwrap_or(ClientMode::Normal),
+                score : self.ledger.balance(banner)
+            });
+        }
+        let (history, map, banners) = (history.clone(), self.leaderboards.map.clone(), self.banners.clone());
+        Box::new(move |connection : &sqlite::Connection| -> sqlite::Result<()> {
+            let id = history.save(connection, &map, &winner, &participants, &banners)?;
+            println!("Archived match {}: {} players, {} kills", id, participants.len(), history.events().len());
+            Ok(())
+        })
+    }
+
+    fn send_live_standings(&self) {
+        let scores = self.players().iter().map(|banner| (*banner, self.ledger.balance(*banner))).collect();
+        for (place, (banner, score)) in leaderboard::live(scores, self.leaderboards.top).iter().enumerate() {
+            self.broadcast(ServerToClient::LiveStanding (place as u32 + 1, *banner as u32, *score));
+        }
+    }
+
+    fn send_leaderboards(&mut self) { // the persistent boards reload in the background, and go out once they're back; see mainloop
+        self.leaderboards.invalidate();
+    }
+
+    fn record_session(&self, banner : usize) -> Write { // an io-mode life, as far as the leaderboards care
+        let finished = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0) as i64;
+        self.leaderboards.record(finished, self.banners[banner].clone(), self.ledger.balance(banner))
+    }
+
+    fn record_highscore(&self, banner : usize) -> Write { // only ever goes up
+        let (name, score) = (self.banners[banner].clone(), self.ledger.balance(banner));
+        Box::new(move |connection : &sqlite::Connection| -> sqlite::Result<()> {
+            let mut statement = connection.prepare("UPDATE logins SET highscore = MAX(highscore, ?) WHERE banner = ?")?;
+            statement.bind((1, score as i64))?;
+            statement.bind((2, name.as_str()))?;
+            statement.next()?;
+            Ok(())
+        })
+    }
+
+    fn restart(&mut self) { // the post-game is over: clear the world and go back to Waiting, without kicking anybody out
+        println!("############## NEW GAME ##############");
+        for obj in &self.objects {
+            self.broadcast(ServerToClient::Delete (obj.get_id()));
+        }
+        self.objects.clear();
+        self.isnt_rtf = 0;
+        self.living_players = 0;
+        self.ledger.clear();
+        self.focus_targets.clear();
+        self.capture = None;
+        self.ctf = None;
+        self.clock = None;
+        self.stats.clear();
+        self.allegiance.clear();
+        self.eliminations.clear();
+        self.history.clear();
+        self.sudden_death = None;
+        self.overtime = None;
+        self.eliminated = false;
+        for team in &mut self.teams {
+            team.members.clear();
+        }
+        self.set_mode(GameMode::Waiting);
+        self.broadcast_tx.send(ClientCommand::NewGame).expect("Broadcast failed");
+        if let Err(error) = self.load_config() { // it loaded fine when the room opened, so this shouldn't happen
+            println!("Couldn't reload the config: {}", error);
+        }
+    }
+
+    pub fn get_focus_target(&self, banner : usize) -> Option<u32> {
+        self.focus_targets.get(&self.side_of(banner)).copied()
+    }
+
+    fn set_focus_target(&mut self, banner : usize, id : u32) { // id 0 clears it
+        let key = self.side_of(banner);
+        if id == 0 {
+            self.focus_targets.remove(&key);
+        }
+        else {
+            let index = match self.obj_lookup(id) { Some(index) => index, None => return }; // it's already gone, or never was
+            if self.side_of(self.objects[index].get_banner()) == key {
+                return; // no focusing fire on your own side
+            }
+            self.focus_targets.insert(key, id);
+        }
+        match self.get_team_of_banner(banner) {
+            Some(team) => {
+                for member in self.teams[team].members.clone() {
+                    self.send_to(ServerToClient::FocusTarget (id), member);
+                }
+            },
+            None => {
+                self.send_to(ServerToClient::FocusTarget (id), banner);
+            }
+        }
+    }
+
+    fn forget_focus(&mut self, id : u32) { // the object's gone, so nobody should be focusing on it anymore
+        let sides : Vec<usize> = self.focus_targets.iter().filter(|focus| *focus.1 == id).map(|focus| *focus.0).collect();
+        for side in sides {
+            self.focus_targets.remove(&side);
+            for member in self.members_of(side) {
+                self.send_to(ServerToClient::FocusTarget (0), member);
+            }
+        }
+    }
+
+    /*async fn metadata(&mut self, user : &mut Client) {
+        println!("Sending metadata to {}", self.banners[user.banner]);
+        for index in 0..self.banners.len() {
+            let banner = &self.banners[index];
+            let team = self.get_team_of_banner(index);
+            println!("Team: {:?}", team);
+            user.send_protocol_message(ServerToClient::BannerAdd (index as u32, banner.to_string())).await;
+            if team.is_some(){
+                user.send_protocol_message(ServerToClient::BannerAddToTeam (index as u32, self.teams[team.unwrap()].banner_id as u32)).await;
+            }
+        }
+        for piece in &self.objects {
+            user.send_protocol_message(piece.get_new_message()).await;
+            for i in 0..piece.upgrades.len() {
+                let upg = piece.upgrades[i].to_string();
+                user.send_protocol_message(ServerToClient::UpgradeThing(piece.get_id(), upg)).await;
+            }
+        }
+        user.send_protocol_message(ServerToClient::Metadata (self.gamesize, self.terrain_seed)).await;
+    }
+
+    async fn user_logged_in(&mut self, user : &mut Client) {
+        self.authenticateds += 1;
+        self.metadata(user).await;
+    }
+
+    async fn spectator_joined(&mut self, user : &mut Client) {
+        self.metadata(user).await;
+    }*/
+
+    fn reset(&mut self) {
+        println!("############## RESETTING ##############");
+        /*while self.clients.len() > 0 {
+            self.clients[0].lock().await.do_close = true;
+            self.clients.remove(0);
+        }*/
+        self.broadcast_tx.send(ClientCommand::CloseAll).expect("Broadcast failed");
+        while self.objects.len() > 0 {
+            self.objects.remove(0);
+        }
+        self.isnt_rtf = 0;
+        self.living_players = 0;
+        self.clients_connected = 0;
+        self.ledger.clear();
+        self.focus_targets.clear();
+        self.capture = None; // the config puts these back, fresh
+        self.ctf = None;
+        self.clock = None;
+        self.stats.clear();
+        self.allegiance.clear();
+        self.eliminations.clear();
+        self.history.clear();
+        self.sudden_death = None;
+        self.overtime = None;
+        self.eliminated = false;
+        self.set_mode(GameMode::Waiting);
+        self.clear_banners();
+        self.teams.clear(); // their banners just went away; the config makes them again
+        if let Err(error) = self.load_config() {
+            println!("Couldn't reload the config: {}", error);
+        }
+    }
+
+    fn clear_banners(&mut self) {
+        println!("Clearing banners...");
+        while self.banners.len() > 1 { 
+            self.banners.remove(1); // Leave the first one, which is the null banner
+        }
+    }
+
+    fn load_config(&mut self) -> Result<(), String> {
+        if self.config.is_some() {
+            let config = self.config.as_ref().unwrap().clone();
+            config.load_into(self)?;
+        }
+        Ok(())
+    }
+
+    fn new_team(&mut self, name : String, password : String) {
+        let banner = self.banner_add(name);
+        let id = self.teams.len();
+        self.teams.push(TeamData {
+            id,
+            banner_id: banner,
+            password: Arc::new(password),
+            members: vec![]
+        });
+    }
+}
+
+
+impl Client {
+    fn new(socket : WebSocketClientStream, commandah : tokio::sync::mpsc::Sender<ServerCommand>) -> Self {
+        Self {
+            socket,
+            is_superuser : false,
+            game_cmode: GameMode::Waiting,
+            is_authorized: false,
+            has_placed: false,
+            banner: 0,
+            m_castle: None,
+            mode: ClientMode::None,
+            team: None,
+            commandah,
+            is_team_leader: false,
+            kys: false,
+            a2a: 0,
+            walls_cap : 2,
+            walls_remaining : 4, // you get a bonus on turn 1
+            is_ready : false,
+            selection : vec![]
+        }
+    }
+
+    fn new_game(&mut self) { // everything that only lasts one game goes back to how Client::new has it
+        self.has_placed = false;
+        self.m_castle = None;
+        self.a2a = 0;
+        self.walls_cap = 2;
+        self.walls_remaining = 4;
+        self.is_ready = false;
+        self.selection.clear();
+    }
+
+    async fn grant_a2a(&mut self) {
+        self.a2a += 1;
+        self.refresh_a2a().await;
+    }
+
+    async fn send_protocol_message(&mut self, message : ServerToClient) {
+        match self.socket.send(message).await { // unwrap the error; it isn't important here
+            _ => {}
+        }
+    }
+
+    async fn refresh_a2a(&mut self) {
+        self.send_protocol_message(ServerToClient::A2A (self.a2a)).await;
+    }
+
+    async fn command(&mut self, command : ServerCommand) { // if the room's shut down, there's nothing left for this client to do
+        if self.commandah.send(command).await.is_err() {
+            self.kys = true;
+        }
+    }
 
     async fn handle(&mut self, message : ClientToServer) {
         if self.is_authorized {
@@ -1145,10 +2116,13 @@ impl Client {
                 ClientToServer::ReadyState (v) => {
                     if v != self.is_ready {
                         self.is_ready = v;
-                        self.commandah.send(ServerCommand::ReadyState (self.is_ready)).await.unwrap();
+                        self.command(ServerCommand::ReadyState (self.is_ready)).await;
                     }
                 }
                 ClientToServer::Place (x, y, tp, variant) => {
+                    if self.game_cmode == GameMode::Over && !self.is_superuser {
+                        return; // the game's over; nothing new goes in until the next one
+                    }
                     if self.game_cmode == GameMode::Play && tp != b'c' && !self.is_superuser { // if it is trying to place an object, but it isn't strat mode or waiting mode and it isn't placing a castle
                         // originally, this retaliated, but now it just refuses. the retaliation was a problem.
                         println!("ATTEMPT TO PLACE IN PLAY MODE");
@@ -1159,7 +2133,7 @@ impl Client {
                         b'c' => {
                             if !self.has_placed {
                                 self.has_placed = true;
-                                self.commandah.send(ServerCommand::Place (PlaceCommand::Castle (x, y, self.mode, self.banner, self.team))).await.unwrap();
+                                self.command(ServerCommand::Place (PlaceCommand::Castle (x, y, self.mode, self.banner, self.team))).await;
                             }
                             else {
                                 println!("User is attempting to place a castle more than once.");
@@ -1168,7 +2142,7 @@ impl Client {
                         },
                         b'w' => {
                             if self.walls_remaining > 0 {
-                                self.commandah.send(ServerCommand::Place (PlaceCommand::SimplePlace (x, y, fire_banner, b'w'))).await.unwrap();
+                                self.command(ServerCommand::Place (PlaceCommand::SimplePlace (x, y, fire_banner, b'w'))).await;
                                 if !self.is_superuser {
                                     self.walls_remaining -= 1;
                                 }
@@ -1177,33 +2151,130 @@ impl Client {
                         b'K' => {
                             match variant {
                                 0 => {
-                                    self.commandah.send(ServerCommand::Place (PlaceCommand::SimplePlace (x, y, fire_banner, b'K'))).await.unwrap();
+                                    self.command(ServerCommand::Place (PlaceCommand::SimplePlace (x, y, fire_banner, b'K'))).await;
                                 },
                                 _ => {
-                                    self.commandah.send(ServerCommand::Place (PlaceCommand::CarrierVariant (x, y, fire_banner, variant))).await.unwrap();
+                                    self.command(ServerCommand::Place (PlaceCommand::CarrierVariant (x, y, fire_banner, variant))).await;
                                 }
                             }
                         },
                         b'F' => {
                             match self.m_castle {
                                 Some(cid) => {
-                                    self.commandah.send(ServerCommand::Place (PlaceCommand::Fort (x, y, fire_banner, cid))).await.unwrap();
+                                    self.command(ServerCommand::Place (PlaceCommand::Fort (x, y, fire_banner, cid))).await;
                                 }
                                 None => {}
                             }
                         },
                         _ => {
-                            self.commandah.send(ServerCommand::Place (PlaceCommand::SimplePlace (x, y, fire_banner, tp))).await.unwrap();
+                            self.command(ServerCommand::Place (PlaceCommand::SimplePlace (x, y, fire_banner, tp))).await;
                         }
                     }
                 },
-                ClientToServer::Cost (amount) => {
-                    let amount = amount.abs();
-                    println!("Costing an amount of money equal to {amount} coins");
-                    self.collect(-amount).await;
+                ClientToServer::PlaceCarrier (x, y, loadout) => {
+                    if (self.game_cmode == GameMode::Play || self.game_cmode == GameMode::Over) && !self.is_superuser {
+                        println!("ATTEMPT TO PLACE IN PLAY MODE");
+                        return;
+                    }
+                    let fire_banner = if self.is_superuser { None } else { Some(self.banner) };
+                    self.command(ServerCommand::PlaceCarrier (x, y, fire_banner, loadout, self.banner)).await;
+                },
+                ClientToServer::QueueCargo (carrier, tp) => {
+                    self.command(ServerCommand::QueueCargo (self.banner, carrier, tp as char, self.is_superuser)).await;
+                },
+                ClientToServer::SwapLoadout (carrier, loadout) => {
+                    self.command(ServerCommand::SwapLoadout (self.banner, carrier, loadout, self.is_superuser)).await;
+                },
+                ClientToServer::Cost (amount) => { // score is owned by the server now; clients don't get to touch it
+                    println!("Ignoring client request to cost {} coins", amount);
                 },
                 ClientToServer::Move (id, x, y, a) => {
-                    self.commandah.send(ServerCommand::Move (self.banner, id, x, y, a, self.is_superuser)).await.unwrap();
+                    self.command(ServerCommand::Move (self.banner, id, x, y, a, self.is_superuser)).await;
+                },
+                ClientToServer::QueueMove (id, x, y, a, attack) => {
+                    self.command(ServerCommand::QueueMove (self.banner, id, MoveOrder { x, y, a, attack }, self.is_superuser)).await;
+                },
+                ClientToServer::SetPatrol (id, patrol) => {
+                    self.command(ServerCommand::SetPatrol (self.banner, id, patrol, self.is_superuser)).await;
+                },
+                ClientToServer::ClearOrders (id) => {
+                    self.command(ServerCommand::ClearOrders (self.banner, id, self.is_superuser)).await;
+                },
+                ClientToServer::SetStance (id, stance) => {
+                    match Stance::from_byte(stance) {
+                        Some(stance) => {
+                            self.command(ServerCommand::SetStance (self.banner, id, stance, self.is_superuser)).await;
+                        },
+                        None => {
+                            println!("Client sent an invalid stance, ignoring");
+                        }
+                    }
+                },
+                ClientToServer::SetTargeting (id, mode, types, banner, threat) => {
+                    match TargetingMode::from_byte(mode) {
+                        Some(mode) => {
+                            let mut filters = vec![];
+                            if types.len() > 0 {
+                                filters.push(TargetingFilter::Types (types.chars().collect()));
+                            }
+                            if banner != 0 {
+                                filters.push(TargetingFilter::Banner (banner as usize));
+                            }
+                            if threat > 0.0 {
+                                filters.push(TargetingFilter::Threat (threat));
+                            }
+                            let filter = match filters.len() {
+                                0 => None,
+                                1 => filters.pop(),
+                                _ => Some(TargetingFilter::All (filters))
+                            };
+                            self.command(ServerCommand::SetTargeting (self.banner, id, mode, filter, self.is_superuser)).await;
+                        },
+                        None => {
+                            println!("Client sent an invalid targeting mode, ignoring");
+                        }
+                    }
+                },
+                ClientToServer::RequireSight (id, require) => {
+                    self.command(ServerCommand::RequireSight (self.banner, id, require, self.is_superuser)).await;
+                },
+                ClientToServer::Launch (id) => {
+                    self.command(ServerCommand::Launch (self.banner, id, self.is_superuser)).await;
+                },
+                ClientToServer::LaunchAll (carrier) => {
+                    self.command(ServerCommand::LaunchAll (self.banner, carrier, self.is_superuser)).await;
+                },
+                ClientToServer::Recall (id, carrier) => {
+                    self.command(ServerCommand::Recall (self.banner, id, carrier, self.is_superuser)).await;
+                },
+                ClientToServer::AssignBerth (id, berth) => {
+                    self.command(ServerCommand::AssignBerth (self.banner, id, berth as usize, self.is_superuser)).await;
+                },
+                ClientToServer::FocusTarget (id) => {
+                    self.command(ServerCommand::FocusTarget (self.banner, id)).await;
+                },
+                ClientToServer::Select (id) => {
+                    if !self.selection.contains(&id) && self.selection.len() < MAX_SELECTION {
+                        self.selection.push(id);
+                    }
+                },
+                ClientToServer::Deselect (id) => {
+                    self.selection.retain(|selected| *selected != id);
+                },
+                ClientToServer::ClearSelection => {
+                    self.selection.clear();
+                },
+                ClientToServer::GroupMove (x, y, a, formation) => {
+                    match Formation::from_byte(formation) {
+                        Some(formation) => {
+                            if self.selection.len() > 0 {
+                                self.command(ServerCommand::GroupMove (self.banner, self.selection.clone(), x, y, a, formation, self.is_superuser)).await;
+                            }
+                        },
+                        None => {
+                            println!("Invalid formation {}", formation);
+                        }
+                    }
                 },
                 ClientToServer::LaunchA2A (target) => { // AIR TO AIR!
                     if self.a2a == 0 {
@@ -1212,12 +2283,12 @@ impl Client {
                     if self.m_castle.is_some() {
                         self.a2a -= 1;
                         self.refresh_a2a().await;
-                        self.commandah.send(ServerCommand::Place (PlaceCommand::A2A (self.m_castle.unwrap(), target, self.banner))).await.unwrap();
+                        self.command(ServerCommand::Place (PlaceCommand::A2A (self.m_castle.unwrap(), target, self.banner))).await;
                     }
                 },
                 ClientToServer::PilotRTF (fire, left, right, airbrake, shoot) => {
                     if self.game_cmode == GameMode::Play && self.m_castle.is_some(){
-                        self.commandah.send(ServerCommand::PilotRTF (self.m_castle.unwrap(), fire, left, right, airbrake, shoot)).await.unwrap();
+                        self.command(ServerCommand::PilotRTF (self.m_castle.unwrap(), fire, left, right, airbrake, shoot)).await;
                     }
                 },
                 ClientToServer::Chat (chatter, broadcast) => { // Talk
@@ -1234,14 +2305,14 @@ impl Client {
                             args
                         });
                     }*/
-                    self.commandah.send(ServerCommand::Chat (self.banner, chatter, if self.is_team_leader { 1 } else { 0 },
+                    self.command(ServerCommand::Chat (self.banner, chatter, if self.is_team_leader { 1 } else { 0 },
                         if self.team.is_none() || broadcast {
                             None
                         }
                         else {
                             self.team
                         }
-                    )).await.unwrap();
+                    )).await;
                 },
                 ClientToServer::UpgradeThing (_, _) => {
                     // Upgrade.
@@ -1276,73 +2347,46 @@ impl Client {
                 },
                 ClientToServer::Shop (thing) => {
                     if self.m_castle.is_some() {
-                        match thing {
-                            b'w' => {
-                                if self.cost(30).await {
-                                    self.walls_cap += 2;
-                                    self.walls_remaining += 2;
-                                }
-                            }
-                            b'g' => {
-                                if self.cost(30).await {
-                                    self.commandah.send(ServerCommand::UpgradeNextTier (self.m_castle.unwrap(), "b".to_string())).await.unwrap();
-                                }
-                            }
-                            b's' => {
-                                if self.cost(40).await {
-                                    self.commandah.send(ServerCommand::UpgradeNextTier (self.m_castle.unwrap(), "s".to_string())).await.unwrap();
-                                }
-                            }
-                            b'f' => {
-                                if self.cost(70).await {
-                                    self.commandah.send(ServerCommand::UpgradeNextTier (self.m_castle.unwrap(), "f".to_string())).await.unwrap();
-                                }
-                            }
-                            b'h' => {
-                                if self.cost(150).await {
-                                    self.commandah.send(ServerCommand::UpgradeNextTier (self.m_castle.unwrap(), "h".to_string())).await.unwrap();
-                                }
-                            }
-                            b'a' => {
-                                if self.cost(100).await {
-                                    self.grant_a2a().await;
-                                }
-                            }
-                            _ => {
-                                println!("Invalid shop command {}", thing);
-                            }
-                        }
+                        self.command(ServerCommand::Shop (self.banner, self.m_castle.unwrap(), thing)).await;
                     }
                 }
                 ClientToServer::GodDelete (id) => {
                     if self.is_superuser {
-                        self.commandah.send(ServerCommand::RejectObject (id)).await.unwrap();
+                        self.command(ServerCommand::RejectObject (id)).await;
                     }
                 },
                 ClientToServer::GodReset => {
                     if self.is_superuser {
-                        self.commandah.send(ServerCommand::Reset).await.unwrap();
+                        self.command(ServerCommand::Reset).await;
                     }
                 }
                 ClientToServer::GodDisconnect (cli) => {
                     if self.is_superuser {
-                        self.commandah.send(ServerCommand::GodDisconnect (cli as usize)).await.unwrap();
+                        self.command(ServerCommand::GodDisconnect (cli as usize)).await;
                     }
                 }
                 ClientToServer::GodFlip => {
                     if self.is_superuser {
-                        self.commandah.send(ServerCommand::Flip).await.unwrap();
+                        self.command(ServerCommand::Flip).await;
                     }
                 }
                 ClientToServer::GodNuke (n) => {
                     if self.is_superuser {
-                        self.commandah.send(ServerCommand::Nuke (n as usize)).await.unwrap();
+                        self.command(ServerCommand::Nuke (n as usize)).await;
                     }
                 },
                 ClientToServer::GodBless (banner) => {
                     if self.is_superuser {
-                        self.commandah.send(ServerCommand::ChristmasExclusive (banner as usize)).await.unwrap();
+                        self.command(ServerCommand::ChristmasExclusive (banner as usize)).await;
                     }
+                },
+                ClientToServer::ListRooms | ClientToServer::Queue (_, _) | ClientToServer::LeaveQueue | ClientToServer::Register (_, _) | ClientToServer::Login (_, _) => {}, // you're already in a room, so there's no point
+                ClientToServer::ConnectRoom (_, _, _, _) => {
+                    println!("What? Client trying to connect twice? Killing.");
+                    self.kys = true;
+                },
+                ClientToServer::GetRating (name, team) => {
+                    self.command(ServerCommand::GetRating (Some(self.banner), name, team)).await;
                 }
             }
         }
@@ -1350,7 +2394,7 @@ impl Client {
             match message {
                 ClientToServer::Connect (password, banner, mode) => {
                     let (tx, mut rx) = tokio::sync::mpsc::channel(32);
-                    self.commandah.send(ServerCommand::BeginConnection (password, banner, mode.clone(), tx)).await.unwrap();
+                    self.command(ServerCommand::BeginConnection (password, banner, mode.clone(), tx)).await;
                     loop {
                         match rx.recv().await {
                             Some(command) => {
@@ -1476,17 +2520,187 @@ impl Client {
 }
 
 
-async fn got_client(client : WebSocketClientStream, broadcaster : tokio::sync::broadcast::Sender<ClientCommand>, commandset : tokio::sync::mpsc::Sender<ServerCommand>){
-    commandset.send(ServerCommand::Connect).await.unwrap();
+async fn next_match(ticket : &mut Option<(u64, String, tokio::sync::oneshot::Receiver<Matched>)>) -> Matched { // never finishes if you aren't queued
+    match ticket {
+        Some((_, _, rx)) => rx.await.unwrap_or(None),
+        None => std::future::pending().await
+    }
+}
+
+
+async fn may_use(sql : &str, account : Option<&str>, banner : &str) -> bool { // accounts::may_use, off the lobby loop
+    let (sql, account, banner) = (sql.to_string(), account.map(str::to_string), banner.to_string());
+    tokio::task::spawn_blocking(move || accounts::may_use(&sql, account.as_deref(), &banner)).await.unwrap_or(false)
+}
+
+
+async fn got_client(mut client : WebSocketClientStream, rooms : Arc<tokio::sync::Mutex<RoomManager>>){
+    // the lobby: until it connects, the client isn't in any room yet, so all it can do is look around, log in, or wait in the matchmaking queue
+    let database = match rooms.lock().await.database() { Some(database) => database, None => return }; // accounts and ratings live in the database every room shares
+    let sql = database.path.clone();
+    let mut account : Option<String> = None; // the banner they're logged in as
+    let mut attempts = 0; // logins and registrations so far; hashing passwords is expensive, so nobody gets to do it all day
+    let mut ticket : Option<(u64, String, tokio::sync::oneshot::Receiver<Matched>)> = None; // id, banner, where the match shows up
+    let (connect, room) = loop {
+        select! {
+            message = client.read::<ClientToServer>().fuse() => {
+                match message {
+                    Some(ClientToServer::Ping) => {
+                        let _ = client.send(ServerToClient::Pong).await;
+                    },
+                    Some(ClientToServer::ListRooms) => {
+                        let list = rooms.lock().await.list();
+                        for (name, population) in list {
+                            let _ = client.send(ServerToClient::RoomInfo (name, population)).await;
+                        }
+                    },
+                    Some(ClientToServer::Register (banner, password)) => {
+                        attempts += 1;
+                        if attempts > accounts::ATTEMPTS {
+                            let _ = client.send(ServerToClient::AccountError ("Too many tries. Reconnect and try again.".to_string())).await;
+                            continue;
+                        }
+                        let registered = {
+                            let (sql, banner) = (sql.clone(), banner.clone());
+                            tokio::task::spawn_blocking(move || accounts::register(&sql, &banner, &password)).await.unwrap_or_else(|error| Err(error.to_string()))
+                        };
+                        match registered {
+                            Ok(()) => {
+                                println!("Registered {}", banner);
+                                account = Some(banner.clone());
+                                let _ = client.send(ServerToClient::LoggedIn (banner)).await;
+                            },
+                            Err(error) => {
+                                let _ = client.send(ServerToClient::AccountError (error)).await;
+                            }
+                        }
+                    },
+                    Some(ClientToServer::Login (banner, password)) => {
+                        attempts += 1;
+                        if attempts > accounts::ATTEMPTS {
+                            let _ = client.send(ServerToClient::AccountError ("Too many tries. Reconnect and try again.".to_string())).await;
+                            continue;
+                        }
+                        let result = {
+                            let (sql, banner) = (sql.clone(), banner.clone());
+                            tokio::task::spawn_blocking(move || accounts::login(&sql, &banner, &password)).await.unwrap_or(LoginResult::NoAccount)
+                        };
+                        match result {
+                            LoginResult::Ok => {
+                                account = Some(banner.clone());
+                                let _ = client.send(ServerToClient::LoggedIn (banner)).await;
+                            },
+                            LoginResult::BadPassword => {
+                                let _ = client.send(ServerToClient::AccountError ("Wrong password.".to_string())).await;
+                            },
+                            LoginResult::NoAccount => {
+                                let _ = client.send(ServerToClient::AccountError (format!("Nobody's registered '{}'.", banner))).await;
+                            }
+                        }
+                    },
+                    Some(ClientToServer::GetRating (name, team)) => {
+                        let found = database.ask(move |connection| rating::load(connection, &name, team).map(|rating| (name, rating))).await;
+                        if let Some(Ok((name, (value, games)))) = found {
+                            let _ = client.send(ServerToClient::Rating (name, team, value, games)).await;
+                        }
+                    },
+                    Some(ClientToServer::Queue (banner, modes)) => {
+                        if let Some((id, _, _)) = ticket.take() { // requeueing with different modes
+                            rooms.lock().await.dequeue(id);
+                        }
+                        if !may_use(&sql, account.as_deref(), &banner).await {
+                            let _ = client.send(ServerToClient::BannerReserved (banner)).await;
+                            continue;
+                        }
+                        let modes : Vec<ClientMode> = modes.split(',').filter_map(|mode| matchmaking::mode_from_name(mode.trim())).collect();
+                        let rating = {
+                            let (sql, banner) = (sql.clone(), banner.clone());
+                            tokio::task::spawn_blocking(move || matchmaking::rating_of(&sql, &banner)).await.unwrap_or(rating::START)
+                        };
+                        let queued = if modes.len() > 0 { rooms.lock().await.enqueue(modes, rating) } else { None };
+                        match queued {
+                            Some((id, place, rx)) => {
+                                ticket = Some((id, banner, rx));
+                                let _ = client.send(ServerToClient::Queued (place as u32)).await;
+                            },
+                            None => {
+                                let _ = client.send(ServerToClient::Unqueued).await;
+                            }
+                        }
+                    },
+                    Some(ClientToServer::LeaveQueue) => {
+                        if let Some((id, _, _)) = ticket.take() {
+                            rooms.lock().await.dequeue(id);
+                        }
+                        let _ = client.send(ServerToClient::Unqueued).await;
+                    },
+                    Some(ClientToServer::Connect (password, banner, mode)) => {
+                        if !may_use(&sql, account.as_deref(), &banner).await {
+                            let _ = client.send(ServerToClient::BannerReserved (banner)).await;
+                            continue;
+                        }
+                        if let Some((id, _, _)) = ticket.take() {
+                            rooms.lock().await.dequeue(id);
+                        }
+                        break (ClientToServer::Connect (password, banner, mode), None);
+                    },
+                    Some(ClientToServer::ConnectRoom (password, banner, mode, room)) => {
+                        if !may_use(&sql, account.as_deref(), &banner).await {
+                            let _ = client.send(ServerToClient::BannerReserved (banner)).await;
+                            continue;
+                        }
+                        if let Some((id, _, _)) = ticket.take() {
+                            rooms.lock().await.dequeue(id);
+                        }
+                        break (ClientToServer::Connect (password, banner, mode), Some(room));
+                    },
+                    Some(_) | None => { // anything else is nonsense before connecting
+                        if let Some((id, _, _)) = ticket.take() {
+                            rooms.lock().await.dequeue(id);
+                        }
+                        return;
+                    }
+                }
+            },
+            matched = next_match(&mut ticket).fuse() => {
+                let (_, banner, _) = ticket.take().unwrap();
+                match matched {
+                    Some((room, mode)) => {
+                        let _ = client.send(ServerToClient::Matched (room.clone())).await;
+                        break (ClientToServer::Connect ("".to_string(), banner, matchmaking::mode_name(mode).to_string()), Some(room));
+                    },
+                    None => {
+                        let _ = client.send(ServerToClient::Unqueued).await;
+                    }
+                }
+            }
+        }
+    };
+    let assigned = rooms.lock().await.assign(room.as_deref()).map(|room| (room.broadcast_tx.clone(), room.commandset.clone()));
+    let (broadcaster, commandset) = match assigned {
+        Some(pipes) => pipes,
+        None => {
+            let _ = client.send(ServerToClient::NoSuchRoom (room.unwrap_or_default())).await;
+            return;
+        }
+    };
+    if commandset.send(ServerCommand::Connect).await.is_err() { // it shut down while they were picking it
+        let _ = client.send(ServerToClient::NoSuchRoom (room.unwrap_or_default())).await;
+        return;
+    }
     let mut receiver = broadcaster.subscribe();
     let mut moi = Client::new(client, commandset);
     let mut dead = false; // TODO: move this into Client
+    moi.handle(connect).await;
     /*
     if server.lock().await.passwordless {
         moi.send_singlet('p').await;
     }*/
     // passwordless broadcasts aren't really relevant any more
     'cliloop: loop {
+        if moi.kys { // the connect could've gone badly
+            break 'cliloop;
+        }
         select! {
             insult = moi.socket.read::<ClientToServer>().fuse() => {
                 match insult {
@@ -1519,7 +2733,8 @@ async fn got_client(client : WebSocketClientStream, broadcaster : tokio::sync::b
                         moi.send_protocol_message(ServerToClient::Tick (counter, match mode {
                             GameMode::Play => 0, 
                             GameMode::Strategy => 1,
-                            GameMode::Waiting => 2
+                            GameMode::Waiting => 2,
+                            GameMode::Over => 3
                         })).await;
                         /*for obj in &schlock.objects {
                             if obj.get_banner() == moi.banner && obj.do_stream_health() {
@@ -1534,9 +2749,6 @@ async fn got_client(client : WebSocketClientStream, broadcaster : tokio::sync::b
                             }
                         }*/
                     },
-                    Ok (ClientCommand::Christmas) => {
-                        moi.collect(1000000).await;
-                    },
                     Ok (ClientCommand::RoleCall) => {
                         if !dead {
                             match moi.commandah.send(ServerCommand::WinningBanner (moi.banner, moi.mode == ClientMode::RealTimeFighter)).await {
@@ -1554,6 +2766,9 @@ async fn got_client(client : WebSocketClientStream, broadcaster : tokio::sync::b
                         moi.send_protocol_message(ServerToClient::HealthUpdate (id, value)).await;
                     },
                     Ok (ClientCommand::Send (message)) => {
+                        if let ServerToClient::Delete (id) = &message { // dead pieces drop out of the selection
+                            moi.selection.retain(|selected| selected != id);
+                        }
                         moi.send_protocol_message(message).await;
                     },
                     Ok (ClientCommand::SendTo (message, banner)) => {
@@ -1571,11 +2786,6 @@ async fn got_client(client : WebSocketClientStream, broadcaster : tokio::sync::b
                             moi.send_protocol_message(message).await;
                         }
                     },*/
-                    Ok (ClientCommand::ScoreTo (banner, amount)) => {
-                        if moi.banner == banner {
-                            moi.collect(amount).await;
-                        }
-                    },
                     Ok (ClientCommand::CloseAll) => {
                         break 'cliloop;
                     },
@@ -1589,34 +2799,29 @@ async fn got_client(client : WebSocketClientStream, broadcaster : tokio::sync::b
                             moi.grant_a2a().await;
                         }
                     },
-                    Ok (ClientCommand::AttachToBanner (id, banner, price)) => {
-                        if banner == moi.banner {
-                            /*let mut reject : bool = false;
-                            if does_cost {
-                                let lock = server.lock().await;
-                                match lock.obj_lookup(id) {
-                                    Some (index) => {
-                                        if moi.score >= lock.objects[index].cost() {
-                                            moi.collect(-lock.objects[index].cost()).await;
-                                        }
-                                        else {
-                                            reject = true;
-                                        }
-                                    }
-                                    None => {
-                                        reject = true;
-                                    }
-                                }
-                            }*/
-                            if moi.score >= price || moi.is_superuser {
-                                moi.collect(-price).await;
-                                moi.send_protocol_message(ServerToClient::Add (id)).await;
-                            }
-                            else {
-                                println!("REJECTING OBJECT WE CAN'T AFFORD!");
-                                moi.commandah.send(ServerCommand::RejectObject (id)).await.expect("Failed!");
+                    Ok (ClientCommand::ShopGrant (to, thing)) => {
+                        if to == moi.banner {
+                            match thing {
+                                b'w' => {
+                                    moi.walls_cap += 2;
+                                    moi.walls_remaining += 2;
+                                },
+                                b'a' => {
+                                    moi.grant_a2a().await;
+                                },
+                                _ => {}
                             }
                         }
+                    },
+                    Ok (ClientCommand::AttachToBanner (id, banner)) => {
+                        if banner == moi.banner {
+                            moi.send_protocol_message(ServerToClient::Add (id)).await;
+                        }
+                    },
+                    Ok (ClientCommand::NewGame) => {
+                        moi.new_game();
+                        dead = false;
+                        moi.send_protocol_message(ServerToClient::NewGame).await;
                     }
                     //_ => {}
                     Err (_) => {
@@ -1649,10 +2854,11 @@ async fn got_client(client : WebSocketClientStream, broadcaster : tokio::sync::b
     if serverlock.is_io || serverlock.mode == GameMode::Waiting {
         serverlock.clear_of_banner(moi.banner);
     }*/
+    // the room might've been closed out from under us, in which case there's nobody left to tell
     if moi.is_ready {
-        moi.commandah.send(ServerCommand::ReadyState (false)).await.unwrap();
+        let _ = moi.commandah.send(ServerCommand::ReadyState (false)).await;
     }
-    moi.commandah.send(ServerCommand::Disconnect (moi.mode, moi.banner, moi.m_castle)).await.unwrap();
+    let _ = moi.commandah.send(ServerCommand::Disconnect (moi.mode, moi.banner, moi.m_castle)).await;
     println!("Dropped client");
 }
 
@@ -1685,6 +2891,25 @@ fn input(prompt: &str) -> String {
 }
 
 
+fn make_piece(tp : char) -> Option<Box<dyn GamePiece + Send + Sync>> { // the pieces that can be built by type code, for things like carrier loadouts
+    match tp {
+        'f' => Some(Box::new(BasicFighter::new())),
+        't' => Some(Box::new(TieFighter::new())),
+        's' => Some(Box::new(Sniper::new())),
+        'h' => Some(Box::new(Missile::new())),
+        'H' => Some(Box::new(LaserMissile::new())),
+        'n' => Some(Box::new(Nuke::new())),
+        'T' => Some(Box::new(Turret::new())),
+        'm' => Some(Box::new(MissileLaunchingSystem::new())),
+        'a' => Some(Box::new(AntiRTFBullet::new())),
+        'g' => Some(Box::new(GoldBar::new())),
+        'G' => Some(Box::new(GreenThumb::new())),
+        'C' => Some(Box::new(Chest::new())),
+        _ => None
+    }
+}
+
+
 #[derive(Copy, Clone, Debug)]
 pub enum PlaceCommand {
     SimplePlace (f32, f32, Option<usize>, u8), // x, y, banner, type
@@ -1724,13 +2949,31 @@ enum ServerCommand {
     Reset,
     Place (PlaceCommand),
     Move (usize, u32, f32, f32, f32, bool), // banner, id, x, y, a, is_superuser
+    QueueMove (usize, u32, MoveOrder, bool), // banner, id, order, is_superuser
+    SetPatrol (usize, u32, bool, bool), // banner, id, patrol, is_superuser
+    ClearOrders (usize, u32, bool), // banner, id, is_superuser
+    SetStance (usize, u32, Stance, bool), // banner, id, stance, is_superuser
+    SetTargeting (usize, u32, TargetingMode, Option<TargetingFilter>, bool), // banner, id, mode, filter (None keeps the current one), is_superuser
+    FocusTarget (usize, u32), // banner, id
+    RequireSight (usize, u32, bool, bool), // banner, id, require line of sight, is_superuser
+    Launch (usize, u32, bool), // banner, id, is_superuser
+    LaunchAll (usize, u32, bool), // banner, carrier, is_superuser
+    Recall (usize, u32, u32, bool), // banner, id, carrier, is_superuser
+    AssignBerth (usize, u32, usize, bool), // banner, id, berth, is_superuser
+    PlaceCarrier (f32, f32, Option<usize>, String, usize), // x, y, banner, loadout, who to tell if it gets rejected
+    QueueCargo (usize, u32, char, bool), // banner, carrier, type, is_superuser
+    SwapLoadout (usize, u32, String, bool), // banner, carrier, loadout, is_superuser
+    GroupMove (usize, Vec<u32>, f32, f32, f32, Formation, bool), // banner, ids, x, y, a, formation, is_superuser
     PilotRTF (u32, bool, bool, bool, bool, bool),
     Chat (usize, String, u8, Option<usize>),
-    UpgradeNextTier (u32, String),
+    Shop (usize, u32, u8), // banner, castle, item
     BeginConnection (String, String, String, tokio::sync::mpsc::Sender<InitialSetupCommand>), // password, banner, mode, outgoing pipe. god i've got to clean this up. vomiting face.
     WinningBanner (usize, bool), // report a banner that is alive and whether or not the player is an rtf. the server will do some routines.
     ReadyState (bool),
-    GodDisconnect (usize) // disconnect a player   
+    GodDisconnect (usize), // disconnect a player   
+    Shutdown, // the room is being closed: kick everyone out and stop ticking
+    ExportLeaderboard, // write the all-time board to <map>.leaderboard
+    GetRating (Option<usize>, String, bool) // who asked (None for the console), player or team name, whether it's a team
 }
 
 const WORDLIST : [&str; 10] = ["Robust", "Nancy", "Sovereign", "Green", "Tailor", "Water", "Freebase", "Neon", "Morlock", "Rastafari"];
@@ -1739,9 +2982,49 @@ const WORDLIST : [&str; 10] = ["Robust", "Nancy", "Sovereign", "Green", "Tailor"
 #[tokio::main]
 async fn main(){
     let args: Vec<String> = std::env::args().collect();
+    let rooms = Arc::new(tokio::sync::Mutex::new(RoomManager::new()));
+    for file in &args[1..] { // one room per config file; the first one is the default
+        if let Err(error) = rooms.lock().await.create(RoomManager::name_for(file), file.clone()) {
+            println!("Couldn't open a room for {}: {}", file, error);
+        }
+    }
+    let (port, headless) = match rooms.lock().await.default_room() {
+        Some(room) => (room.port, room.headless),
+        None => {
+            println!("No rooms! Usage: server-rust config.json [more configs, one room each]");
+            return;
+        }
+    };
+
+    tokio::task::spawn(matchmaker(rooms.clone()));
+    if !headless {
+        tokio::task::spawn(cli(rooms.clone()));
+        //std::thread::spawn(|| {
+        //    cli(commandset).await;
+        //});
+    }
+
+    let mut websocket_server = WebSocketServer::new(port, "MMOSG".to_string()).await;
+    println!("made it here");
+    loop {
+        let client = websocket_server.accept::<ClientToServer, ServerToClient>().await;
+        tokio::task::spawn(got_client(client, rooms.clone()));
+    }
+}
+
+
+async fn matchmaker(rooms : Arc<tokio::sync::Mutex<RoomManager>>) {
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        RoomManager::matchmake(&rooms).await;
+    }
+}
+
+
+fn new_server(config : &str, broadcast_tx : tokio::sync::broadcast::Sender<ClientCommand>) -> Result<Server, String> { // a fresh world for a room, with the config already loaded. a bad config is an Err, not a crash.
     let mut rng = rand::thread_rng();
     use rand::prelude::SliceRandom;
-    let (broadcast_tx, _rx) = tokio::sync::broadcast::channel(128); // Give _rx a name because we want it to live to the end of this function; if it doesn't, the tx will be invalidated. or something.
     let mut admin_password = String::new();
     for x in 0..4 {
         admin_password += WORDLIST.choose(&mut rng).unwrap();
@@ -1754,7 +3037,7 @@ async fn main(){
         mode                : GameMode::Waiting,
         admin_password,
         password            : "".to_string(),
-        config              : Some(Arc::new(Config::new(&args[1]))),
+        config              : Some(Arc::new(Config::new(config)?)),
         objects             : vec![],
         teams               : vec![],
         gamesize            : 5000.0,
@@ -1768,10 +3051,11 @@ async fn main(){
         autonomous          : None,
         is_io               : false,
         passwordless        : true,
-        broadcast_tx        : broadcast_tx.clone(),
+        broadcast_tx,
         living_players      : 0,
         isnt_rtf            : 0,
         times               : (120.0, 20.0),
+        postgame            : 15.0,
         clients_connected   : 0,
         is_headless         : false,
         permit_npcs         : true,
@@ -1780,537 +3064,647 @@ async fn main(){
         worldzone_count     : 1,
         zones               : Vec::new(),
         vvlm                : false,
-        readies             : 0
+        readies             : 0,
+        ledger              : Ledger::new(),
+        navgrid             : NavGrid::empty(),
+        focus_targets       : HashMap::new(),
+        capture             : None,
+        ctf                 : None,
+        clock               : None,
+        stats               : MatchStats::new(),
+        allegiance          : HashMap::new(),
+        sudden_death        : None,
+        overtime            : None,
+        eliminated          : false,
+        leaderboards        : Leaderboards::new(RoomManager::name_for(config)),
+        eliminations        : vec![],
+        history             : MatchHistory::new(),
+        database            : Database::detached()
     };
-    //rx.close().await;
-    server.load_config();
-    let port = server.port;
-    let headless = server.is_headless;
+    server.load_config()?;
     println!("Started server with password {}, terrain seed {}. The admin password is {}.", server.password, server.terrain_seed, server.admin_password);
-    let (commandset, mut commandget) = tokio::sync::mpsc::channel(32); // fancy number
-    let commandset_clone = commandset.clone();
-    tokio::task::spawn(async move {
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_millis((1000.0/FPS) as u64));
-        let connection = sqlite::open(server.sql.clone()).unwrap();
-        let init_query = "CREATE TABLE IF NOT EXISTS logins (banner TEXT, password TEXT, highscore INTEGER, wins INTEGER, losses INTEGER);CREATE TABLE IF NOT EXISTS teams_records (teamname TEXT, wins INTEGER, losses INTEGER);";
-        connection.execute(init_query).unwrap();
-        loop {
-            select! {
-                _ = interval.tick() => {
-                    use tokio::time::Instant;
-                    let start = Instant::now();
-                    server.mainloop();
-                    if start.elapsed() > tokio::time::Duration::from_millis((1000.0/FPS) as u64) {
-                        if server.self_test {
-                            println!("Failure at {} objects", server.objects.len());
-                            let obj = server.objects.len() - 1;
-                            server.objects[obj].damage(100.0);
-                            server.objects[obj].damage(100.0);
+    Ok(server)
+}
+
+
+async fn run_room(mut server : Server, mut commandget : tokio::sync::mpsc::Receiver<ServerCommand>, population : Arc<std::sync::atomic::AtomicU32>) { // the tick loop; every room gets its own task running this
+    let mut interval = tokio::time::interval(tokio::time::Duration::from_millis((1000.0/FPS) as u64));
+    server.leaderboards.invalidate(); // the boards load on the first tick
+    loop {
+        select! {
+            _ = interval.tick() => {
+                use tokio::time::Instant;
+                let start = Instant::now();
+                server.mainloop();
+                population.store(server.clients_connected as u32, std::sync::atomic::Ordering::Relaxed);
+                if start.elapsed() > tokio::time::Duration::from_millis((1000.0/FPS) as u64) {
+                    if server.self_test {
+                        println!("Failure at {} objects", server.objects.len());
+                        let obj = server.objects.len() - 1;
+                        server.objects[obj].damage(100.0);
+                        server.objects[obj].damage(100.0);
+                    }
+                    else {
+                        println!("LOOP OVERRUN!");
+                    }
+                }
+                else if server.self_test {
+                    server.place_random_rubble();
+                    if server.objects.len() % 10 == 0 {
+                        println!("reached {} objects", server.objects.len());
+                    }
+                }
+            },
+            command = commandget.recv() => {
+                //println!("honk");
+                match command {
+                    Some (ServerCommand::Shutdown) => {
+                        println!("Room closed");
+                        server.broadcast_tx.send(ClientCommand::CloseAll).unwrap();
+                        break;
+                    },
+                    Some (ServerCommand::ChristmasExclusive (n)) => {
+                        for obj in &mut server.objects {
+                            if obj.get_banner() == n { // this player is now the warrior of God. all current weapons are like a hundred times more powerful.
+                                obj.exposed_properties.health_properties.max_health *= 10.0;
+                                obj.exposed_properties.health_properties.health = obj.exposed_properties.health_properties.max_health;
+                                obj.exposed_properties.shooter_properties.counter /= 3;
+                            }
+                        }
+                        server.score_to(n, 100000, TransactionReason::Grant);
+                    },
+                    Some (ServerCommand::GodDisconnect (n)) => {
+                        server.broadcast_tx.send(ClientCommand::Close (n)).unwrap();
+                    }
+                    Some (ServerCommand::Start) => {
+                        server.start();
+                    },
+                    Some (ServerCommand::ReadyState (v)) => {
+                        if v {
+                            server.readies += 1;
                         }
                         else {
-                            println!("LOOP OVERRUN!");
+                            server.readies -= 1;
+                        }
+                        if server.readies >= server.living_players {
+                            if server.mode != GameMode::Play {
+                                server.readies = 0;
+                                server.set_mode(GameMode::Play);
+                            }
                         }
                     }
-                    else if server.self_test {
-                        server.place_random_rubble();
-                        if server.objects.len() % 10 == 0 {
-                            println!("reached {} objects", server.objects.len());
+                    Some (ServerCommand::Christmas) => {
+                        for banner in 1..server.banners.len() {
+                            server.score_to(banner, 1000000, TransactionReason::Grant);
                         }
                     }
-                },
-                command = commandget.recv() => {
-                    //println!("honk");
-                    match command {
-                        Some (ServerCommand::ChristmasExclusive (n)) => {
-                            for obj in &mut server.objects {
-                                if obj.get_banner() == n { // this player is now the warrior of God. all current weapons are like a hundred times more powerful.
-                                    obj.exposed_properties.health_properties.max_health *= 10.0;
-                                    obj.exposed_properties.health_properties.health = obj.exposed_properties.health_properties.max_health;
-                                    obj.exposed_properties.shooter_properties.counter /= 3;
-                                }
+                    Some (ServerCommand::RejectObject (id)) => {
+                        server.refund_obj(id);
+                        server.delete_obj(id);
+                    },
+                    Some (ServerCommand::Shop (banner, castle, thing)) => {
+                        let price = match thing {
+                            b'w' => 30,
+                            b'g' => 30,
+                            b's' => 40,
+                            b'f' => 70,
+                            b'h' => 150,
+                            b'a' => 100,
+                            _ => {
+                                println!("Invalid shop command {}", thing);
+                                continue;
                             }
-                            server.broadcast_tx.send(ClientCommand::ScoreTo (n, 100000)).unwrap();
-                        },
-                        Some (ServerCommand::GodDisconnect (n)) => {
-                            server.broadcast_tx.send(ClientCommand::Close (n)).unwrap();
+                        };
+                        if server.obj_lookup(castle).is_none() {
+                            continue; // no castle, no shopping
                         }
-                        Some (ServerCommand::Start) => {
-                            server.start();
-                        },
-                        Some (ServerCommand::ReadyState (v)) => {
-                            if v {
-                                server.readies += 1;
-                            }
-                            else {
-                                server.readies -= 1;
-                            }
-                            if server.readies >= server.living_players {
-                                if server.mode != GameMode::Play {
-                                    server.readies = 0;
-                                    server.set_mode(GameMode::Play);
-                                }
+                        if !server.charge(banner, price, TransactionReason::Shop) {
+                            continue;
+                        }
+                        match thing {
+                            b'g' => {
+                                server.upgrade_next_tier(castle, "b".to_string());
+                            },
+                            b's' => {
+                                server.upgrade_next_tier(castle, "s".to_string());
+                            },
+                            b'f' => {
+                                server.upgrade_next_tier(castle, "f".to_string());
+                            },
+                            b'h' => {
+                                server.upgrade_next_tier(castle, "h".to_string());
+                            },
+                            _ => { // walls and A2As are tracked by the client task
+                                server.broadcast_tx.send(ClientCommand::ShopGrant (banner, thing)).unwrap();
                             }
                         }
-                        Some (ServerCommand::Christmas) => {
-                            server.broadcast_tx.send(ClientCommand::Christmas).unwrap();
+                    },
+                    Some (ServerCommand::Flip) => {
+                        server.flip();
+                    },
+                    Some (ServerCommand::TeamNew (name, password)) => {
+                        server.new_team(name, password);
+                    },
+                    Some (ServerCommand::Autonomous (min_players, max_players, auto_timeout)) => {
+                        server.autonomous = Some((min_players, max_players, auto_timeout, auto_timeout));
+                    },
+                    Some (ServerCommand::Move (banner, id, x, y, a, superuser)) => {
+                        for object in &mut server.objects {
+                            if object.get_id() == id && (object.get_banner() == banner || superuser) {
+                                object.order_move(x, y, a);
+                            }
                         }
-                        Some (ServerCommand::RejectObject (id)) => {
-                            server.delete_obj(id);
-                        },
-                        Some (ServerCommand::Flip) => {
-                            server.flip();
-                        },
-                        Some (ServerCommand::TeamNew (name, password)) => {
-                            server.new_team(name, password);
-                        },
-                        Some (ServerCommand::Autonomous (min_players, max_players, auto_timeout)) => {
-                            server.autonomous = Some((min_players, max_players, auto_timeout, auto_timeout));
-                        },
-                        Some (ServerCommand::Move (banner, id, x, y, a, superuser)) => {
-                            for object in &mut server.objects {
-                                if object.get_id() == id && (object.get_banner() == banner || superuser) {
-                                    object.exposed_properties.goal_x = x;
-                                    object.exposed_properties.goal_y = y;
-                                    object.exposed_properties.goal_a = a;
-                                }
+                    },
+                    Some (ServerCommand::QueueMove (banner, id, order, superuser)) => {
+                        if let Some(index) = server.obj_lookup(id) {
+                            let owner = server.objects[index].get_banner();
+                            if owner == banner || superuser {
+                                server.objects[index].queue_order(order);
+                                server.send_to(ServerToClient::OrderQueued (id, order.x, order.y, order.a, order.attack), owner);
                             }
-                        },
-                        Some (ServerCommand::UpgradeNextTier (item, upgrade)) => {
-                            server.upgrade_next_tier(item, upgrade);
-                        },
-                        Some (ServerCommand::WinningBanner (banner, _is_rtf)) => {
-                            if !server.is_io && server.living_players == 1 {
-                                server.broadcast(ServerToClient::End (banner as u32));
-                                println!("{} won the game!", banner);
+                        }
+                    },
+                    Some (ServerCommand::GroupMove (banner, ids, x, y, a, formation, superuser)) => {
+                        server.group_move(banner, ids, x, y, a, formation, superuser);
+                    },
+                    Some (ServerCommand::SetPatrol (banner, id, patrol, superuser)) => {
+                        for object in &mut server.objects {
+                            if object.get_id() == id && (object.get_banner() == banner || superuser) {
+                                object.exposed_properties.orders.patrol = patrol;
                             }
-                        },
-                        Some (ServerCommand::PilotRTF (id, fire, left, right, airbrake, shoot)) => {
-                            match server.obj_lookup(id) {
-                                Some (index) => {
-                                    let mut thrust = 0.0;
-                                    let mut resistance = 1.0;
-                                    let mut angle_thrust = 0.0;
-                                    let is_better_turns = server.objects[index].upgrades.contains(&"f3".to_string());
-                                    let angle_thrust_power = if is_better_turns { 0.04 } else { 0.02 };
-                                    if fire {
-                                        thrust = 2.0;
-                                    }
-                                    if left {
-                                        angle_thrust -= angle_thrust_power;
-                                    }
-                                    if right {
-                                        angle_thrust += angle_thrust_power;
-                                    }
-                                    if airbrake {
-                                        resistance = 0.8;
-                                    }
-                                    server.objects[index].exposed_properties.shooter_properties.suppress = !shoot;
-                                    let thrust = Vector2::new_from_manda(thrust, server.objects[index].exposed_properties.physics.angle() - PI/2.0);
-                                    server.objects[index].exposed_properties.physics.velocity += thrust;
-                                    server.objects[index].exposed_properties.physics.velocity *= resistance;
-                                    server.objects[index].exposed_properties.physics.angle_v += angle_thrust;
-                                    server.objects[index].exposed_properties.physics.angle_v *= if is_better_turns { 0.8 } else { 0.9 };
-                                    server.objects[index].exposed_properties.physics.angle_v *= resistance;
-                                }
-                                None => {}
+                        }
+                    },
+                    Some (ServerCommand::ClearOrders (banner, id, superuser)) => {
+                        if let Some(index) = server.obj_lookup(id) {
+                            let owner = server.objects[index].get_banner();
+                            if owner == banner || superuser {
+                                server.objects[index].clear_orders();
+                                server.send_to(ServerToClient::OrdersCleared (id), owner);
                             }
-                        },
-                        Some (ServerCommand::BeginConnection (password, banner, mode, transmit)) => {
-                            let banner_id = server.banner_add(banner);
-                            if server.new_user_can_join() {
-                                let thing = server.authenticate(password, mode == "spectator");
-                                match thing {
-                                    AuthState::Error => {
-                                        println!("Authentication error");
-
-                                    },
-                                    AuthState::God => {
-                                        println!("========== God joined ==========");
-                                        transmit.send(InitialSetupCommand::Joined (AuthState::God)).await.unwrap();
-                                    },
-                                    AuthState::Single => {
-                                        println!("Single player joined");
-                                        transmit.send(InitialSetupCommand::Joined (AuthState::Single)).await.unwrap();
-                                    },
-                                    AuthState::Team (teamid, _) => {
-                                        println!("Player {} joined to team {}", server.banners[banner_id], server.banners[server.teams[teamid].banner_id]);
-                                        transmit.send(InitialSetupCommand::Joined (thing)).await.unwrap();
-                                    },
-                                    AuthState::Spectator => {
-                                        println!("Spectator joined");
-                                        transmit.send(InitialSetupCommand::Joined (AuthState::Spectator)).await.unwrap();
-                                    }
-                                }
+                        }
+                    },
+                    Some (ServerCommand::SetStance (banner, id, stance, superuser)) => {
+                        if let Some(index) = server.obj_lookup(id) {
+                            let owner = server.objects[index].get_banner();
+                            if (owner == banner || superuser) && server.objects[index].set_stance(stance) {
+                                server.send_to(ServerToClient::StanceChanged (id, stance.to_byte()), owner);
                             }
-                            else {
-                                transmit.send(InitialSetupCommand::Joined (AuthState::Spectator)).await.unwrap();
+                        }
+                    },
+                    Some (ServerCommand::SetTargeting (banner, id, mode, filter, superuser)) => {
+                        if let Some(index) = server.obj_lookup(id) {
+                            let owner = server.objects[index].get_banner();
+                            if owner == banner || superuser {
+                                server.objects[index].set_targeting(mode, filter);
                             }
-                            for object in &server.objects {
-                                transmit.send(InitialSetupCommand::Message (object.get_new_message())).await.unwrap();
-                                for upg in &object.upgrades {
-                                    transmit.send(InitialSetupCommand::Message (ServerToClient::UpgradeThing(object.exposed_properties.id, upg.clone()))).await.unwrap();
-                                }
+                        }
+                    },
+                    Some (ServerCommand::RequireSight (banner, id, require, superuser)) => {
+                        if let Some(index) = server.obj_lookup(id) {
+                            let owner = server.objects[index].get_banner();
+                            if owner == banner || superuser {
+                                server.objects[index].exposed_properties.targeting.require_sight = require;
                             }
-                            for i in 0..server.banners.len() {
-                                transmit.send(InitialSetupCommand::Message (ServerToClient::BannerAdd(i as u32, server.banners[i].clone()))).await.unwrap();
+                        }
+                    },
+                    Some (ServerCommand::Launch (banner, id, superuser)) => {
+                        if let Some(index) = server.obj_lookup(id) {
+                            if server.objects[index].get_banner() == banner || superuser {
+                                server.objects[index].launch();
                             }
-                            for i in 0..server.teams.len() {
-                                for j in 0..server.teams[i].members.len() {
-                                    transmit.send(InitialSetupCommand::Message (ServerToClient::BannerAddToTeam(server.teams[i].members[j] as u32, server.teams[i].banner_id as u32))).await.unwrap();
+                        }
+                    },
+                    Some (ServerCommand::LaunchAll (banner, carrier, superuser)) => {
+                        if let Some(index) = server.obj_lookup(carrier) {
+                            if server.objects[index].get_banner() == banner || superuser {
+                                for carried in server.objects[index].exposed_properties.carrier_properties.carrying.clone() {
+                                    if let Some(carried) = server.obj_lookup(carried) {
+                                        server.objects[carried].launch();
+                                    }
                                 }
                             }
-                            server.authenticateds += 1;
-                            transmit.send(InitialSetupCommand::Metadata (server.gamesize, banner_id)).await.unwrap();
-                            transmit.send(InitialSetupCommand::Finished).await.unwrap();
-                        },
-                        Some (ServerCommand::IoModeToggle) => {
-                            server.is_io = !server.is_io;
-                            println!("Set io mode to {}", server.is_io);
-                        },
-                        Some (ServerCommand::Disconnect (mode, banner, castle)) => {
-                            if castle.is_some() && server.obj_lookup(castle.unwrap()).is_some() {
-                                server.player_died(banner, mode == ClientMode::RealTimeFighter);
-                                println!("yuh");
-                            }
-                            server.clear_of_banner(banner);
-                            if server.clients_connected > 0 {
-                                server.clients_connected -= 1;
-                            }
-                            println!("Client disconnected! Connected clients: {}, living players: {}", server.clients_connected, server.living_players);
-                            if server.clients_connected == 0 {
-                                server.reset();
-                            }
-                        },
-                        Some (ServerCommand::Connect) => {
-                            server.clients_connected += 1;
-                        },
-                        Some (ServerCommand::Broadcast (message)) => {
-                            server.chat(message, 0, 6, None);
-                        },
-                        Some (ServerCommand::Chat (banner, message, priority, to_whom)) => {
-                            println!("{} says {}", server.banners[banner], message);
-                            server.chat(message, banner, priority, to_whom);
-                        }
-                        Some (ServerCommand::PasswordlessToggle) => {
-                            server.passwordless = !server.passwordless;
-                            server.broadcast(ServerToClient::SetPasswordless (server.passwordless));
-                            println!("Set passwordless mode to {}", server.passwordless);
-                        },
-                        /*Some (ServerCommand::LivePlayerInc (team, mode)) => {
-                            if mode != ClientMode::RealTimeFighter {
-                                println!("{:?} isn't an rtf", mode);
-                                server.isnt_rtf += 1;
-                            }
-                            server.living_players += 1;
-                            if team.is_some() {
-                                server.teams[team.unwrap()].live_count += 1;
-                            }
-                            println!("New live player. Living players: {}", server.living_players);
-                        },
-                        Some (ServerCommand::LivePlayerDec (team, mode)) => {
-                            if mode != ClientMode::RealTimeFighter {
-                                server.isnt_rtf -= 1;
+                        }
+                    },
+                    Some (ServerCommand::Recall (banner, id, carrier, superuser)) => {
+                        if let Some(index) = server.obj_lookup(id) {
+                            if server.objects[index].get_banner() == banner || superuser {
+                                server.recall(id, carrier);
                             }
-                            server.living_players -= 1;
-                            if team.is_some() {
-                                server.teams[team.unwrap()].live_count -= 1;
+                        }
+                    },
+                    Some (ServerCommand::AssignBerth (banner, id, berth, superuser)) => {
+                        if let Some(index) = server.obj_lookup(id) {
+                            if server.objects[index].get_banner() == banner || superuser {
+                                server.assign_berth(id, berth);
                             }
-                            println!("Player died. Living players: {}", server.living_players);
-                        },*/
-                        Some (ServerCommand::PrintBanners) => {
-                            println!("Current banners are,");
-                            for banner in 0..server.banners.len() {
-                                println!("{}: {}", banner, server.banners[banner]);
+                        }
+                    },
+                    Some (ServerCommand::QueueCargo (banner, carrier, tp, superuser)) => {
+                        if let Some(index) = server.obj_lookup(carrier) {
+                            if server.objects[index].get_banner() == banner || superuser {
+                                server.queue_cargo(carrier, tp);
                             }
                         }
-                        Some (ServerCommand::Nuke (banner)) => {
-                            for object in 0..server.objects.len() {
-                                if server.objects[object].get_banner() == banner {
-                                    let x = server.objects[object].exposed_properties.physics.cx();
-                                    let y = server.objects[object].exposed_properties.physics.cy();
-                                    server.place_nuke(x, y, 0.0, None);
+                    },
+                    Some (ServerCommand::SwapLoadout (banner, carrier, loadout, superuser)) => {
+                        if let Some(index) = server.obj_lookup(carrier) {
+                            let owner = server.objects[index].get_banner();
+                            if owner == banner || superuser {
+                                if let Err(reason) = server.swap_loadout(owner, carrier, &loadout) {
+                                    server.send_to(ServerToClient::PlacementRejected (reason), banner);
                                 }
                             }
-                        },
-                        Some (ServerCommand::Reset) => {
-                            server.reset()
-                        },
-                        Some (ServerCommand::Place (PlaceCommand::SimplePlace (x, y, banner, tp))) => {
-                            match tp {
-                                b'f' => {
-                                    server.place_basic_fighter(x, y, 0.0, banner);
-                                },
-                                b'm' => {
-                                    server.place_mls(x, y, 0.0, banner);
-                                },
-                                b'a' => {
-                                    server.place_antirtf_missile(x, y, 0.0, banner);
-                                },
-                                b'K' => {
-                                    server.place_carrier(x, y, 0.0, banner);
-                                },
-                                b't' => {
-                                    server.place_tie_fighter(x, y, 0.0, banner);
-                                },
-                                b's' => {
-                                    server.place_sniper(x, y, 0.0, banner);
-                                },
-                                b'h' => {
-                                    server.place_missile(x, y, 0.0, banner);
-                                },
-                                b'H' => {
-                                    server.place_laser_missile(x, y, 0.0, banner);
-                                }
-                                b'T' => {
-                                    server.place_turret(x, y, 0.0, banner);
-                                },
-                                b'n' => {
-                                    server.place_nuke(x, y, 0.0, banner);
-                                },
-                                b'w' => {
-                                    server.place_wall(x, y, banner);
-                                },
-                                b'S' => {
-                                    server.place_seed(x, y, banner);
-                                },
-                                b'G' => {
-                                    server.place_green_thumb(x, y, banner);
-                                },
-                                b'g' => {
-                                    server.place_gold_bar(x, y, banner);
+                        }
+                    },
+                    Some (ServerCommand::FocusTarget (banner, id)) => {
+                        server.set_focus_target(banner, id);
+                    },
+                    Some (ServerCommand::WinningBanner (banner, _is_rtf)) => {
+                        if !server.is_io && server.living_players == 1 {
+                            println!("{} won the game!", banner);
+                            server.end_game(Outcome::Winner (banner));
+                        }
+                    },
+                    Some (ServerCommand::PilotRTF (id, fire, left, right, airbrake, shoot)) => {
+                        match server.obj_lookup(id) {
+                            Some (index) => {
+                                let mut thrust = 0.0;
+                                let mut resistance = 1.0;
+                                let mut angle_thrust = 0.0;
+                                let is_better_turns = server.objects[index].upgrades.contains(&"f3".to_string());
+                                let angle_thrust_power = if is_better_turns { 0.04 } else { 0.02 };
+                                if fire {
+                                    thrust = 2.0;
                                 }
-                                _ => {
-                                    println!("The client attempted to place an object with invalid type {}", tp);
+                                if left {
+                                    angle_thrust -= angle_thrust_power;
+                                }
+                                if right {
+                                    angle_thrust += angle_thrust_power;
                                 }
+                                if airbrake {
+                                    resistance = 0.8;
+                                }
+                                server.objects[index].exposed_properties.shooter_properties.suppress = !shoot;
+                                let thrust = Vector2::new_from_manda(thrust, server.objects[index].exposed_properties.physics.angle() - PI/2.0);
+                                server.objects[index].exposed_properties.physics.velocity += thrust;
+                                server.objects[index].exposed_properties.physics.velocity *= resistance;
+                                server.objects[index].exposed_properties.physics.angle_v += angle_thrust;
+                                server.objects[index].exposed_properties.physics.angle_v *= if is_better_turns { 0.8 } else { 0.9 };
+                                server.objects[index].exposed_properties.physics.angle_v *= resistance;
                             }
+                            None => {}
                         }
-                        Some (ServerCommand::Place (PlaceCommand::CarrierVariant (x, y, banner, variant))) => {
-                            let carrier = server.place_carrier(x, y, 0.0, banner);
-                            let carrier = server.obj_lookup(carrier).unwrap();
-                            /*match variant {
-                                1 => {
-                                    let turret = server.place_turret(x, y, 0.0, banner);
-                                    server.into_berth(carrier, turret, 0);
-                                    let turret = server.place_turret(x, y, 0.0, banner);
-                                    server.into_berth(carrier, turret, 1);
-                                    let turret = server.place_turret(x, y, 0.0, banner);
-                                    server.into_berth(carrier, turret, 8);
-                                    let turret = server.place_turret(x, y, 0.0, banner);
-                                    server.into_berth(carrier, turret, 9);
-
-                                    let hyper = server.place_missile(x, y, 0.0, banner);
-                                    server.into_berth(carrier, hyper, 2);
-                                    let hyper = server.place_missile(x, y, 0.0, banner);
-                                    server.into_berth(carrier, hyper, 3);
-                                    let hyper = server.place_missile(x, y, 0.0, banner);
-                                    server.into_berth(carrier, hyper, 6);
-                                    let hyper = server.place_missile(x, y, 0.0, banner);
-                                    server.into_berth(carrier, hyper, 7);
-
-                                    let nuke = server.place_nuke(x, y, 0.0, banner);
-                                    server.into_berth(carrier, nuke, 4);
-                                    let nuke = server.place_nuke(x, y, 0.0, banner);
-                                    server.into_berth(carrier, nuke, 5);
-                                },
-                                2 => {
-                                    let turret = server.place_turret(x, y, 0.0, banner);
-                                    server.into_berth(carrier, turret, 0);
-                                    let turret = server.place_turret(x, y, 0.0, banner);
-                                    server.into_berth(carrier, turret, 1);
-
-                                    let hyper = server.place_missile(x, y, 0.0, banner);
-                                    server.into_berth(carrier, hyper, 4);
-                                    let hyper = server.place_missile(x, y, 0.0, banner);
-                                    server.into_berth(carrier, hyper, 5);
-                                    let hyper = server.place_missile(x, y, 0.0, banner);
-                                    server.into_berth(carrier, hyper, 6);
-                                    let hyper = server.place_missile(x, y, 0.0, banner);
-                                    server.into_berth(carrier, hyper, 7);
-                                    let hyper = server.place_missile(x, y, 0.0, banner);
-                                    server.into_berth(carrier, hyper, 8);
-                                    let hyper = server.place_missile(x, y, 0.0, banner);
-                                    server.into_berth(carrier, hyper, 9);
-
-                                    let nuke = server.place_nuke(x, y, 0.0, banner);
-                                    server.into_berth(carrier, nuke, 2);
-                                    let nuke = server.place_nuke(x, y, 0.0, banner);
-                                    server.into_berth(carrier, nuke, 3);
-                                },
-                                3 => {
-                                    for i in 0..10 {
-                                        let thing = if i % 2 == 0 { server.place_missile(x, y, 0.0, banner) } else { server.place_turret(x, y, 0.0, banner) };
-                                        server.into_berth(carrier, thing, i);
-                                    }
+                    },
+                    Some (ServerCommand::BeginConnection (password, banner, mode, transmit)) => {
+                        let banner_id = server.banner_add(banner);
+                        if server.new_user_can_join() {
+                            let thing = server.authenticate(password, mode == "spectator");
+                            match thing {
+                                AuthState::Error => {
+                                    println!("Authentication error");
+
                                 },
-                                4 => {
-                                    for i in 0..10 {
-                                        let thing = server.place_missile(x, y, 0.0, banner);
-                                        server.into_berth(carrier, thing, i);
-                                    }
+                                AuthState::God => {
+                                    println!("========== God joined ==========");
+                                    let _ = transmit.send(InitialSetupCommand::Joined (AuthState::God)).await; // they might have hung up already
                                 },
-                                5 => {
-                                    for i in 0..4 {
-                                        let tie = server.place_tie_fighter(x, y, 0.0, banner);
-                                        server.into_berth(carrier, tie, i);
-                                    }
-                                    for i in 4..10 {
-                                        let hyper = server.place_missile(x, y, 0.0, banner);
-                                        server.into_berth(carrier, hyper, i);
-                                    }
+                                AuthState::Single => {
+                                    println!("Single player joined");
+                                    let _ = transmit.send(InitialSetupCommand::Joined (AuthState::Single)).await;
                                 },
-                                6 => {
-                                    for i in 0..10 {
-                                        let thing = server.place_tie_fighter(x, y, 0.0, banner);
-                                        server.into_berth(carrier, thing, i);
-                                    }
+                                AuthState::Team (teamid, _) => {
+                                    println!("Player {} joined to team {}", server.banners[banner_id], server.banners[server.teams[teamid].banner_id]);
+                                    let _ = transmit.send(InitialSetupCommand::Joined (thing)).await;
                                 },
-                                _ => {
-                                    println!("Unrecognized carrier variant {}", variant);
-                                }
-                            }*/
-                            // bitbanged
-                            for i in 0..10_usize { // 10 berths
-                                let bit = 9_u32.pow(i as u32);
-                                let word = (variant / bit) % 9; // it's complex math. don't worry your sweet wittle head about it.
-                                let item = match word {
-                                    1 => {
-                                        server.place_missile(x, y, 0.0, banner)
-                                    },
-                                    2 => {
-                                        server.place_basic_fighter(x, y, 0.0, banner)
-                                    },
-                                    3 => {
-                                        server.place_tie_fighter(x, y, 0.0, banner)
-                                    },
-                                    4 => {
-                                        server.place_sniper(x, y, 0.0, banner)
-                                    },
-                                    5 => {
-                                        server.place_nuke(x, y, 0.0, banner)
-                                    },
-                                    6 => {
-                                        server.place_turret(x, y, 0.0, banner)
-                                    },
-                                    7 => {
-                                        server.place_mls(x, y, 0.0, banner)
-                                    },
-                                    8 => {
-                                        server.place_gold_bar(x, y, banner)
-                                    }
-                                    _ => {
-                                        0
-                                    }
-                                };
-                                if item != 0 {
-                                    server.into_berth(carrier, item, i);
+                                AuthState::Spectator => {
+                                    println!("Spectator joined");
+                                    let _ = transmit.send(InitialSetupCommand::Joined (AuthState::Spectator)).await;
                                 }
                             }
                         }
-                        Some (ServerCommand::Place (PlaceCommand::Fort (x, y, banner, target))) => {
-                            match server.obj_lookup(target) {
-                                Some(index) => {
-                                    let fort = server.place_fort(x, y, 0.0, banner);
-                                    server.objects[index].add_fort(fort);
-                                }
-                                None => {}
+                        else {
+                            let _ = transmit.send(InitialSetupCommand::Joined (AuthState::Spectator)).await;
+                        }
+                        for object in &server.objects {
+                            let _ = transmit.send(InitialSetupCommand::Message (object.get_new_message())).await;
+                            for upg in &object.upgrades {
+                                let _ = transmit.send(InitialSetupCommand::Message (ServerToClient::UpgradeThing(object.exposed_properties.id, upg.clone()))).await;
                             }
                         }
-                        Some (ServerCommand::SelfTest) => {
-                            server.self_test = true;
+                        for i in 0..server.banners.len() {
+                            let _ = transmit.send(InitialSetupCommand::Message (ServerToClient::BannerAdd(i as u32, server.banners[i].clone()))).await;
                         }
-                        Some (ServerCommand::Place (PlaceCommand::Castle (x, y, mode, banner, team))) => {
-                            if server.mode != GameMode::Waiting && !server.is_io {
-                                continue;
+                        for i in 0..server.teams.len() {
+                            for j in 0..server.teams[i].members.len() {
+                                let _ = transmit.send(InitialSetupCommand::Message (ServerToClient::BannerAddToTeam(server.teams[i].members[j] as u32, server.teams[i].banner_id as u32))).await;
                             }
-                            server.costs = false;
-                            let castle = server.place_castle(x, y, mode == ClientMode::RealTimeFighter, Some(banner));
-                            server.broadcast_tx.send(ClientCommand::SetCastle (banner, castle)).unwrap();
-                            match mode {
-                                ClientMode::Normal => {
-                                    server.place_basic_fighter(x - 200.0, y, PI, Some(banner));
-                                    server.place_basic_fighter(x + 200.0, y, 0.0, Some(banner));
-                                    server.place_basic_fighter(x, y - 200.0, 0.0, Some(banner));
-                                    server.place_basic_fighter(x, y + 200.0, 0.0, Some(banner));
-                                    server.broadcast_tx.send(ClientCommand::ScoreTo (banner, 100)).unwrap();
-                                },
-                                ClientMode::RealTimeFighter => {
-                                    server.place_basic_fighter(x - 100.0, y, PI, Some(banner));
-                                    server.place_basic_fighter(x + 100.0, y, 0.0, Some(banner));
-                                    server.broadcast_tx.send(ClientCommand::GrantA2A (banner)).unwrap();
-                                },
-                                ClientMode::Defense => {
-                                    server.place_basic_fighter(x - 200.0, y, PI, Some(banner));
-                                    server.place_basic_fighter(x + 200.0, y, 0.0, Some(banner));
-                                    server.place_turret(x, y - 200.0, 0.0, Some(banner));
-                                    server.place_turret(x, y + 200.0, 0.0, Some(banner));
-                                    server.broadcast_tx.send(ClientCommand::ScoreTo (banner, 25)).unwrap();
-                                },
-                                _ => {
-
-                                }
+                        }
+                        for message in server.leaderboards.messages() {
+                            let _ = transmit.send(InitialSetupCommand::Message (message)).await;
+                        }
+                        server.authenticateds += 1;
+                        let _ = transmit.send(InitialSetupCommand::Metadata (server.gamesize, banner_id)).await;
+                        let _ = transmit.send(InitialSetupCommand::Finished).await;
+                    },
+                    Some (ServerCommand::IoModeToggle) => {
+                        server.is_io = !server.is_io;
+                        println!("Set io mode to {}", server.is_io);
+                    },
+                    Some (ServerCommand::Disconnect (mode, banner, castle)) => {
+                        if castle.is_some() && server.obj_lookup(castle.unwrap()).is_some() {
+                            server.player_died(banner, mode == ClientMode::RealTimeFighter);
+                            println!("yuh");
+                        }
+                        server.clear_of_banner(banner);
+                        if server.clients_connected > 0 {
+                            server.clients_connected -= 1;
+                        }
+                        println!("Client disconnected! Connected clients: {}, living players: {}", server.clients_connected, server.living_players);
+                        if server.clients_connected == 0 {
+                            server.reset();
+                        }
+                    },
+                    Some (ServerCommand::Connect) => {
+                        server.clients_connected += 1;
+                    },
+                    Some (ServerCommand::Broadcast (message)) => {
+                        server.chat(message, 0, 6, None);
+                    },
+                    Some (ServerCommand::Chat (banner, message, priority, to_whom)) => {
+                        println!("{} says {}", server.banners[banner], message);
+                        server.chat(message, banner, priority, to_whom);
+                    }
+                    Some (ServerCommand::PasswordlessToggle) => {
+                        server.passwordless = !server.passwordless;
+                        server.broadcast(ServerToClient::SetPasswordless (server.passwordless));
+                        println!("Set passwordless mode to {}", server.passwordless);
+                    },
+                    /*Some (ServerCommand::LivePlayerInc (team, mode)) => {
+                        if mode != ClientMode::RealTimeFighter {
+                            println!("{:?} isn't an rtf", mode);
+                            server.isnt_rtf += 1;
+                        }
+                        server.living_players += 1;
+                        if team.is_some() {
+                            server.teams[team.unwrap()].live_count += 1;
+                        }
+                        println!("New live player. Living players: {}", server.living_players);
+                    },
+                    Some (ServerCommand::LivePlayerDec (team, mode)) => {
+                        if mode != ClientMode::RealTimeFighter {
+                            server.isnt_rtf -= 1;
+                        }
+                        server.living_players -= 1;
+                        if team.is_some() {
+                            server.teams[team.unwrap()].live_count -= 1;
+                        }
+                        println!("Player died. Living players: {}", server.living_players);
+                    },*/
+                    Some (ServerCommand::GetRating (asker, name, team)) => {
+                        let broadcast_tx = server.broadcast_tx.clone();
+                        let _ = server.database.read(move |connection| match (rating::load(connection, &name, team), asker) { // the database thread answers them itself, so the tick loop doesn't wait
+                            (Ok((value, games)), Some(banner)) => {
+                                let _ = broadcast_tx.send(ClientCommand::SendTo (ServerToClient::Rating (name, team, value, games), banner));
+                            },
+                            (Ok((value, games)), None) => {
+                                println!("{} is rated {:.0} after {} games", name, value, games);
+                            },
+                            (Err(error), _) => {
+                                println!("Couldn't look up the rating for {}: {}", name, error);
+                            }
+                        });
+                    },
+                    Some (ServerCommand::ExportLeaderboard) => {
+                        let path = format!("{}.leaderboard", server.leaderboards.map);
+                        match leaderboard::write_leaderboard(&path, &server.leaderboards.board(leaderboard::ALL_TIME)) {
+                            Ok(()) => {
+                                println!("Wrote the all-time leaderboard to {}", path);
+                            },
+                            Err(error) => {
+                                println!("Couldn't write {}: {}", path, error);
+                            }
+                        }
+                    },
+                    Some (ServerCommand::PrintBanners) => {
+                        println!("Current banners are,");
+                        for banner in 0..server.banners.len() {
+                            println!("{}: {}", banner, server.banners[banner]);
+                        }
+                    }
+                    Some (ServerCommand::Nuke (banner)) => {
+                        for object in 0..server.objects.len() {
+                            if server.objects[object].get_banner() == banner {
+                                let x = server.objects[object].exposed_properties.physics.cx();
+                                let y = server.objects[object].exposed_properties.physics.cy();
+                                server.place_nuke(x, y, 0.0, None);
+                            }
+                        }
+                    },
+                    Some (ServerCommand::Reset) => {
+                        server.reset()
+                    },
+                    Some (ServerCommand::Place (PlaceCommand::SimplePlace (x, y, banner, tp))) => {
+                        match tp {
+                            b'f' => {
+                                server.place_basic_fighter(x, y, 0.0, banner);
+                            },
+                            b'm' => {
+                                server.place_mls(x, y, 0.0, banner);
+                            },
+                            b'a' => {
+                                server.place_antirtf_missile(x, y, 0.0, banner);
+                            },
+                            b'K' => {
+                                server.place_carrier(x, y, 0.0, banner);
+                            },
+                            b't' => {
+                                server.place_tie_fighter(x, y, 0.0, banner);
+                            },
+                            b's' => {
+                                server.place_sniper(x, y, 0.0, banner);
+                            },
+                            b'h' => {
+                                server.place_missile(x, y, 0.0, banner);
+                            },
+                            b'H' => {
+                                server.place_laser_missile(x, y, 0.0, banner);
                             }
-                            // shamelessly copy/pasted from LivePlayerInc. clean up when the dust settles!
-                            server.costs = true;
-                            if mode != ClientMode::RealTimeFighter {
-                                println!("{:?} isn't an rtf", mode);
-                                server.isnt_rtf += 1;
+                            b'T' => {
+                                server.place_turret(x, y, 0.0, banner);
+                            },
+                            b'n' => {
+                                server.place_nuke(x, y, 0.0, banner);
+                            },
+                            b'w' => {
+                                server.place_wall(x, y, banner);
+                            },
+                            b'S' => {
+                                server.place_seed(x, y, banner);
+                            },
+                            b'G' => {
+                                server.place_green_thumb(x, y, banner);
+                            },
+                            b'g' => {
+                                server.place_gold_bar(x, y, banner);
                             }
-                            server.living_players += 1;
-                            if team.is_some() {
-                                server.teams[team.unwrap()].members.push(banner);
-                                server.broadcast(ServerToClient::BannerAddToTeam (banner as u32, server.teams[team.unwrap()].banner_id as u32));
+                            _ => {
+                                println!("The client attempted to place an object with invalid type {}", tp);
                             }
-                            println!("New live player. Living players: {}", server.living_players);
-                        },
-                        Some (ServerCommand::Place (PlaceCommand::A2A (castle, target, banner))) => {
-                            let target_i = match server.obj_lookup(target) { Some(i) => i, None => continue };
-                            let castle_i = match server.obj_lookup(castle) { Some(i) => i, None => continue };
-                            let obj_vec = server.objects[target_i].exposed_properties.physics.vector_position();
-                            if (server.objects[castle_i].exposed_properties.physics.vector_position() - obj_vec).magnitude() < 1500.0 {
-                                let off_ang = functions::coterminal(server.objects[castle_i].exposed_properties.physics.angle() - (server.objects[castle_i].exposed_properties.physics.vector_position() - obj_vec).angle(), PI * 2.0);
-                                let pos = server.objects[castle_i].exposed_properties.physics.vector_position() + Vector2::new_from_manda(if off_ang > PI { 50.0 } else { -50.0 }, server.objects[castle_i].exposed_properties.physics.angle());
-                                let launchangle = server.objects[castle_i].exposed_properties.physics.angle() - PI/2.0; // rust requires this to be explicit because of the dumbass borrow checker
-                                let a2a_id = server.place_air2air(pos.x, pos.y, launchangle, target, Some(banner));
-                                let a2a_i = server.obj_lookup(a2a_id).unwrap(); // it's certain to exist
-                                server.objects[a2a_i].exposed_properties.physics.velocity = server.objects[castle_i].exposed_properties.physics.velocity;
+                        }
+                    }
+                    Some (ServerCommand::Place (PlaceCommand::CarrierVariant (x, y, banner, variant))) => {
+                        if let Err(reason) = server.place_carrier_loadout(x, y, banner, &Carrier::loadout_from_variant(variant)) {
+                            println!("Refused to place carrier variant {}: {}", variant, reason);
+                            if let Some(banner) = banner {
+                                server.send_to(ServerToClient::PlacementRejected (reason), banner);
                             }
-                        },
-                        None => {
-                            println!("The channel handling server control was disconnected!");
                         }
-                        /*Err (TryRecvError::Disconnected) => {
-                            println!("The channel handling server control was disconnected!");
-                        },
-                        Err (TryRecvError::Empty) => {} // Do nothing; we expect it to be empty quite often.*/
                     }
+                    Some (ServerCommand::PlaceCarrier (x, y, banner, loadout, requester)) => {
+                        if let Err(reason) = server.place_carrier_loadout(x, y, banner, &loadout) {
+                            server.send_to(ServerToClient::PlacementRejected (reason), requester);
+                        }
+                    }
+                    Some (ServerCommand::Place (PlaceCommand::Fort (x, y, banner, target))) => {
+                        match server.obj_lookup(target) {
+                            Some(index) => {
+                                let fort = server.place_fort(x, y, 0.0, banner);
+                                server.objects[index].add_fort(fort);
+                            }
+                            None => {}
+                        }
+                    }
+                    Some (ServerCommand::SelfTest) => {
+                        server.self_test = true;
+                    }
+                    Some (ServerCommand::Place (PlaceCommand::Castle (x, y, mode, banner, team))) => {
+                        if server.mode != GameMode::Waiting && !server.is_io {
+                            continue;
+                        }
+                        server.costs = false;
+                        let castle = server.place_castle(x, y, mode == ClientMode::RealTimeFighter, Some(banner));
+                        if castle != 0 {
+                            server.history.join(banner, mode); // this is what makes them a player in this game, rather than a spectator
+                        }
+                        server.broadcast_tx.send(ClientCommand::SetCastle (banner, castle)).unwrap();
+                        match mode {
+                            ClientMode::Normal => {
+                                server.place_basic_fighter(x - 200.0, y, PI, Some(banner));
+                                server.place_basic_fighter(x + 200.0, y, 0.0, Some(banner));
+                                server.place_basic_fighter(x, y - 200.0, 0.0, Some(banner));
+                                server.place_basic_fighter(x, y + 200.0, 0.0, Some(banner));
+                                server.score_to(banner, 100, TransactionReason::Grant);
+                            },
+                            ClientMode::RealTimeFighter => {
+                                server.place_basic_fighter(x - 100.0, y, PI, Some(banner));
+                                server.place_basic_fighter(x + 100.0, y, 0.0, Some(banner));
+                                server.broadcast_tx.send(ClientCommand::GrantA2A (banner)).unwrap();
+                            },
+                            ClientMode::Defense => {
+                                server.place_basic_fighter(x - 200.0, y, PI, Some(banner));
+                                server.place_basic_fighter(x + 200.0, y, 0.0, Some(banner));
+                                server.place_turret(x, y - 200.0, 0.0, Some(banner));
+                                server.place_turret(x, y + 200.0, 0.0, Some(banner));
+                                server.score_to(banner, 25, TransactionReason::Grant);
+                            },
+                            _ => {
+
+                            }
+                        }
+                        // shamelessly copy/pasted from LivePlayerInc. clean up when the dust settles!
+                        server.costs = true;
+                        if mode != ClientMode::RealTimeFighter {
+                            println!("{:?} isn't an rtf", mode);
+                            server.isnt_rtf += 1;
+                        }
+                        server.living_players += 1;
+                        if team.is_some() {
+                            server.teams[team.unwrap()].members.push(banner);
+                            server.allegiance.insert(banner, server.teams[team.unwrap()].banner_id);
+                            server.broadcast(ServerToClient::BannerAddToTeam (banner as u32, server.teams[team.unwrap()].banner_id as u32));
+                        }
+                        println!("New live player. Living players: {}", server.living_players);
+                    },
+                    Some (ServerCommand::Place (PlaceCommand::A2A (castle, target, banner))) => {
+                        let target_i = match server.obj_lookup(target) { Some(i) => i, None => continue };
+                        let castle_i = match server.obj_lookup(castle) { Some(i) => i, None => continue };
+                        let obj_vec = server.objects[target_i].exposed_properties.physics.vector_position();
+                        if (server.objects[castle_i].exposed_properties.physics.vector_position() - obj_vec).magnitude() < 1500.0 {
+                            let off_ang = functions::coterminal(server.objects[castle_i].exposed_properties.physics.angle() - (server.objects[castle_i].exposed_properties.physics.vector_position() - obj_vec).angle(), PI * 2.0);
+                            let pos = server.objects[castle_i].exposed_properties.physics.vector_position() + Vector2::new_from_manda(if off_ang > PI { 50.0 } else { -50.0 }, server.objects[castle_i].exposed_properties.physics.angle());
+                            let launchangle = server.objects[castle_i].exposed_properties.physics.angle() - PI/2.0; // rust requires this to be explicit because of the dumbass borrow checker
+                            let a2a_id = server.place_air2air(pos.x, pos.y, launchangle, target, Some(banner));
+                            let a2a_i = server.obj_lookup(a2a_id).unwrap(); // it's certain to exist
+                            server.objects[a2a_i].exposed_properties.physics.velocity = server.objects[castle_i].exposed_properties.physics.velocity;
+                        }
+                    },
+                    None => {
+                        println!("The channel handling server control was disconnected!");
+                    }
+                    /*Err (TryRecvError::Disconnected) => {
+                        println!("The channel handling server control was disconnected!");
+                    },
+                    Err (TryRecvError::Empty) => {} // Do nothing; we expect it to be empty quite often.*/
                 }
             }
         }
-    });
-
-    if !headless {
-        tokio::task::spawn(cli(commandset));
-        //std::thread::spawn(|| {
-        //    cli(commandset).await;
-        //});
-    }
-
-    let mut websocket_server = WebSocketServer::new(port, "MMOSG".to_string()).await;
-    println!("made it here");
-    loop {
-        let client = websocket_server.accept::<ClientToServer, ServerToClient>().await;
-        tokio::task::spawn(got_client(client, broadcast_tx.clone(), commandset_clone.clone()));
     }
 }
 
 
-async fn cli(commandset : tokio::sync::mpsc::Sender<ServerCommand>) {
+async fn cli(rooms : Arc<tokio::sync::Mutex<RoomManager>>) {
     use tokio::io::AsyncBufReadExt;
     let buffer = tokio::io::BufReader::new(tokio::io::stdin());
     let mut lines = buffer.lines();
+    let mut current = match rooms.lock().await.default_room() { Some(room) => room.name.clone(), None => return }; // the room every other command goes to
     loop {
         let command = match lines.next_line().await { Ok(Some(line)) => line, Ok(None) => continue, Err(_) => continue };
         let to_send = match command.as_str() {
+            "rooms" => {
+                for (name, population) in rooms.lock().await.list() {
+                    println!("{}{}: {} clients", if name == current { "* " } else { "  " }, name, population);
+                }
+                continue;
+            },
+            "room new" => {
+                let name = input("Room name: ");
+                let config = input("Config file: ");
+                match RoomManager::create_shared(&rooms, name.clone(), config).await {
+                    Ok(()) => {
+                        println!("Opened room {}", name);
+                    },
+                    Err(error) => {
+                        println!("Couldn't open the room: {}", error);
+                    }
+                }
+                continue;
+            },
+            "room use" => {
+                let name = input("Room name: ");
+                if rooms.lock().await.get(&name).is_some() {
+                    current = name;
+                }
+                else {
+                    println!("No such room.");
+                }
+                continue;
+            },
+            "room close" => {
+                let name = input("Room name: ");
+                match RoomManager::destroy_shared(&rooms, &name).await {
+                    Ok(()) => {
+                        println!("Closed room {}", name);
+                    },
+                    Err(error) => {
+                        println!("Couldn't close the room: {}", error);
+                    }
+                }
+                if name == current {
+                    current = match rooms.lock().await.default_room() { Some(room) => room.name.clone(), None => return };
+                }
+                continue;
+            },
             "start" => { // Notes: starting causes the deadlock, but flipping doesn't, so the problem isn't merely locking/unlocking.
                 ServerCommand::Start
             },
@@ -2371,12 +3765,23 @@ async fn cli(commandset : tokio::sync::mpsc::Sender<ServerCommand>) {
             "selftest" => {
                 ServerCommand::SelfTest
             }
+            "leaderboard export" => {
+                ServerCommand::ExportLeaderboard
+            }
+            "rating" => {
+                let name = input("Player or team name: ");
+                let team = input("Is that a team? (y/n): ") == "y";
+                ServerCommand::GetRating (None, name, team)
+            }
             _ => {
                 println!("Invalid command.");
                 continue;
             }
         };
-        commandset.send(to_send).await.expect("OOOOOOPS");
+        let commandset = match rooms.lock().await.get(&current) { Some(room) => room.commandset.clone(), None => continue };
+        if commandset.send(to_send).await.is_err() {
+            println!("{} has shut down.", current);
+        }
     }
 }
 
@@ -2387,7 +3792,33 @@ pub mod tests {
     use crate::gamepiece::npc;
     use crate::BoxShape;
     use crate::functions::*;
+    use crate::ledger::{Ledger, TransactionReason};
+    use crate::pathfinding::NavGrid;
+    use crate::formation::{Formation, assign_slots};
+    use crate::gamepiece::{GamePieceBase, TargetingFilter, Stance, MoveOrder, Castle};
+    use crate::gamepiece::fighters::BasicFighter;
+    use crate::gamepiece::misc::{Carrier, AntiRTFBullet};
+    use crate::gamepiece::zones::Flag;
+    use crate::{Server, ClientCommand, GameMode, new_server, make_piece};
+    use crate::gamepiece::nexus::SpawnTable;
+    use crate::capture::CaptureMode;
+    use crate::stats::{Outcome, MatchStats};
+    use crate::overtime::{Overtime, OvertimeRules, SuddenDeath};
+    use crate::ctf::CtfMode;
+    use crate::rooms::RoomManager;
+    use crate::matchmaking::{MatchQueue, Batch};
+    use crate::accounts::{self, LoginResult};
+    use crate::leaderboard::{self, Entry};
+    use crate::rating;
+    use crate::history::{MatchHistory, KillEvent};
+    use crate::ClientMode;
+    use crate::matchclock::{MatchClock, Standing, most_points};
     use std::f32::consts::PI;
+    fn test_server() -> (Server, tokio::sync::broadcast::Receiver<ClientCommand>) { // a real world with nobody in it. hang on to the receiver, or broadcasts fail.
+        let (broadcast_tx, receiver) = tokio::sync::broadcast::channel(1024);
+        (new_server("default_world.json", broadcast_tx).unwrap(), receiver)
+    }
+
     #[test]
     fn check_vector_creation() {
         let vec = Vector2::new_from_manda(1.0, 0.0);
@@ -2455,8 +3886,519 @@ pub mod tests {
         assert!(shape.contains(Vector2::new(-4.0, 0.0)));
     }
 
-    /*#[test]
+    #[test]
+    fn check_box_segment() {
+        let shape = BoxShape {
+            x : 0.0,
+            y : 0.0,
+            w : 10.0,
+            h : 10.0,
+            a : 0.0
+        };
+        assert!(shape.segment_intersects(Vector2::new(-20.0, 0.0), Vector2::new(20.0, 0.0)));
+        assert!(!shape.segment_intersects(Vector2::new(-20.0, 6.0), Vector2::new(20.0, 6.0)));
+        assert!(!shape.segment_intersects(Vector2::new(-20.0, 0.0), Vector2::new(-6.0, 0.0))); // stops short
+        let turned = BoxShape { a : PI/4.0, ..shape };
+        assert!(turned.segment_intersects(Vector2::new(-20.0, 6.0), Vector2::new(20.0, 6.0))); // the corners stick out further when it's turned
+    }
+
+    #[test]
+    fn check_ledger_charge() {
+        let mut ledger = Ledger::new();
+        assert_eq!(ledger.credit(1, 50, TransactionReason::Grant), 50);
+        assert_eq!(ledger.charge(1, 80, TransactionReason::Placement), None); // can't afford it
+        assert_eq!(ledger.balance(1), 50);
+        assert_eq!(ledger.charge(1, 30, TransactionReason::Placement), Some(20));
+        assert_eq!(ledger.history(1).len(), 2);
+        assert_eq!(ledger.history(2).len(), 0);
+    }
+
+    #[test]
+    fn check_ledger_refunds() {
+        let mut ledger = Ledger::new();
+        ledger.paid_for(7, 1, 60);
+        ledger.paid_for(8, 1, 0); // free stuff isn't worth remembering
+        assert_eq!(ledger.paid(7), 60);
+        assert_eq!(ledger.forget(8), None);
+        assert_eq!(ledger.forget(7), Some((1, 60)));
+        assert_eq!(ledger.forget(7), None); // only refunded once
+        for _ in 0..5000 {
+            ledger.credit(1, 1, TransactionReason::Kill);
+        }
+        assert!(ledger.log().len() < 5000);
+        assert_eq!(ledger.earned(1), 5000); // the totals don't forget
+    }
+
+    #[test]
+    fn check_path_around_block() {
+        let wall = BoxShape::from_corners(2000.0, 0.0, 2200.0, 4000.0); // leaves a gap at the bottom of a 5000 world
+        let grid = NavGrid::new(5000.0, &vec![wall]);
+        let from = Vector2::new(1000.0, 1000.0);
+        let to = Vector2::new(3500.0, 1000.0);
+        assert!(!grid.line_clear(from, to));
+        let path = grid.find_path(from, to);
+        assert!(path.len() > 1);
+        assert_eq!(*path.last().unwrap(), to);
+        let mut anchor = from;
+        for waypoint in path {
+            assert!(grid.line_clear(anchor, waypoint));
+            anchor = waypoint;
+        }
+    }
+
+    #[test]
+    fn check_formation_slots() {
+        let slots = Formation::Line.slots(Vector2::new(100.0, 100.0), 0.0, 3, 50.0);
+        assert_eq!(slots.len(), 3);
+        assert!((slots[1] - Vector2::new(100.0, 100.0)).is_basically(0.0)); // the middle of an odd line sits on the destination
+        assert!(((slots[0] - slots[2]).magnitude() - 100.0).abs() < 0.001);
+        let positions = vec![Vector2::new(100.0, 150.0), Vector2::new(100.0, 50.0)];
+        let slots = Formation::Line.slots(Vector2::new(100.0, 100.0), 0.0, 2, 100.0);
+        let assignments = assign_slots(&positions, &slots);
+        assert!((slots[assignments[0]] - positions[0]).is_basically(0.0)); // everyone takes the slot they're already sitting on
+        assert!((slots[assignments[1]] - positions[1]).is_basically(0.0));
+    }
+
+    #[test]
+    fn check_group_move() {
+        let (mut server, _receiver) = test_server();
+        let banner = server.banner_add("tester".to_string());
+        let enemy = server.banner_add("enemy".to_string());
+        let first = server.add(GamePieceBase::new(Box::new(BasicFighter::new()), 1000.0, 1000.0, 0.0), Some(banner));
+        let second = server.add(GamePieceBase::new(Box::new(BasicFighter::new()), 1100.0, 1000.0, 0.0), Some(banner));
+        let castle = server.add(GamePieceBase::new(Box::new(Castle::new(false)), 1200.0, 1000.0, 0.0), Some(banner));
+        let stranger = server.add(GamePieceBase::new(Box::new(BasicFighter::new()), 1300.0, 1000.0, 0.0), Some(enemy));
+        let goal = |server : &Server, id : u32| {
+            let index = server.obj_lookup(id).unwrap();
+            (server.objects[index].exposed_properties.goal_x, server.objects[index].exposed_properties.goal_y)
+        };
+        let (castle_goal, stranger_goal) = (goal(&server, castle), goal(&server, stranger));
+        server.group_move(banner, vec![first, second, castle, stranger], 2000.0, 2000.0, 0.0, Formation::Line, false);
+        assert_eq!(goal(&server, castle), castle_goal); // castles don't go anywhere
+        assert_eq!(goal(&server, stranger), stranger_goal); // not ours
+        let (a, b) = (Vector2::new(goal(&server, first).0, goal(&server, first).1), Vector2::new(goal(&server, second).0, goal(&server, second).1));
+        assert!(((a + b) * 0.5 - Vector2::new(2000.0, 2000.0)).is_basically(0.0)); // the two fighters got the whole line to themselves
+    }
+
+    #[test]
+    fn check_intercept() {
+        let to = Vector2::new(300.0, 0.0);
+        let velocity = Vector2::new(0.0, 10.0);
+        let aim = Vector2::intercept(to, velocity, 20.0).unwrap();
+        let time = aim.magnitude() / 20.0; // how long the bullet takes to get to the aim point
+        assert!(((to + velocity * time) - aim).magnitude() < 0.01); // and the target is right there when it does
+        assert!(aim.y > 0.0); // leading, not trailing
+        assert!(Vector2::intercept(to, Vector2::new(30.0, 0.0), 20.0).is_none()); // running away faster than we can shoot
+    }
+
+    #[test]
+    fn check_carrier_loadouts() {
+        assert_eq!(Carrier::parse_loadout("0T1T4H").unwrap(), vec![(0, 'T'), (1, 'T'), (4, 'H')]);
+        assert!(Carrier::parse_loadout("0T0h").is_err()); // same berth twice
+        assert!(Carrier::parse_loadout("0K").is_err()); // carriers don't carry carriers
+        assert_eq!(Carrier::parse_loadout("2a").unwrap(), vec![(2, 'a')]); // anti-rtf bullets
+        assert!(Carrier::parse_loadout("0").is_err());
+        assert_eq!(Carrier::loadout_from_variant(1 + 6 * 9), "0h1T"); // berth 0 missile, berth 1 turret
+    }
+
+    #[test]
+    fn check_carrier_berths() {
+        let (mut server, _receiver) = test_server();
+        let banner = server.banner_add("tester".to_string());
+        server.score_to(banner, 10000, TransactionReason::Grant);
+        server.place_castle(1000.0, 1000.0, false, Some(banner));
+        let carrier_id = server.place_carrier_loadout(1000.0, 1200.0, Some(banner), "0f1f").unwrap();
+        let carrier = server.obj_lookup(carrier_id).unwrap();
+        let (first, second) = (server.objects[carrier].exposed_properties.carrier_properties.carrying[0], server.objects[carrier].exposed_properties.carrier_properties.carrying[1]);
+        let position = |server : &Server, id : u32| server.objects[server.obj_lookup(id).unwrap()].exposed_properties.physics.vector_position();
+        let spot = position(&server, second);
+        assert!(server.assign_berth(first, 1)); // swaps with whatever's in berth 1
+        assert!((position(&server, first) - spot).is_basically(0.0));
+        assert_eq!(server.objects[server.obj_lookup(second).unwrap()].exposed_properties.carrier_properties.berth, 0);
+        assert!(!server.assign_berth(first, Carrier::BERTHS)); // no such berth
+        let first_index = server.obj_lookup(first).unwrap();
+        assert!(server.objects[first_index].launch());
+        let third = server.add(GamePieceBase::new(Box::new(BasicFighter::new()), 1000.0, 1200.0, 0.0), Some(banner));
+        let carrier = server.obj_lookup(carrier_id).unwrap();
+        server.into_berth(carrier, third, 5); // the carrier's next update lets the launched one out
+        let first_index = server.obj_lookup(first).unwrap();
+        assert!(!server.objects[first_index].exposed_properties.carrier_properties.is_carried);
+        assert!(!server.objects[first_index].launch()); // it's already out
+        let enemy = server.banner_add("enemy".to_string());
+        let stranger = server.add(GamePieceBase::new(Box::new(BasicFighter::new()), 1500.0, 1500.0, 0.0), Some(enemy));
+        assert!(!server.recall(stranger, carrier_id)); // not their carrier
+        assert!(!server.recall(third, carrier_id)); // already docked
+        assert!(server.recall(first, carrier_id));
+        let first_index = server.obj_lookup(first).unwrap();
+        assert_eq!(server.objects[first_index].exposed_properties.carrier_properties.recall, Some(carrier_id));
+        let carrier = server.obj_lookup(carrier_id).unwrap();
+        server.into_berth(carrier, first, 2); // what the collision code does when it gets there
+        let first_index = server.obj_lookup(first).unwrap();
+        assert!(server.objects[first_index].exposed_properties.carrier_properties.is_carried);
+        assert_eq!(server.objects[first_index].exposed_properties.carrier_properties.recall, None);
+    }
+
+    #[test]
+    fn check_carrier_refunds() {
+        let (mut server, _receiver) = test_server();
+        let banner = server.banner_add("tester".to_string());
+        server.score_to(banner, 10000, TransactionReason::Grant);
+        server.place_castle(1000.0, 1000.0, false, Some(banner));
+        let carrier_id = server.place_carrier_loadout(1000.0, 1200.0, Some(banner), "0f").unwrap();
+        let carrier = server.obj_lookup(carrier_id).unwrap();
+        let bought = server.objects[carrier].exposed_properties.carrier_properties.carrying[0];
+        let free = server.add(GamePieceBase::new(Box::new(BasicFighter::new()), 1000.0, 1200.0, 0.0), Some(banner)); // like a starting fighter: nobody paid for it
+        server.into_berth(carrier, free, 1);
+        let balance = server.ledger.balance(banner);
+        server.unload(carrier_id, free);
+        assert_eq!(server.ledger.balance(banner), balance); // free stuff sells for nothing
+        assert!(server.obj_lookup(free).is_none());
+        server.unload(carrier_id, bought);
+        assert_eq!(server.ledger.balance(banner), balance + make_piece('f').unwrap().cost());
+        server.unload(carrier_id, bought); // it's gone, so nothing happens
+        assert_eq!(server.ledger.balance(banner), balance + make_piece('f').unwrap().cost());
+        let flag = server.add(GamePieceBase::new(Box::new(Flag::new()), 1000.0, 1200.0, 0.0), None);
+        let carrier = server.obj_lookup(carrier_id).unwrap();
+        server.into_berth(carrier, flag, 2);
+        server.set_mode(GameMode::Strategy);
+        server.swap_loadout(banner, carrier_id, "0f1f").unwrap();
+        let flag = server.obj_lookup(flag).expect("swapping loadouts sold the flag");
+        assert!(!server.objects[flag].exposed_properties.carrier_properties.is_carried); // dropped instead
+        let carrier = server.obj_lookup(carrier_id).unwrap();
+        assert_eq!(server.objects[carrier].exposed_properties.carrier_properties.carrying.len(), 2);
+        while server.queue_cargo(carrier_id, 'f') {}
+        let carrier = server.obj_lookup(carrier_id).unwrap();
+        let props = &server.objects[carrier].exposed_properties.carrier_properties;
+        assert_eq!(props.purchase_queue.len(), props.space_remaining as usize); // one order per empty berth, and no more
+    }
+
+    #[test]
+    fn check_nexus_escalation() {
+        let mut table = SpawnTable::classic();
+        assert_eq!(table.wave(100000), 1); // classic nexuses never escalate
+        table.escalate_every = 100;
+        table.wave_growth = 2;
+        table.max_wave = 4;
+        assert_eq!(table.wave(99), 1);
+        assert_eq!(table.wave(100), 3);
+        assert_eq!(table.wave(500), 4);
+        table.interval_scale = 0.5;
+        assert!(table.interval(200) <= table.max_interval / 4);
+    }
+
+    #[test]
+    fn check_targeting_filters() {
+        let mut fighter = GamePieceBase::new(Box::new(BasicFighter::new()), 0.0, 0.0, 0.0);
+        fighter.set_banner(2);
+        assert!(TargetingFilter::Fighters.matches(&fighter));
+        assert!(!TargetingFilter::Types (vec!['c', 'F']).matches(&fighter));
+        assert!(TargetingFilter::All (vec![TargetingFilter::Fighters, TargetingFilter::Banner (2)]).matches(&fighter));
+        assert!(!TargetingFilter::All (vec![TargetingFilter::Fighters, TargetingFilter::Banner (3)]).matches(&fighter));
+        assert!(!TargetingFilter::Threat (fighter.threat() + 1.0).matches(&fighter));
+    }
+
+    #[test]
+    fn check_default_stance() {
+        let mut fighter = GamePieceBase::new(Box::new(BasicFighter::new()), 0.0, 0.0, 0.0);
+        assert_eq!(fighter.exposed_properties.stance, Some(Stance::FireAtWill));
+        fighter.order_move(1000.0, 0.0, 0.0);
+        fighter.exposed_properties.targeting.lock_on(Vector2::new(0.0, 200.0), Vector2::new(0.0, 0.0)); // an enemy off to the side, well in range
+        assert!((fighter.exposed_properties.nav_target() - Vector2::new(1000.0, 0.0)).is_basically(0.0)); // keeps going where it was told
+        assert!(fighter.set_stance(Stance::Aggressive));
+        assert!((fighter.exposed_properties.nav_target() - Vector2::new(0.0, 200.0)).is_basically(0.0)); // unless it's told to go hunting
+        assert!(fighter.set_stance(Stance::FireAtWill));
+        assert!(fighter.exposed_properties.targeting.lead(20.0).is_none()); // and stops looking once it's back to firing forward
+    }
+
+    #[test]
+    fn check_missile_speed() {
+        assert_eq!(AntiRTFBullet::average_speed(0.0), 20.0); // straight out of the gun
+        let near = AntiRTFBullet::average_speed(300.0);
+        let far = AntiRTFBullet::average_speed(3000.0);
+        assert!(near > 20.0 && far > near); // the further they go, the faster they get
+    }
+
+    #[test]
+    fn check_order_queue() {
+        let (mut server, _receiver) = test_server();
+        let banner = server.banner_add("tester".to_string());
+        let id = server.add(GamePieceBase::new(Box::new(BasicFighter::new()), 1000.0, 1000.0, 0.0), Some(banner));
+        let index = server.obj_lookup(id).unwrap();
+        server.objects[index].queue_order(MoveOrder { x : 1200.0, y : 1000.0, a : 0.0, attack : false });
+        server.objects[index].queue_order(MoveOrder { x : 1200.0, y : 1200.0, a : 0.0, attack : false });
+        server.objects[index].exposed_properties.orders.patrol = true;
+        let mut legs = vec![];
+        for _ in 0..5 {
+            let index = server.obj_lookup(id).unwrap();
+            let (x, y) = (server.objects[index].exposed_properties.goal_x, server.objects[index].exposed_properties.goal_y);
+            legs.push((x, y));
+            server.objects[index].exposed_properties.physics.set_cx(x); // pretend it got there
+            server.objects[index].exposed_properties.physics.set_cy(y);
+            server.objects[index].exposed_properties.physics.velocity = Vector2::empty();
+            let mut piece = server.objects.remove(index);
+            piece.update(&mut server);
+            server.objects.insert(index, piece);
+        }
+        assert_eq!(legs, vec![(1200.0, 1000.0), (1200.0, 1200.0), (1200.0, 1000.0), (1200.0, 1200.0), (1200.0, 1000.0)]); // in the order they were given, and back around again
+        let index = server.obj_lookup(id).unwrap();
+        for _ in 0..100 {
+            server.objects[index].queue_order(MoveOrder { x : 1500.0, y : 1500.0, a : 0.0, attack : false });
+        }
+        assert_eq!(server.objects[index].exposed_properties.orders.queue.len(), crate::gamepiece::MAX_ORDERS); // the rest got dropped
+        server.objects[index].clear_orders();
+        assert!(server.objects[index].exposed_properties.orders.current.is_none());
+        assert!(!server.objects[index].exposed_properties.orders.patrol);
+    }
+
+    #[test]
+    fn check_focus_targets() {
+        let (mut server, _receiver) = test_server();
+        let banner = server.banner_add("tester".to_string());
+        let enemy = server.banner_add("enemy".to_string());
+        let ours = server.add(GamePieceBase::new(Box::new(BasicFighter::new()), 1000.0, 1000.0, 0.0), Some(banner));
+        let theirs = server.add(GamePieceBase::new(Box::new(BasicFighter::new()), 1500.0, 1000.0, 0.0), Some(enemy));
+        server.set_focus_target(banner, 12345); // doesn't exist
+        assert_eq!(server.get_focus_target(banner), None);
+        server.set_focus_target(banner, ours); // not an enemy
+        assert_eq!(server.get_focus_target(banner), None);
+        server.set_focus_target(banner, theirs);
+        assert_eq!(server.get_focus_target(banner), Some(theirs));
+        server.delete_obj(theirs);
+        assert_eq!(server.get_focus_target(banner), None); // gone with it
+    }
+
+    #[test]
+    fn check_capture_scoring() {
+        let mut capture = CaptureMode::new(2, 0, 3);
+        assert!(!capture.hold(1));
+        assert!(!capture.hold(1));
+        assert!(capture.hold(1));
+        assert_eq!(capture.score(1), 1);
+        assert_eq!(capture.tick(), None);
+        for _ in 0..3 {
+            capture.hold(1);
+        }
+        assert_eq!(capture.tick(), Some(Outcome::Winner (1)));
+        let mut timed = CaptureMode::new(0, 2, 1);
+        timed.hold(1);
+        timed.hold(2);
+        assert_eq!(timed.tick(), None);
+        assert_eq!(timed.tick(), Some(Outcome::Tie));
+    }
+
+    #[test]
+    fn check_ctf_captures() {
+        let mut ctf = CtfMode::new(2);
+        ctf.add_flag(10, 1, Vector2::new(0.0, 0.0));
+        ctf.add_flag(11, 2, Vector2::new(1000.0, 0.0));
+        assert_eq!(ctf.side_of_flag(11), Some(2));
+        assert_eq!(ctf.flag_of(1).unwrap().id, 10);
+        assert_eq!(ctf.capture(1), None);
+        assert_eq!(ctf.capture(2), None);
+        assert_eq!(ctf.capture(1), Some(Outcome::Winner (1)));
+        assert_eq!(ctf.captures(2), 1);
+    }
+
+    #[test]
+    fn check_most_points() {
+        let mut clock = MatchClock::new(2);
+        assert!(!clock.tick());
+        assert!(clock.tick());
+        assert_eq!(clock.remaining(), 0);
+        let mut standings = vec![
+            Standing { side : 1, points : 100, earned : 50 },
+            Standing { side : 2, points : 100, earned : 80 },
+            Standing { side : 3, points : 20, earned : 500 }
+        ];
+        assert_eq!(most_points(&standings), Outcome::Winner (2)); // tied on points, so earnings break it
+        standings[0].earned = 80;
+        assert_eq!(most_points(&standings), Outcome::Tie);
+        let mut ledger = Ledger::new();
+        ledger.credit(1, 100, TransactionReason::Grant);
+        ledger.credit(1, 30, TransactionReason::Kill);
+        ledger.charge(1, 50, TransactionReason::Shop);
+        assert_eq!(ledger.earned(1), 30);
+    }
+
+    #[test]
+    fn check_match_stats() {
+        let mut stats = MatchStats::new();
+        stats.kill(1);
+        stats.kill(1);
+        stats.loss(2);
+        stats.damage(1, 1.5);
+        stats.built(2);
+        assert_eq!(stats.get(1).kills, 2);
+        assert_eq!(stats.get(1).damage, 1.5);
+        assert_eq!(stats.get(2).losses, 1);
+        assert_eq!(stats.get(2).built, 1);
+        assert_eq!(stats.get(3).kills, 0); // never did anything
+        stats.clear();
+        assert_eq!(stats.get(1).kills, 0);
+    }
+
+    #[test]
+    fn check_sudden_death() {
+        let mut shrink = Overtime::new(OvertimeRules { kind : SuddenDeath::Shrink, shrink_ticks : 10, damage : 0.1, interval : 0 }, 1000.0);
+        assert!(shrink.radius() > 707.0); // covers the corners to start with
+        for _ in 0..5 {
+            assert_eq!(shrink.tick(), 0);
+        }
+        assert!(shrink.radius() < 360.0 && shrink.radius() > 350.0);
+        for _ in 0..10 {
+            shrink.tick();
+        }
+        assert_eq!(shrink.radius(), 0.0);
+        let mut pressure = Overtime::new(OvertimeRules { kind : SuddenDeath::Pressure, shrink_ticks : 0, damage : 0.0, interval : 4 }, 1000.0);
+        let waves : Vec<u32> = (0..15).map(|_| pressure.tick()).filter(|wave| *wave > 0).collect();
+        assert_eq!(waves, vec![1, 2, 3, 4]); // the waits between waves go 4, 3, 2, 2, and keep shrinking from there, down to a floor
+        let mut pressure = Overtime::new(OvertimeRules { kind : SuddenDeath::Pressure, shrink_ticks : 0, damage : 0.0, interval : 400 }, 1000.0);
+        let waves : Vec<u32> = (0..100000).map(|_| pressure.tick()).filter(|wave| *wave > 0).collect();
+        assert!(waves.len() <= 1000); // never faster than one wave every 100 ticks
+        assert!(waves.iter().all(|wave| *wave <= 20)); // and never bigger than the biggest wave
+    }
+
+    #[test]
+    fn check_rooms() {
+        assert_eq!(RoomManager::name_for("maps/big.json"), "big");
+        assert_eq!(RoomManager::name_for("default.json"), "default");
+        let mut rooms = RoomManager::new();
+        assert!(rooms.create("".to_string(), "default.json".to_string()).is_err());
+        assert!(rooms.create("ghost".to_string(), "no/such/config.json".to_string()).is_err());
+        let broken = std::env::temp_dir().join("mmosg-broken-config.json");
+        std::fs::write(&broken, "{ \"world_size\" : ").unwrap();
+        assert!(rooms.create("broken".to_string(), broken.to_string_lossy().to_string()).is_err()); // doesn't parse
+        std::fs::write(&broken, "{ \"world_size\" : 5000, \"map\" : [], \"match_secs\" : -1 }").unwrap();
+        assert!(rooms.create("broken".to_string(), broken.to_string_lossy().to_string()).is_err()); // parses, but makes no sense
+        std::fs::remove_file(&broken).unwrap();
+        assert!(rooms.default_room().is_none());
+        assert!(rooms.assign(None).is_none());
+        assert_eq!(rooms.list().len(), 0);
+    }
+
+    #[test]
+    fn check_matchmaking() {
+        let mut queue = MatchQueue::new("room.json".to_string(), 2, 3, 5, None, 0);
+        let a = queue.join(vec![ClientMode::RealTimeFighter], 0.5);
+        assert_eq!(queue.tick(), vec![]); // nobody to play with yet
+        let b = queue.join(vec![ClientMode::Normal], 0.5);
+        let c = queue.join(vec![ClientMode::Normal, ClientMode::RealTimeFighter], 0.5);
+        assert_eq!(queue.tick(), vec![Batch { mode : ClientMode::Normal, tickets : vec![b, c] }]);
+        assert_eq!(queue.count(), 1);
+        queue.leave(a);
+        assert_eq!(queue.count(), 0);
+        let mut rated = MatchQueue::new("room.json".to_string(), 2, 4, 5, Some(0.1), 0);
+        let good = rated.join(vec![ClientMode::Normal], 0.9);
+        let bad = rated.join(vec![ClientMode::Normal], 0.2);
+        assert_eq!(rated.tick(), vec![]); // too far apart
+        let decent = rated.join(vec![ClientMode::Normal], 0.85);
+        assert_eq!(rated.tick(), vec![Batch { mode : ClientMode::Normal, tickets : vec![good, decent] }]);
+        for _ in 0..4 {
+            assert_eq!(rated.tick(), vec![]);
+        }
+        let worse = rated.join(vec![ClientMode::Normal], 0.05);
+        assert_eq!(rated.tick(), vec![Batch { mode : ClientMode::Normal, tickets : vec![bad, worse] }]); // bad's waited long enough to take a looser match
+    }
+
+    #[test]
+    fn check_password_hashing() {
+        let salt = accounts::new_salt();
+        assert_eq!(salt.len(), 32);
+        assert_ne!(salt, accounts::new_salt());
+        let hash = accounts::hash_password("hunter2", &salt);
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, accounts::hash_password("hunter2", &salt));
+        assert_ne!(hash, accounts::hash_password("hunter3", &salt));
+        assert_ne!(hash, accounts::hash_password("hunter2", &accounts::new_salt())); // same password, different salt
+    }
+
+    #[test]
+    fn check_accounts() {
+        let path = std::env::temp_dir().join(format!("mmosg-accounts-{}.db", accounts::new_salt()));
+        let sql = path.to_string_lossy().to_string();
+        sqlite::open(&sql).unwrap().execute(accounts::SCHEMA).unwrap();
+        assert!(accounts::may_use(&sql, None, "alice")); // nobody's registered it yet
+        accounts::register(&sql, "alice", "hunter2").unwrap();
+        assert!(accounts::register(&sql, "alice", "hunter3").is_err()); // the index turns away the second one
+        assert!(accounts::register(&sql, "bob", "").is_err());
+        assert_eq!(accounts::login(&sql, "alice", "hunter2"), LoginResult::Ok);
+        assert_eq!(accounts::login(&sql, "alice", "hunter3"), LoginResult::BadPassword);
+        assert_eq!(accounts::login(&sql, "bob", "hunter2"), LoginResult::NoAccount);
+        assert!(!accounts::may_use(&sql, None, "alice"));
+        assert!(!accounts::may_use(&sql, Some("bob"), "alice"));
+        assert!(accounts::may_use(&sql, Some("alice"), "alice"));
+        assert!(accounts::may_use(&sql, None, "bob"));
+        std::fs::remove_file(&path).unwrap();
+        assert!(!accounts::may_use(&path.join("nope").to_string_lossy(), None, "bob")); // no database, no guests
+    }
+
+    #[test]
+    fn check_duplicate_banners() {
+        let path = std::env::temp_dir().join(format!("mmosg-duplicates-{}.db", accounts::new_salt()));
+        let sql = path.to_string_lossy().to_string();
+        let old = sqlite::open(&sql).unwrap();
+        old.execute("CREATE TABLE logins (banner TEXT, password TEXT, highscore INTEGER, wins INTEGER, losses INTEGER);INSERT INTO logins VALUES ('alice', 'first$x', 0, 0, 0);INSERT INTO logins VALUES ('alice', 'second$x', 0, 0, 0);").unwrap(); // from before banners were unique
+        old.execute(accounts::SCHEMA).unwrap();
+        let mut statement = old.prepare("SELECT password FROM logins WHERE banner = 'alice'").unwrap();
+        assert_eq!(statement.next().unwrap(), sqlite::State::Row);
+        assert_eq!(statement.read::<String, _>(0).unwrap(), "first$x");
+        assert_eq!(statement.next().unwrap(), sqlite::State::Done);
+        drop(statement);
+        drop(old);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn check_ratings() {
+        assert_eq!(rating::expected(1500.0, 1500.0), 0.5);
+        let duel = rating::update(&vec![1500.0, 1500.0], &vec![0, 1]);
+        assert_eq!(duel, vec![1516.0, 1484.0]);
+        let upset = rating::update(&vec![1200.0, 1800.0], &vec![0, 1]);
+        assert!(upset[0] - 1200.0 > 30.0); // beating somebody way better is worth nearly all of K
+        let draw = rating::update(&vec![1500.0, 1500.0, 1500.0], &vec![0, 0, 0]);
+        assert_eq!(draw, vec![1500.0, 1500.0, 1500.0]);
+        // 4 won; 1 and 2 went down first and second, 3 is still alive
+        assert_eq!(rating::placements(&vec![1, 2, 3, 4], &vec![4], &vec![1, 2]), vec![3, 2, 1, 0]);
+        let ffa = rating::update(&vec![1500.0; 4], &vec![3, 2, 1, 0]);
+        assert!(ffa[3] > ffa[2] && ffa[2] > ffa[1] && ffa[1] > ffa[0]);
+        assert!((ffa.iter().sum::<f32>() - 6000.0).abs() < 0.01); // elo doesn't make points out of nowhere
+    }
+
+    #[test]
+    fn check_match_history() {
+        let mut history = MatchHistory::new();
+        assert_eq!(history.elapsed(), 0.0); // hasn't started
+        history.begin();
+        history.join(3, ClientMode::RealTimeFighter);
+        history.join(1, ClientMode::Normal);
+        assert_eq!(history.participants(), vec![1, 3]);
+        assert_eq!(history.mode_of(3), Some(ClientMode::RealTimeFighter));
+        history.kill(KillEvent { time : history.elapsed(), killer : 1, victim : 3, killer_type : 'f', victim_type : 'R', x : 10.0, y : 20.0, castle : true, victim_id : 42 });
+        assert!(history.has_death(42));
+        assert!(!history.has_death(43));
+        history.kill(KillEvent { time : history.elapsed(), killer : 3, victim : 2, killer_type : 'f', victim_type : 'c', x : 0.0, y : 0.0, castle : true, victim_id : 44 });
+        history.kill(KillEvent { time : history.elapsed(), killer : 2, victim : 1, killer_type : 's', victim_type : 'f', x : 0.0, y : 0.0, castle : false, victim_id : 45 });
+        let session = history.take_session(3); // io mode: their life goes out on its own
+        assert_eq!(session.participants(), vec![3]);
+        assert_eq!(session.mode_of(3), Some(ClientMode::RealTimeFighter));
+        assert_eq!(session.events().len(), 2);
+        assert_eq!(history.participants(), vec![1]);
+        assert_eq!(history.events().len(), 1);
+        assert!(history.has_death(45));
+        history.clear();
+        assert_eq!(history.participants().len(), 0);
+        assert_eq!(history.events().len(), 0);
+    }
+
+    #[test]
     fn leaderboard_read() {
-        leaderboard::read_leaderboard("fancy_world_io.leaderboard");
-    }*/
+        assert_eq!(leaderboard::read_leaderboard("test.leaderboard"), vec![]); // it's empty
+        assert_eq!(leaderboard::read_leaderboard("no such.leaderboard"), vec![]);
+        let path = std::env::temp_dir().join("mmosg_test.leaderboard");
+        let path = path.to_str().unwrap();
+        let entries = vec![Entry { banner : "Space Man".to_string(), score : 1200 }, Entry { banner : "frog".to_string(), score : -5 }];
+        leaderboard::write_leaderboard(path, &entries).unwrap();
+        assert_eq!(leaderboard::read_leaderboard(path), entries);
+        assert_eq!(leaderboard::live(vec![(1, 50), (2, 300), (3, 120)], 2), vec![(2, 300), (3, 120)]);
+    }
 }
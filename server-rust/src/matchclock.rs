@@ -0,0 +1,56 @@
+// Timed matches: the clock, and picking a winner by points when it runs out
+use crate::stats::Outcome;
+
+
+pub struct MatchClock {
+    length  : u32, // in ticks
+    elapsed : u32
+}
+
+
+pub struct Standing {
+    pub side   : usize,
+    pub points : i32, // what they're holding at the end
+    pub earned : i32 // everything they made from fighting and objectives over the match; breaks ties on points
+}
+
+
+impl MatchClock {
+    pub fn new(length : u32) -> Self {
+        Self {
+            length,
+            elapsed : 0
+        }
+    }
+
+    pub fn tick(&mut self) -> bool { // returns true when time's up
+        self.elapsed += 1;
+        self.elapsed >= self.length
+    }
+
+    pub fn remaining(&self) -> u32 {
+        self.length.saturating_sub(self.elapsed)
+    }
+}
+
+
+pub fn most_points(standings : &Vec<Standing>) -> Outcome { // most points wins; if that's tied, most earned; if that's tied too, it's a tie
+    let mut best : Option<&Standing> = None;
+    let mut tied = false;
+    for standing in standings {
+        match best {
+            Some(current) if (standing.points, standing.earned) == (current.points, current.earned) => {
+                tied = true;
+            },
+            Some(current) if (standing.points, standing.earned) < (current.points, current.earned) => {},
+            _ => {
+                best = Some(standing);
+                tied = false;
+            }
+        }
+    }
+    match best {
+        Some(best) if !tied => Outcome::Winner (best.side),
+        _ => Outcome::Tie
+    }
+}
@@ -0,0 +1,140 @@
+// Matchmaking: players wait in the lobby for the modes they'd like to play, and get batched into fresh rooms once enough of them are waiting
+use crate::ClientMode;
+
+const MODES : [ClientMode; 3] = [ClientMode::Normal, ClientMode::Defense, ClientMode::RealTimeFighter];
+
+
+pub fn mode_from_name(name : &str) -> Option<ClientMode> { // the same names Connect uses
+    match name {
+        "normal" => Some(ClientMode::Normal),
+        "defender" => Some(ClientMode::Defense),
+        "rtf" => Some(ClientMode::RealTimeFighter),
+        _ => None
+    }
+}
+
+
+pub fn mode_name(mode : ClientMode) -> &'static str {
+    match mode {
+        ClientMode::Defense => "defender",
+        ClientMode::RealTimeFighter => "rtf",
+        _ => "normal"
+    }
+}
+
+
+pub fn rating_of(sql : &str, banner : &str) -> f32 { // their elo; guests and anybody we can't look up start where everyone does. blocks, so use spawn_blocking.
+    match sqlite::open(sql).and_then(|connection| crate::rating::load(&connection, banner, false)) {
+        Ok((rating, _)) => rating,
+        Err(_) => crate::rating::START
+    }
+}
+
+
+struct Ticket {
+    id     : u64,
+    modes  : Vec<ClientMode>, // anything they're willing to play
+    rating : f32,
+    waited : u32 // seconds
+}
+
+
+#[derive(Debug, PartialEq)]
+pub struct Batch {
+    pub mode    : ClientMode, // everybody in the room plays this
+    pub tickets : Vec<u64>
+}
+
+
+pub struct MatchQueue {
+    pub room_config : String, // the config every matchmade room is built from
+    pub start_ticks : u32, // autonomous countdown for the new rooms, once everyone's in
+    min_players     : usize,
+    max_players     : usize,
+    wait_secs       : u32, // after this long, the oldest ticket will take any game with at least two players, and a looser rating match
+    rating_spread   : Option<f32>, // if set, only players within this much rating of each other get batched together
+    waiting         : Vec<Ticket>, // oldest first
+    top_id          : u64
+}
+
+
+impl MatchQueue {
+    pub fn new(room_config : String, min_players : usize, max_players : usize, wait_secs : u32, rating_spread : Option<f32>, start_ticks : u32) -> Self {
+        Self {
+            room_config,
+            start_ticks,
+            min_players : std::cmp::max(min_players, 2),
+            max_players : std::cmp::max(max_players, std::cmp::max(min_players, 2)),
+            wait_secs,
+            rating_spread,
+            waiting : vec![],
+            top_id : 1
+        }
+    }
+
+    pub fn join(&mut self, modes : Vec<ClientMode>, rating : f32) -> u64 {
+        let id = self.top_id;
+        self.top_id += 1;
+        self.waiting.push(Ticket {
+            id,
+            modes,
+            rating,
+            waited : 0
+        });
+        id
+    }
+
+    pub fn leave(&mut self, id : u64) {
+        self.waiting.retain(|ticket| ticket.id != id);
+    }
+
+    pub fn count(&self) -> usize {
+        self.waiting.len()
+    }
+
+    fn group_for(&self, mode : ClientMode) -> Option<Vec<u64>> { // the best batch for one mode right now, if there is one
+        let candidates : Vec<&Ticket> = self.waiting.iter().filter(|ticket| ticket.modes.contains(&mode)).collect();
+        let anchor = match candidates.first() { Some(ticket) => *ticket, None => return None }; // the oldest ticket always gets served first
+        let patient = anchor.waited >= self.wait_secs;
+        let mut group : Vec<&Ticket> = match self.rating_spread {
+            Some(spread) => {
+                let allowed = spread * (1.0 + anchor.waited as f32 / std::cmp::max(self.wait_secs, 1) as f32); // loosen up the longer they wait
+                let mut close : Vec<&Ticket> = candidates.iter().copied().filter(|ticket| (ticket.rating - anchor.rating).abs() <= allowed).collect();
+                close.sort_by(|a, b| (a.rating - anchor.rating).abs().partial_cmp(&(b.rating - anchor.rating).abs()).unwrap_or(std::cmp::Ordering::Equal)); // the anchor is 0 away, so it stays in front
+                close
+            },
+            None => candidates
+        };
+        group.truncate(self.max_players);
+        if group.len() >= self.min_players || (patient && group.len() >= 2) {
+            Some(group.iter().map(|ticket| ticket.id).collect())
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn tick(&mut self) -> Vec<Batch> { // once a second
+        for ticket in &mut self.waiting {
+            ticket.waited += 1;
+        }
+        let mut ret = vec![];
+        loop {
+            let mut found = None;
+            for mode in MODES {
+                if let Some(tickets) = self.group_for(mode) {
+                    found = Some(Batch { mode, tickets });
+                    break;
+                }
+            }
+            match found {
+                Some(batch) => {
+                    self.waiting.retain(|ticket| !batch.tickets.contains(&ticket.id));
+                    ret.push(batch);
+                },
+                None => break
+            }
+        }
+        ret
+    }
+}
\ No newline at end of file
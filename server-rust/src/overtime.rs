@@ -0,0 +1,87 @@
+// Sudden death for timed matches that end in a tie: either the safe zone closes in, or the NPCs keep coming faster and faster
+
+const MAX_WAVE     : u32 = 20; // npcs in the biggest wave
+pub const MAX_NPCS : usize = 100; // waves stop dropping npcs while this many are still alive
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SuddenDeath {
+    Shrink, // everything outside a circle in the middle of the world takes damage, and the circle gets smaller
+    Pressure // waves of npcs, each bigger and sooner than the last
+}
+
+
+impl SuddenDeath {
+    pub fn from_name(name : &str) -> Option<Self> {
+        match name {
+            "shrink" => Some(SuddenDeath::Shrink),
+            "npcs" => Some(SuddenDeath::Pressure),
+            _ => None
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            SuddenDeath::Shrink => b's',
+            SuddenDeath::Pressure => b'n'
+        }
+    }
+}
+
+
+#[derive(Clone)]
+pub struct OvertimeRules { // what the config asked for; Overtime is one run of it
+    pub kind         : SuddenDeath,
+    pub shrink_ticks : u32, // how long the zone takes to close all the way
+    pub damage       : f32, // per tick, to anything outside the zone
+    pub interval     : u32 // ticks before the first npc wave; every wave after comes a little sooner
+}
+
+
+pub struct Overtime {
+    rules    : OvertimeRules,
+    start    : f32, // radius the zone starts at: big enough to cover the whole world
+    elapsed  : u32,
+    timer    : u32,
+    wave     : u32
+}
+
+
+impl Overtime {
+    pub fn new(rules : OvertimeRules, gamesize : f32) -> Self {
+        Self {
+            timer : rules.interval,
+            rules,
+            start : gamesize * std::f32::consts::FRAC_1_SQRT_2 + 1.0, // center to corner
+            elapsed : 0,
+            wave : 0
+        }
+    }
+
+    pub fn kind(&self) -> SuddenDeath {
+        self.rules.kind
+    }
+
+    pub fn damage(&self) -> f32 {
+        self.rules.damage
+    }
+
+    pub fn radius(&self) -> f32 { // the safe zone, for shrink overtime. it doesn't go below zero, so eventually everything's outside.
+        let progress = self.elapsed as f32 / std::cmp::max(self.rules.shrink_ticks, 1) as f32;
+        (self.start * (1.0 - progress)).max(0.0)
+    }
+
+    pub fn tick(&mut self) -> u32 { // returns how many npcs to drop this tick, for pressure overtime
+        self.elapsed += 1;
+        if self.rules.kind != SuddenDeath::Pressure {
+            return 0;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return 0;
+        }
+        self.wave += 1;
+        self.timer = std::cmp::max(self.rules.interval * 3 / (self.wave + 3), std::cmp::max(self.rules.interval / 4, 1)); // gets ugly fast, but bottoms out at a quarter of the first wait
+        std::cmp::min(self.wave, MAX_WAVE)
+    }
+}
@@ -0,0 +1,237 @@
+// Navigation grid built from the map's fixed blocks, and A* pathfinding across it.
+use crate::vector::Vector2;
+use crate::physics::BoxShape;
+use std::collections::BinaryHeap;
+use std::cmp::Ordering;
+
+const CLEARANCE : f32 = 50.0; // how far unit centers stay away from block edges; about half the length of a fighter
+const MAX_CELLS : f32 = 200.0; // the grid never gets bigger than this many cells to a side, no matter how big the world is
+
+
+pub struct NavGrid {
+    cell_size : f32,
+    width     : usize, // the world is square, so this is both the width and the height in cells
+    blocked   : Vec<bool>,
+    is_empty  : bool // no blocks at all, so every path is a straight line
+}
+
+
+#[derive(Copy, Clone, PartialEq)]
+struct OpenNode {
+    cost  : f32, // cost so far plus the heuristic
+    index : usize
+}
+
+impl Eq for OpenNode {}
+
+impl Ord for OpenNode {
+    fn cmp(&self, other : &Self) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal) // reversed: BinaryHeap is a max-heap, and we want the cheapest node out first
+    }
+}
+
+impl PartialOrd for OpenNode {
+    fn partial_cmp(&self, other : &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+impl NavGrid {
+    pub fn empty() -> Self {
+        Self {
+            cell_size : 100.0,
+            width     : 0,
+            blocked   : vec![],
+            is_empty  : true
+        }
+    }
+
+    pub fn new(gamesize : f32, blocks : &Vec<BoxShape>) -> Self {
+        let cell_size = if gamesize / MAX_CELLS > 100.0 { gamesize / MAX_CELLS } else { 100.0 };
+        let width = (gamesize / cell_size).ceil() as usize;
+        let mut grid = Self {
+            cell_size,
+            width,
+            blocked : vec![false; width * width],
+            is_empty : blocks.len() == 0
+        };
+        for block in blocks {
+            let inflated = block.bigger(CLEARANCE * 2.0); // bigger() grows the whole width, so double it to get clearance on each side
+            let bounds = inflated.ong_fr();
+            let (x1, y1) = grid.cell_of(Vector2::new(bounds.x - bounds.w/2.0, bounds.y - bounds.h/2.0));
+            let (x2, y2) = grid.cell_of(Vector2::new(bounds.x + bounds.w/2.0, bounds.y + bounds.h/2.0));
+            for x in x1..(x2 + 1) {
+                for y in y1..(y2 + 1) {
+                    let cell = BoxShape::from_corners(x as f32 * cell_size, y as f32 * cell_size, (x as f32 + 1.0) * cell_size, (y as f32 + 1.0) * cell_size);
+                    if cell.intersects(inflated).0 {
+                        grid.blocked[x + y * width] = true;
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    fn cell_of(&self, point : Vector2) -> (usize, usize) { // positions outside the world get clamped to the edge cells
+        let clamp = |v : f32| -> usize {
+            let cell = (v / self.cell_size).floor();
+            if cell < 0.0 {
+                0
+            }
+            else if cell as usize >= self.width {
+                self.width - 1
+            }
+            else {
+                cell as usize
+            }
+        };
+        (clamp(point.x), clamp(point.y))
+    }
+
+    fn center_of(&self, index : usize) -> Vector2 {
+        Vector2::new(((index % self.width) as f32 + 0.5) * self.cell_size, ((index / self.width) as f32 + 0.5) * self.cell_size)
+    }
+
+    fn is_blocked(&self, x : usize, y : usize) -> bool {
+        self.blocked[x + y * self.width]
+    }
+
+    pub fn is_point_blocked(&self, point : Vector2) -> bool {
+        if self.is_empty {
+            return false;
+        }
+        let (x, y) = self.cell_of(point);
+        self.is_blocked(x, y)
+    }
+
+    pub fn line_clear(&self, from : Vector2, to : Vector2) -> bool { // samples the line every half cell. the cell we start in doesn't count, so units hugging a wall can still get out.
+        if self.is_empty {
+            return true;
+        }
+        let start = self.cell_of(from);
+        let diff = to - from;
+        let steps = std::cmp::max((diff.magnitude() / (self.cell_size / 2.0)).ceil() as usize, 1);
+        for i in 0..(steps + 1) {
+            let cell = self.cell_of(from + diff * (i as f32 / steps as f32));
+            if cell != start && self.is_blocked(cell.0, cell.1) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn nearest_free(&self, (x, y) : (usize, usize)) -> Option<usize> { // search outwards in square rings for the closest unblocked cell
+        for r in 0..self.width as i64 {
+            for dx in -r..(r + 1) {
+                for dy in -r..(r + 1) {
+                    if dx.abs() != r && dy.abs() != r {
+                        continue; // only the edge of the ring; the inside has already been checked
+                    }
+                    let cx = x as i64 + dx;
+                    let cy = y as i64 + dy;
+                    if cx < 0 || cy < 0 || cx >= self.width as i64 || cy >= self.width as i64 {
+                        continue;
+                    }
+                    if !self.is_blocked(cx as usize, cy as usize) {
+                        return Some(cx as usize + cy as usize * self.width);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn astar(&self, start : usize, goal : usize) -> Option<Vec<usize>> {
+        let mut open = BinaryHeap::new();
+        let mut cost = vec![f32::MAX; self.blocked.len()];
+        let mut came_from = vec![usize::MAX; self.blocked.len()];
+        let mut closed = vec![false; self.blocked.len()];
+        let goal_center = self.center_of(goal);
+        cost[start] = 0.0;
+        open.push(OpenNode { cost : (self.center_of(start) - goal_center).magnitude(), index : start });
+        while let Some(OpenNode { index, .. }) = open.pop() {
+            if index == goal {
+                let mut path = vec![goal];
+                let mut current = goal;
+                while came_from[current] != usize::MAX {
+                    current = came_from[current];
+                    path.push(current);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if closed[index] {
+                continue;
+            }
+            closed[index] = true;
+            let x = (index % self.width) as i64;
+            let y = (index / self.width) as i64;
+            for dx in -1..2_i64 {
+                for dy in -1..2_i64 {
+                    if dx == 0 && dy == 0 {
+                        continue;
+                    }
+                    let nx = x + dx;
+                    let ny = y + dy;
+                    if nx < 0 || ny < 0 || nx >= self.width as i64 || ny >= self.width as i64 {
+                        continue;
+                    }
+                    if self.is_blocked(nx as usize, ny as usize) {
+                        continue;
+                    }
+                    if dx != 0 && dy != 0 && (self.is_blocked(nx as usize, y as usize) || self.is_blocked(x as usize, ny as usize)) {
+                        continue; // no cutting corners
+                    }
+                    let neighbor = nx as usize + ny as usize * self.width;
+                    if closed[neighbor] {
+                        continue;
+                    }
+                    let step = if dx != 0 && dy != 0 { std::f32::consts::SQRT_2 } else { 1.0 } * self.cell_size;
+                    let new_cost = cost[index] + step;
+                    if new_cost < cost[neighbor] {
+                        cost[neighbor] = new_cost;
+                        came_from[neighbor] = index;
+                        open.push(OpenNode { cost : new_cost + (self.center_of(neighbor) - goal_center).magnitude(), index : neighbor });
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    pub fn find_path(&self, from : Vector2, to : Vector2) -> Vec<Vector2> { // returns waypoints to follow in order, ending at the goal. if there's no way around, it just goes straight there.
+        if self.line_clear(from, to) {
+            return vec![to];
+        }
+        let start = match self.nearest_free(self.cell_of(from)) { Some(cell) => cell, None => return vec![to] };
+        let goal_cell = self.cell_of(to);
+        let goal = match self.nearest_free(goal_cell) { Some(cell) => cell, None => return vec![to] };
+        let cells = match self.astar(start, goal) { Some(cells) => cells, None => return vec![to] };
+        let mut points : Vec<Vector2> = cells.iter().map(|cell| self.center_of(*cell)).collect();
+        if goal == goal_cell.0 + goal_cell.1 * self.width {
+            let last = points.len() - 1;
+            points[last] = to; // the goal itself is reachable, so finish on it rather than on the middle of its cell
+        }
+        // string-pull: skip every waypoint we can see past
+        let mut ret = vec![];
+        let mut anchor = from;
+        let mut i = 0;
+        while i < points.len() {
+            let mut furthest = i;
+            for j in (i + 1)..points.len() {
+                if self.line_clear(anchor, points[j]) {
+                    furthest = j;
+                }
+            }
+            ret.push(points[furthest]);
+            anchor = points[furthest];
+            i = furthest + 1;
+        }
+        ret
+    }
+}
@@ -203,6 +203,37 @@ impl BoxShape {
         }
     }
 
+    pub fn segment_intersects(&self, from : Vector2, to : Vector2) -> bool { // does the line segment from -> to pass through this box at all?
+        let center = Vector2::new(self.x, self.y);
+        let (from, to) = if self.a != 0.0 {
+            (from.rotate_about(center, -self.a), to.rotate_about(center, -self.a))
+        }
+        else {
+            (from, to)
+        };
+        // slab test: clip the segment against the x and y extents in turn, and see if anything's left
+        let diff = to - from;
+        let mut enter : f32 = 0.0;
+        let mut exit : f32 = 1.0;
+        for (start, delta, low, high) in [(from.x, diff.x, self.x - self.w/2.0, self.x + self.w/2.0), (from.y, diff.y, self.y - self.h/2.0, self.y + self.h/2.0)] {
+            if delta == 0.0 {
+                if start < low || start > high {
+                    return false; // parallel to this slab and outside it
+                }
+            }
+            else {
+                let t1 = (low - start) / delta;
+                let t2 = (high - start) / delta;
+                enter = enter.max(t1.min(t2));
+                exit = exit.min(t1.max(t2));
+                if enter > exit {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
     pub fn contains(&self, point : Vector2) -> bool {
         let cmp = if self.a != 0.0 {
             point.rotate_about(Vector2::new(self.x, self.y), -self.a)
@@ -0,0 +1,89 @@
+// Elo ratings for players and teams. A game with more than two sides gets split into every pair of sides: whoever placed better beat the other one, and equal places are a draw.
+pub const START : f32 = 1500.0;
+const K         : f32 = 32.0; // how far one game can move you, spread over all your opponents
+
+
+pub fn expected(rating : f32, opponent : f32) -> f32 { // the chance of beating them
+    1.0 / (1.0 + 10.0_f32.powf((opponent - rating) / 400.0))
+}
+
+
+pub fn update(ratings : &Vec<f32>, places : &Vec<u32>) -> Vec<f32> { // places start at 0 for the winner; returns the new ratings in the same order
+    let count = ratings.len();
+    if count < 2 {
+        return ratings.clone();
+    }
+    let k = K / (count - 1) as f32;
+    let mut ret = ratings.clone();
+    for i in 0..count {
+        for j in 0..count {
+            if i == j {
+                continue;
+            }
+            let score = if places[i] < places[j] { 1.0 } else if places[i] == places[j] { 0.5 } else { 0.0 };
+            ret[i] += k * (score - expected(ratings[i], ratings[j]));
+        }
+    }
+    ret
+}
+
+
+pub fn placements(players : &Vec<usize>, winners : &Vec<usize>, eliminations : &Vec<usize>) -> Vec<u32> { // winners come first, then anyone still alive, then the dead: the later you went down, the better you placed
+    players.iter().map(|player| {
+        if winners.contains(player) {
+            0
+        }
+        else {
+            match eliminations.iter().position(|dead| dead == player) {
+                Some(order) => 2 + (eliminations.len() - 1 - order) as u32,
+                None => 1
+            }
+        }
+    }).collect()
+}
+
+
+pub fn load(connection : &sqlite::Connection, name : &str, is_team : bool) -> sqlite::Result<(f32, u32)> { // rating and games played; new names start at START
+    let mut statement = connection.prepare("SELECT rating, games FROM ratings WHERE name = ? AND team = ?")?;
+    statement.bind((1, name))?;
+    statement.bind((2, is_team as i64))?;
+    if let sqlite::State::Row = statement.next()? {
+        return Ok((statement.read::<f64, _>(0)? as f32, statement.read::<i64, _>(1)? as u32));
+    }
+    Ok((START, 0))
+}
+
+
+pub fn store(connection : &sqlite::Connection, name : &str, is_team : bool, rating : f32) -> sqlite::Result<()> { // counts a game, too
+    let mut statement = connection.prepare("INSERT INTO ratings SELECT ?, ?, ?, 0 WHERE NOT EXISTS (SELECT 1 FROM ratings WHERE name = ? AND team = ?)")?;
+    statement.bind((1, name))?;
+    statement.bind((2, is_team as i64))?;
+    statement.bind((3, START as f64))?;
+    statement.bind((4, name))?;
+    statement.bind((5, is_team as i64))?;
+    statement.next()?;
+    let mut statement = connection.prepare("UPDATE ratings SET rating = ?, games = games + 1 WHERE name = ? AND team = ?")?;
+    statement.bind((1, rating as f64))?;
+    statement.bind((2, name))?;
+    statement.bind((3, is_team as i64))?;
+    statement.next()?;
+    Ok(())
+}
+
+
+pub fn rate(connection : &sqlite::Connection, names : Vec<String>, places : Vec<u32>, is_team : bool) -> sqlite::Result<Vec<(f32, u32)>> { // load, update and store everyone's ratings; returns the new rating and games played for each name. run it inside a transaction, so nobody else's game lands in between.
+    let mut ratings = vec![];
+    let mut games = vec![];
+    for name in &names {
+        let (value, played) = load(connection, name, is_team)?;
+        ratings.push(value);
+        games.push(played);
+    }
+    let updated = update(&ratings, &places);
+    let mut ret = vec![];
+    for i in 0..names.len() {
+        store(connection, &names[i], is_team, updated[i])?;
+        ret.push((updated[i], games[i] + 1));
+    }
+    Ok(ret)
+}
\ No newline at end of file
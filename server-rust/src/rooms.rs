@@ -0,0 +1,261 @@
+// Rooms: lots of independent matches in one process. Every room owns its own Server, config and tick loop; they only share the websocket port.
+use crate::{ServerCommand, ClientCommand, ClientMode};
+use crate::matchmaking::MatchQueue;
+use crate::database::Database;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::collections::HashMap;
+
+pub type Matched = Option<(String, ClientMode)>; // room and mode for a queued player, or None if their batch fell through
+
+const MATCH_TIMEOUT : u32 = 60; // seconds a matchmade room gets to fill up enough to start before it's closed
+
+
+pub struct RoomHandle {
+    pub name            : String,
+    pub config          : String, // path of the config file it was made from
+    pub port            : u16, // only the default room's port and headless setting matter; everybody else rides along on them
+    pub headless        : bool,
+    pub database        : Database, // every room shares the default room's, since that's where the accounts are
+    pub(crate) commandset   : tokio::sync::mpsc::Sender<ServerCommand>,
+    pub(crate) broadcast_tx : tokio::sync::broadcast::Sender<ClientCommand>,
+    population          : Arc<AtomicU32>, // written by the room's tick loop
+    matchmaking         : Option<MatchQueue>, // what the config says about matchmaking; only the default room's gets used
+    _keepalive          : tokio::sync::broadcast::Receiver<ClientCommand> // if nobody's subscribed, broadcasts fail, and the server panics on failed broadcasts
+}
+
+
+impl RoomHandle {
+    pub fn population(&self) -> u32 {
+        self.population.load(Ordering::Relaxed)
+    }
+
+    pub async fn shutdown(self) { // everyone in it gets kicked
+        let _ = self.commandset.send(ServerCommand::Shutdown).await; // if the loop's already gone, it's closed anyways
+    }
+}
+
+
+pub struct RoomManager {
+    rooms     : Vec<RoomHandle>, // the first one is the default room
+    queue     : Option<MatchQueue>, // only if the default room's config turns matchmaking on
+    tickets   : HashMap<u64, tokio::sync::oneshot::Sender<Matched>>, // where to tell each queued player about their room
+    matchmade : Vec<(String, bool, u32)>, // rooms the queue made: name, whether anyone's shown up yet, seconds open
+    top_match : u32
+}
+
+
+impl RoomManager {
+    pub fn new() -> Self {
+        Self {
+            rooms     : vec![],
+            queue     : None,
+            tickets   : HashMap::new(),
+            matchmade : vec![],
+            top_match : 1
+        }
+    }
+
+    pub fn name_for(config : &str) -> String { // "maps/big.json" makes a room called "big"
+        match std::path::Path::new(config).file_stem() {
+            Some(stem) => stem.to_string_lossy().to_string(),
+            None => config.to_string()
+        }
+    }
+
+    pub fn open(name : String, config : String, database : Option<Database>) -> Result<RoomHandle, String> { // build a room and start its tick loop. this is the slow part, so it doesn't need the manager. database is the default room's, if there is one yet.
+        if name.len() == 0 {
+            return Err("rooms need a name".to_string());
+        }
+        if !std::path::Path::new(&config).is_file() {
+            return Err(format!("no config file at '{}'", config));
+        }
+        let (broadcast_tx, keepalive) = tokio::sync::broadcast::channel(128);
+        let mut server = crate::new_server(&config, broadcast_tx.clone())?; // nothing's running yet, so a bad config just means no room
+        let database = match database {
+            Some(database) => {
+                if server.sql != database.path {
+                    println!("Room {} wanted {} for its database, but accounts and results all go in {}", name, server.sql, database.path);
+                }
+                database // results have to land next to the accounts they belong to
+            },
+            None => Database::open(&server.sql)?
+        };
+        server.database = database.clone();
+        let (commandset, commandget) = tokio::sync::mpsc::channel(32); // fancy number
+        let population = Arc::new(AtomicU32::new(0));
+        let port = server.port;
+        let headless = server.is_headless;
+        let matchmaking = server.config.as_ref().and_then(|config| config.matchmaking());
+        tokio::task::spawn(crate::run_room(server, commandget, population.clone()));
+        Ok(RoomHandle {
+            name,
+            config,
+            port,
+            headless,
+            database,
+            commandset,
+            broadcast_tx,
+            population,
+            matchmaking,
+            _keepalive : keepalive
+        })
+    }
+
+    fn insert(&mut self, mut room : RoomHandle) {
+        if self.rooms.len() == 0 {
+            self.queue = room.matchmaking.take();
+        }
+        self.rooms.push(room);
+    }
+
+    pub fn create(&mut self, name : String, config : String) -> Result<(), String> {
+        if self.get(&name).is_some() {
+            return Err(format!("there's already a room called '{}'", name));
+        }
+        let room = Self::open(name, config, self.database())?;
+        self.insert(room);
+        Ok(())
+    }
+
+    pub async fn create_shared(rooms : &tokio::sync::Mutex<Self>, name : String, config : String) -> Result<(), String> { // create, without holding the lock while the room gets built
+        let database = {
+            let manager = rooms.lock().await;
+            if manager.get(&name).is_some() {
+                return Err(format!("there's already a room called '{}'", name));
+            }
+            manager.database()
+        };
+        let room = Self::open(name, config, database)?;
+        let mut manager = rooms.lock().await;
+        if manager.get(&room.name).is_some() { // somebody beat us to it
+            let error = format!("there's already a room called '{}'", room.name);
+            drop(manager);
+            room.shutdown().await;
+            return Err(error);
+        }
+        manager.insert(room);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name : &str) -> Result<RoomHandle, String> { // the default room can go, as long as it isn't the last one; the next one in line takes over. shut it down once you've let go of the manager.
+        let index = match self.rooms.iter().position(|room| room.name == name) { Some(i) => i, None => return Err(format!("there's no room called '{}'", name)) };
+        if self.rooms.len() == 1 {
+            return Err("that's the last room".to_string());
+        }
+        Ok(self.rooms.remove(index))
+    }
+
+    pub async fn destroy_shared(rooms : &tokio::sync::Mutex<Self>, name : &str) -> Result<(), String> { // everyone in it gets kicked
+        let room = rooms.lock().await.remove(name)?;
+        room.shutdown().await;
+        Ok(())
+    }
+
+    pub fn get(&self, name : &str) -> Option<&RoomHandle> {
+        self.rooms.iter().find(|room| room.name == name)
+    }
+
+    pub fn default_room(&self) -> Option<&RoomHandle> {
+        self.rooms.first()
+    }
+
+    pub fn database(&self) -> Option<Database> { // the one every room writes to
+        self.default_room().map(|room| room.database.clone())
+    }
+
+    pub fn assign(&self, name : Option<&str>) -> Option<&RoomHandle> { // clients that don't ask for a room go to the default one
+        match name {
+            Some(name) => self.get(name),
+            None => self.default_room()
+        }
+    }
+
+    pub fn list(&self) -> Vec<(String, u32)> {
+        self.rooms.iter().map(|room| (room.name.clone(), room.population())).collect()
+    }
+
+    pub fn enqueue(&mut self, modes : Vec<ClientMode>, rating : f32) -> Option<(u64, usize, tokio::sync::oneshot::Receiver<Matched>)> { // ticket, place in line, and where the room will show up
+        let queue = match &mut self.queue { Some(queue) => queue, None => return None };
+        let id = queue.join(modes, rating);
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.tickets.insert(id, tx);
+        Some((id, queue.count(), rx))
+    }
+
+    pub fn dequeue(&mut self, id : u64) {
+        if let Some(queue) = &mut self.queue {
+            queue.leave(id);
+        }
+        self.tickets.remove(&id);
+    }
+
+    fn match_name(&mut self) -> String {
+        loop {
+            let name = format!("match-{}", self.top_match);
+            self.top_match += 1;
+            if self.get(&name).is_none() {
+                return name;
+            }
+        }
+    }
+
+    fn sweep(&mut self) -> Vec<RoomHandle> { // matchmade rooms that are done with, out of the list and ready to shut down
+        let mut done = vec![];
+        for (name, seen, age) in &mut self.matchmade {
+            *age += 1;
+            let population = match self.rooms.iter().find(|room| room.name == *name) { Some(room) => room.population(), None => 0 };
+            if population >= 2 {
+                *seen = true; // enough for the game to get going
+            }
+            if (*seen && population == 0) || (!*seen && *age > MATCH_TIMEOUT) { // everyone's gone home, or it never filled up enough to start
+                done.push(name.clone());
+            }
+        }
+        let mut ret = vec![];
+        for name in done {
+            self.matchmade.retain(|room| room.0 != name);
+            if let Ok(room) = self.remove(&name) {
+                ret.push(room);
+            }
+        }
+        ret
+    }
+
+    pub async fn matchmake(rooms : &tokio::sync::Mutex<Self>) { // once a second: batch up whoever's ready, and close matchmade rooms that are done with. the lock's only held for bookkeeping, never while rooms open or close.
+        let (batches, config, start_ticks, database) = {
+            let mut manager = rooms.lock().await;
+            let database = manager.database();
+            let queue = match &mut manager.queue { Some(queue) => queue, None => return };
+            (queue.tick(), queue.room_config.clone(), queue.start_ticks, database)
+        };
+        for batch in batches {
+            let name = rooms.lock().await.match_name();
+            let players = batch.tickets.len() as u32;
+            let matched = match Self::open(name.clone(), config.clone(), database.clone()) {
+                Ok(room) => {
+                    let _ = room.commandset.send(ServerCommand::Autonomous (2, players, start_ticks)).await; // counts down once two are in, so one no-show doesn't hold everyone up
+                    let mut manager = rooms.lock().await;
+                    manager.insert(room);
+                    manager.matchmade.push((name.clone(), false, 0));
+                    println!("Matched {} players into {}", players, name);
+                    Some((name, batch.mode))
+                },
+                Err(error) => {
+                    println!("Couldn't open a room for a match: {}", error);
+                    None
+                }
+            };
+            let mut manager = rooms.lock().await;
+            for ticket in batch.tickets {
+                if let Some(tx) = manager.tickets.remove(&ticket) {
+                    let _ = tx.send(matched.clone()); // they might've just left
+                }
+            }
+        }
+        let done = rooms.lock().await.sweep();
+        for room in done {
+            room.shutdown().await;
+        }
+    }
+}
\ No newline at end of file
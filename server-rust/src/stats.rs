@@ -0,0 +1,56 @@
+// Per-banner numbers for the post-game summary, and how a match came out
+use std::collections::HashMap;
+
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Winner (usize), // side that won
+    Tie
+}
+
+
+#[derive(Clone, Default, Debug)]
+pub struct BannerStats {
+    pub kills  : u32, // enemy pieces that were worth something when they went down
+    pub losses : u32,
+    pub built  : u32, // pieces bought, including carrier cargo
+    pub damage : f32 // dealt to other banners' pieces
+}
+
+
+pub struct MatchStats {
+    stats : HashMap<usize, BannerStats>
+}
+
+
+impl MatchStats {
+    pub fn new() -> Self {
+        Self {
+            stats : HashMap::new()
+        }
+    }
+
+    pub fn kill(&mut self, banner : usize) {
+        self.stats.entry(banner).or_default().kills += 1;
+    }
+
+    pub fn loss(&mut self, banner : usize) {
+        self.stats.entry(banner).or_default().losses += 1;
+    }
+
+    pub fn built(&mut self, banner : usize) {
+        self.stats.entry(banner).or_default().built += 1;
+    }
+
+    pub fn damage(&mut self, banner : usize, amount : f32) {
+        self.stats.entry(banner).or_default().damage += amount;
+    }
+
+    pub fn get(&self, banner : usize) -> BannerStats {
+        self.stats.get(&banner).cloned().unwrap_or_default()
+    }
+
+    pub fn clear(&mut self) {
+        self.stats.clear();
+    }
+}
@@ -129,6 +129,38 @@ impl Vector2 {
     pub fn perpendicular(&self) -> Self { // returns a right-flipped vector. useful for projection.
         Vector2::new_from_manda(self.magnitude(), self.angle() + PI/2.0)
     }
+
+    pub fn intercept(to : Vector2, velocity : Vector2, speed : f32) -> Option<Vector2> { // where to aim a projectile moving at `speed` to hit something at `to` (relative to us) moving at `velocity` (relative to us). None if it's outrunning us.
+        // solve |to + velocity * t| = speed * t for the smallest positive t
+        let a = velocity.mag2() - speed * speed;
+        let b = 2.0 * (to.x * velocity.x + to.y * velocity.y);
+        let c = to.mag2();
+        let t = if a.abs() < 0.0001 { // same speed as the projectile: the quadratic degenerates into a line
+            if b >= 0.0 {
+                return None;
+            }
+            -c / b
+        }
+        else {
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 {
+                return None;
+            }
+            let root = discriminant.sqrt();
+            let t1 = (-b - root) / (2.0 * a);
+            let t2 = (-b + root) / (2.0 * a);
+            if t1 > 0.0 && (t1 < t2 || t2 <= 0.0) {
+                t1
+            }
+            else if t2 > 0.0 {
+                t2
+            }
+            else {
+                return None;
+            }
+        };
+        Some(to + velocity * t)
+    }
 }
 
 impl std::fmt::Debug for Vector2 {